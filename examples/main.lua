@@ -1,30 +1,132 @@
 package.cpath = package.cpath .. ";./target/debug/lib?.so"
 
-local example = require("dwr")
-local client = example.create_client()
-
-local surfaces = {}
-local amount = 1
-for i = 1, amount do
-    client:create_surface(50, 50, function(surface)
-        surfaces[i] = surface
-    end)
+local dwr = require("dwr")
+-- `backend` is "auto" by default, which falls back to "software" when EGL is unavailable
+-- `gl` picks the EGL config shared by every surface, an alpha channel is requested by default
+local client = dwr.create_client({ backend = "auto", gl = { alpha = 8, samples = 4 } })
+
+local font = "/usr/share/fonts/TTF/DejaVuSans.ttf"
+local icon = "/usr/share/icons/Adwaita/symbolic/status/battery-level-80-symbolic.svg"
+
+for _, output in ipairs(client:outputs()) do
+    print("output", output.name, output.width, output.height, output.scale)
 end
+client:on_output_added(function(output)
+    print("output added", output.name)
+end)
+client:on_output_removed(function(output)
+    print("output removed", output.name)
+end)
+
+-- Pixels per frame
+local speed = 2
+
+for i = 1, 1 do
+    local top = 0
+
+    -- Every option is applied before the compositor first sees the surface
+    client:create_surface({
+        width = 50,
+        height = 50,
+        anchor = { "top", "left" },
+        layer = "top",
+        keyboard = "on_demand",
+    }, function(surface)
+        surface:on_pointer(function(event)
+            if event.type == "button" and event.pressed then
+                print("clicked", event.button_name, event.x, event.y)
+            elseif event.type == "axis" then
+                print("scrolled", event.vertical.discrete, event.source)
+            end
+        end)
+        -- Keys left out keep their current value
+        surface:set_properties({ layer = "overlay" })
+        print("layer", surface:get_properties().layer)
+        surface:on_keyboard(function(event)
+            if event.type == "key" and event.pressed then
+                print("key", event.key, event.text, event.repeat and "(repeat)" or "")
+            end
+        end)
+        surface:on_draw(function(ctx)
+            -- The alpha of the clear color leaves the surface see-through
+            ctx:clear(0.2, 0.1, 0.0, 0.8)
+
+            -- Coordinates are pixels from the top-left corner, regardless of the output scale
+            ctx:set_color(0.0, 0.0, 1.0)
+            ctx:draw_polygon("line_loop", { { 12.5, 12.5 }, { 37.5, 12.5 }, { 37.5, 37.5 } })
+
+            -- Everything between the pushes and pops is moved and cut off to the left half
+            ctx:push_clip(0, 0, 25, 50)
+            ctx:push_transform(2.5, 0)
+            ctx:set_color(1.0, 0.0, 0.5)
+            ctx:draw_rectangle(20, 20, 10, 10)
+            ctx:stroke_path({ { 17.5, 32.5 }, { 32.5, 32.5 }, { 32.5, 17.5 } }, true)
+            ctx:pop_transform()
+            ctx:pop_clip()
 
-local fps = 60
-local speed = 100
-
-local top = 0
-while client:is_alive() do
-    for i = 1, amount do
-        local surface = surfaces[i]
-        if surface then
-            -- surface:set_margin({ top = top + i * 60, bottom = 0, left = 0, right = 0 })
-            surface:set_margin({ top = top + i * 60, bottom = 0, right = 0 })
-        end
-    end
-    top = top + speed / fps
-    top = top % 800
-
-    client:render()
+            -- Rotations nest like the other transforms, this square spins around the center
+            ctx:push_rotation(top / 50, 25, 25)
+            ctx:set_color(0.0, 1.0, 0.5, 0.5)
+            ctx:draw_rectangle(20, 20, 10, 10)
+            ctx:pop_transform()
+
+            ctx:text("dwr", 2.5, 2.5, { font = font, size = 14, color = { 1.0, 1.0, 1.0 } })
+
+            -- What the previous frame submitted to the GPU, nil for surfaces drawn in software
+            local stats = surface:get_frame_stats()
+            if stats and top < speed then
+                print("frame", stats.draw_calls, "draw calls", stats.vertices, "vertices")
+            end
+
+            -- Keep animating, the next draw happens once the compositor asks for a new frame
+            top = (top + speed) % 800
+            surface:set_margin({ top = top + i * 60 })
+            surface:request_redraw()
+        end)
+    end)
 end
+
+local ticks = 0
+local clock = dwr.timer(1, function()
+    ticks = ticks + 1
+    print("tick", ticks)
+end)
+dwr.timeout(10, function()
+    clock:cancel()
+end)
+dwr.easy_async("date", function(stdout, _, reason, code)
+    print("date:", stdout, reason, code)
+end)
+dwr.spawn({ "sh", "-c", "read name; echo hello $name; echo oops >&2" }, {
+    stdin = true,
+    stdout = function(line)
+        print("stdout:", line)
+    end,
+    stderr = function(line)
+        print("stderr:", line)
+    end,
+    exit = function(reason, code)
+        print("exited:", reason, code)
+    end,
+    timeout = 5,
+}):write("dwr\n")
+dwr.idle(function()
+    print("entering the event loop")
+end)
+
+-- Inside a coroutine a surface can be awaited instead of passing a callback
+coroutine.wrap(function()
+    local bar = client:await_surface({
+        height = 24,
+        anchor = { "bottom", "left", "right" },
+        exclusive_zone = 24,
+        namespace = "dwr-bar",
+    })
+    bar:on_draw(function(ctx)
+        ctx:clear(0.1, 0.1, 0.1, 0.9)
+        -- SVGs are rasterized at the size they are drawn at, the tint makes this one white
+        ctx:image(icon, 4, 4, 16, 16, { 1.0, 1.0, 1.0 })
+    end)
+end)()
+
+client:run()
@@ -4,8 +4,44 @@ use std::{
     os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
 };
 
+mod pool;
+
+pub use pool::ShmPool;
+
 const MEMFD_NAME: &CStr = c"progmemfd";
 
+/// File seals which restrict how the size of a `Shm` may change, see `memfd_create(2)`
+///
+/// Seals can only be added, never removed, and apply to every process sharing the memory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Seals {
+    /// The memory can't be made smaller, so other processes can't be made to fault on it
+    pub shrink: bool,
+    /// The memory can't be made larger
+    pub grow: bool,
+}
+
+impl Seals {
+    fn flags(&self) -> libc::c_int {
+        let mut flags = 0;
+        if self.shrink {
+            flags |= libc::F_SEAL_SHRINK;
+        }
+        if self.grow {
+            flags |= libc::F_SEAL_GROW;
+        }
+        flags
+    }
+
+    fn from_flags(flags: libc::c_int) -> Seals {
+        Seals {
+            shrink: flags & libc::F_SEAL_SHRINK != 0,
+            grow: flags & libc::F_SEAL_GROW != 0,
+        }
+    }
+}
+
+/// Anonymous shared memory, mapped into this process for as long as the `Shm` lives
 #[derive(Debug)]
 pub struct Shm {
     fd: OwnedFd,
@@ -13,6 +49,11 @@ pub struct Shm {
     length: usize,
 }
 
+// The mapping is owned exclusively by `Shm` and only reachable through its `&self`/`&mut self`
+// methods, so moving it to another thread is sound. Other processes writing to the same memory
+// (e.g. the compositor) is outside of what Rust can guard against either way.
+unsafe impl Send for Shm {}
+
 impl Shm {
     pub fn new(length: usize) -> IoResult<Self> {
         if length == 0 {
@@ -27,14 +68,47 @@ impl Shm {
         Ok(Shm { fd, ptr, length })
     }
 
+    /// Resizes the memory, keeping its contents up to the smallest of both lengths
+    ///
+    /// The data may move to a different address, slices returned earlier are invalidated.
     pub fn resize(&mut self, length: usize) -> IoResult<()> {
-        match remap_shm_memory(length, self.fd.as_fd()) {
-            Ok(ptr) => {
-                self.ptr = ptr;
-                self.length = length;
-                Ok(())
-            }
-            Err(err) => Err(err),
+        if length == 0 {
+            return Err(IoError::new(
+                std::io::ErrorKind::InvalidInput,
+                "Zero-length SHM is not allowed",
+            ));
+        }
+        if length == self.length {
+            return Ok(());
+        }
+
+        self.ptr = remap_shm_memory(self.ptr, self.length, length, self.fd.as_fd())?;
+        self.length = length;
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// Adds `seals` on top of the ones already applied
+    ///
+    /// Once sealed against shrinking, `resize` to a smaller length only shrinks the mapping.
+    pub fn add_seals(&self, seals: Seals) -> IoResult<()> {
+        match unsafe { libc::fcntl(self.fd.as_raw_fd(), libc::F_ADD_SEALS, seals.flags()) } {
+            -1 => Err(IoError::last_os_error()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn get_seals(&self) -> IoResult<Seals> {
+        match unsafe { libc::fcntl(self.fd.as_raw_fd(), libc::F_GET_SEALS) } {
+            -1 => Err(IoError::last_os_error()),
+            flags => Ok(Seals::from_flags(flags)),
         }
     }
 
@@ -55,11 +129,20 @@ impl Shm {
     }
 }
 
+impl Drop for Shm {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, self.length) };
+    }
+}
+
 /// Allocates memory for shared processes, returning the file descriptor pointing to the allocated
 /// memory
 fn allocate_shm(length: usize) -> IoResult<OwnedFd> {
     unsafe {
-        let fd = match libc::memfd_create(MEMFD_NAME.as_ptr(), libc::MFD_CLOEXEC) {
+        let fd = match libc::memfd_create(
+            MEMFD_NAME.as_ptr(),
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING,
+        ) {
             -1 => Err(IoError::last_os_error()),
             fd => Ok(OwnedFd::from_raw_fd(fd)),
         }?;
@@ -71,12 +154,45 @@ fn allocate_shm(length: usize) -> IoResult<OwnedFd> {
     }
 }
 
-/// Resize an already mapped a shm buffer
-fn remap_shm_memory(length: usize, fd: BorrowedFd) -> IoResult<*mut u8> {
-    if unsafe { libc::ftruncate(fd.as_raw_fd(), length as libc::off_t) } == -1 {
+/// Resize an already mapped shm buffer, returning the (possibly moved) mapping
+///
+/// The old mapping is always released on success, on failure it is left untouched.
+fn remap_shm_memory(
+    ptr: *mut u8,
+    old_length: usize,
+    length: usize,
+    fd: BorrowedFd,
+) -> IoResult<*mut u8> {
+    // Grow the file before the mapping, but shrink the mapping before the file so no part of the
+    // mapping is ever beyond the end of the file
+    if length > old_length
+        && unsafe { libc::ftruncate(fd.as_raw_fd(), length as libc::off_t) } == -1
+    {
         return Err(IoError::last_os_error());
     }
-    map_shm_memory(length, fd)
+
+    let new_ptr = unsafe {
+        libc::mremap(
+            ptr as *mut libc::c_void,
+            old_length,
+            length,
+            libc::MREMAP_MAYMOVE,
+        )
+    };
+    let new_ptr = if new_ptr == libc::MAP_FAILED {
+        // Fall back to a fresh mapping, which never overlaps with the old one
+        let new_ptr = map_shm_memory(length, fd)?;
+        unsafe { libc::munmap(ptr as *mut libc::c_void, old_length) };
+        new_ptr
+    } else {
+        new_ptr as *mut u8
+    };
+
+    if length < old_length {
+        // A failed shrink only wastes memory, the mapping is valid either way
+        unsafe { libc::ftruncate(fd.as_raw_fd(), length as libc::off_t) };
+    }
+    Ok(new_ptr)
 }
 
 /// Maps memory to a shm buffer, returning the newly mapped byte array
@@ -135,4 +251,106 @@ mod tests {
     fn alloc_gb() {
         Shm::new(1024 * 1024 * 1024).expect("Failed 1gb");
     }
+
+    /// Inode of the memfd, identifies its mappings in `/proc/self/maps`
+    fn inode(shm: &Shm) -> u64 {
+        let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+        assert_eq!(unsafe { libc::fstat(shm.get_raw_fd(), &mut stat) }, 0);
+        stat.st_ino
+    }
+
+    /// Number of mappings of the memfd with the given inode, tests run in parallel so mappings
+    /// can't simply be counted by name
+    fn mapping_count(inode: u64) -> usize {
+        std::fs::read_to_string("/proc/self/maps")
+            .expect("Unable to read /proc/self/maps")
+            .lines()
+            .filter(|line| line.contains("memfd:progmemfd"))
+            .filter(|line| line.split_whitespace().nth(4) == Some(&inode.to_string()))
+            .count()
+    }
+
+    #[test]
+    fn drop_unmaps() {
+        let shm = Shm::new(4096).expect("Failed 4kb");
+        let inode = inode(&shm);
+        assert_eq!(mapping_count(inode), 1);
+        drop(shm);
+        assert_eq!(mapping_count(inode), 0);
+    }
+
+    #[test]
+    fn resize_keeps_single_mapping() {
+        let mut shm = Shm::new(4096).expect("Failed 4kb");
+        let inode = inode(&shm);
+        for i in 1..=500 {
+            let length = 4096 * (1 + i % 37);
+            shm.resize(length)
+                .unwrap_or_else(|_| panic!("Failed resize {i}"));
+            assert_eq!(shm.len(), length);
+            assert_eq!(mapping_count(inode), 1, "Leaked mapping at resize {i}");
+        }
+    }
+
+    #[test]
+    fn resize_keeps_data() {
+        let mut shm = Shm::new(4096).expect("Failed 4kb");
+        shm.data_mut().fill(0xAB);
+        shm.resize(8 * 1024 * 1024).expect("Failed grow");
+        assert!(shm.data()[..4096].iter().all(|byte| *byte == 0xAB));
+        assert!(shm.data()[4096..].iter().all(|byte| *byte == 0));
+
+        shm.resize(1024).expect("Failed shrink");
+        assert_eq!(shm.data().len(), 1024);
+        assert!(shm.data().iter().all(|byte| *byte == 0xAB));
+    }
+
+    #[test]
+    #[should_panic]
+    fn resize_zero() {
+        let mut shm = Shm::new(4096).expect("Failed 4kb");
+        shm.resize(0).expect("Zero byte resizes should fail");
+    }
+
+    #[test]
+    fn seal_grow() {
+        let mut shm = Shm::new(4096).expect("Failed 4kb");
+        assert_eq!(shm.get_seals().expect("Failed get seals"), Seals::default());
+        shm.add_seals(Seals {
+            shrink: false,
+            grow: true,
+        })
+        .expect("Failed seal");
+        assert!(shm.resize(8192).is_err());
+        assert_eq!(shm.len(), 4096);
+        assert_eq!(mapping_count(inode(&shm)), 1);
+    }
+
+    #[test]
+    fn seal_shrink() {
+        let mut shm = Shm::new(8192).expect("Failed 8kb");
+        shm.add_seals(Seals {
+            shrink: true,
+            grow: false,
+        })
+        .expect("Failed seal");
+        shm.resize(16384).expect("Sealed against shrinking only");
+        shm.resize(4096)
+            .expect("Shrinking the mapping is still allowed");
+        assert_eq!(shm.len(), 4096);
+        assert_eq!(mapping_count(inode(&shm)), 1);
+    }
+
+    #[test]
+    fn send_to_thread() {
+        let mut shm = Shm::new(4096).expect("Failed 4kb");
+        shm.data_mut()[0] = 42;
+        let shm = std::thread::spawn(move || {
+            assert_eq!(shm.data()[0], 42);
+            shm
+        })
+        .join()
+        .expect("Thread panicked");
+        assert_eq!(shm.data()[0], 42);
+    }
 }
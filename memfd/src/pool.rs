@@ -0,0 +1,252 @@
+use std::io::{Error as IoError, ErrorKind, Result as IoResult};
+use std::ops::Range;
+
+use crate::{Seals, Shm};
+
+/// A `Shm` split into equally sized buffers, which are handed out one at a time
+///
+/// Buffer `i` starts at `offset(i)`. A buffer is busy from `acquire` until `release`, e.g. from
+/// attaching it to a surface until the compositor sends `wl_buffer.release`.
+///
+/// The memory never shrinks, so it is sealed against shrinking and a compositor which mapped it
+/// can't be made to fault on it.
+///
+/// Buffers which are busy while the buffers are resized are retired, their memory stays reserved
+/// until `release_retired` and no new buffer overlapping it is handed out.
+#[derive(Debug)]
+pub struct ShmPool {
+    shm: Shm,
+    buffer_size: usize,
+    busy: Vec<bool>,
+    /// Memory of busy buffers from before a resize
+    retired: Vec<Range<usize>>,
+}
+
+impl ShmPool {
+    /// Creates a pool of `count` buffers of `buffer_size` bytes each
+    pub fn new(buffer_size: usize, count: usize) -> IoResult<Self> {
+        let shm = Shm::new(pool_length(buffer_size, count)?)?;
+        shm.add_seals(Seals {
+            shrink: true,
+            grow: false,
+        })?;
+        Ok(ShmPool {
+            shm,
+            buffer_size,
+            busy: vec![false; count],
+            retired: Vec::new(),
+        })
+    }
+
+    /// Creates a pool which can't ever grow, `resize_buffers` only accepts sizes which fit
+    pub fn fixed(buffer_size: usize, count: usize) -> IoResult<Self> {
+        let pool = ShmPool::new(buffer_size, count)?;
+        pool.shm.add_seals(Seals {
+            shrink: true,
+            grow: true,
+        })?;
+        Ok(pool)
+    }
+
+    pub fn get_shm(&self) -> &Shm {
+        &self.shm
+    }
+
+    /// Total length of the memory, which is at least `count() * buffer_size()`
+    pub fn len(&self) -> usize {
+        self.shm.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shm.is_empty()
+    }
+
+    pub fn buffer_size(&self) -> usize {
+        self.buffer_size
+    }
+
+    pub fn count(&self) -> usize {
+        self.busy.len()
+    }
+
+    /// Start of buffer `index` in the memory
+    pub fn offset(&self, index: usize) -> usize {
+        index * self.buffer_size
+    }
+
+    /// Marks the first free buffer as busy and returns its index, `None` if all of them are busy
+    /// or overlap retired buffers
+    pub fn acquire(&mut self) -> Option<usize> {
+        let index = (0..self.count()).find(|index| {
+            let range = self.range(*index);
+            !self.busy[*index]
+                && self
+                    .retired
+                    .iter()
+                    .all(|retired| retired.end <= range.start || range.end <= retired.start)
+        })?;
+        self.busy[index] = true;
+        Some(index)
+    }
+
+    /// Makes buffer `index` available to `acquire` again
+    pub fn release(&mut self, index: usize) {
+        if let Some(busy) = self.busy.get_mut(index) {
+            *busy = false;
+        }
+    }
+
+    /// Frees the memory of the retired buffer which started at `offset`
+    pub fn release_retired(&mut self, offset: usize) {
+        self.retired.retain(|retired| retired.start != offset);
+    }
+
+    pub fn is_busy(&self, index: usize) -> bool {
+        self.busy.get(index).copied().unwrap_or(false)
+    }
+
+    /// Whether memory of a buffer from before a resize is still in use
+    pub fn has_retired(&self) -> bool {
+        !self.retired.is_empty()
+    }
+
+    fn range(&self, index: usize) -> Range<usize> {
+        let offset = self.offset(index);
+        offset..offset + self.buffer_size
+    }
+
+    pub fn buffer(&self, index: usize) -> &[u8] {
+        let range = self.range(index);
+        &self.shm.data()[range]
+    }
+
+    pub fn buffer_mut(&mut self, index: usize) -> &mut [u8] {
+        let range = self.range(index);
+        &mut self.shm.data_mut()[range]
+    }
+
+    /// Changes the size of every buffer, growing the memory in place when they no longer fit
+    ///
+    /// Returns whether the memory grew, in which case other processes have to be told about the
+    /// new length. The old layout is gone, so busy buffers are retired rather than released.
+    pub fn resize_buffers(&mut self, buffer_size: usize) -> IoResult<bool> {
+        let length = pool_length(buffer_size, self.count())?;
+        let grew = length > self.shm.len();
+        if grew {
+            self.shm.resize(length)?;
+        }
+        let busy: Vec<Range<usize>> = (0..self.count())
+            .filter(|index| self.busy[*index])
+            .map(|index| self.range(index))
+            .collect();
+        self.retired.extend(busy);
+        self.buffer_size = buffer_size;
+        self.busy.fill(false);
+        Ok(grew)
+    }
+}
+
+fn pool_length(buffer_size: usize, count: usize) -> IoResult<usize> {
+    buffer_size.checked_mul(count).ok_or(IoError::new(
+        ErrorKind::InvalidInput,
+        "SHM pool length overflows",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sealed_against_shrinking() {
+        let pool = ShmPool::new(1024, 2).expect("Failed pool");
+        let seals = pool.get_shm().get_seals().expect("Failed get seals");
+        assert!(seals.shrink && !seals.grow);
+
+        let pool = ShmPool::fixed(1024, 2).expect("Failed fixed pool");
+        let seals = pool.get_shm().get_seals().expect("Failed get seals");
+        assert!(seals.shrink && seals.grow);
+    }
+
+    #[test]
+    fn acquire_and_release() {
+        let mut pool = ShmPool::new(1024, 3).expect("Failed pool");
+        assert_eq!(pool.acquire(), Some(0));
+        assert_eq!(pool.acquire(), Some(1));
+        assert_eq!(pool.acquire(), Some(2));
+        assert_eq!(pool.acquire(), None);
+
+        pool.release(1);
+        assert!(!pool.is_busy(1));
+        assert_eq!(pool.acquire(), Some(1));
+        assert_eq!(pool.acquire(), None);
+    }
+
+    #[test]
+    fn buffers_dont_overlap() {
+        let mut pool = ShmPool::new(1024, 2).expect("Failed pool");
+        pool.buffer_mut(0).fill(1);
+        pool.buffer_mut(1).fill(2);
+        assert_eq!(pool.offset(1), 1024);
+        assert!(pool.buffer(0).iter().all(|byte| *byte == 1));
+        assert!(pool.buffer(1).iter().all(|byte| *byte == 2));
+    }
+
+    #[test]
+    fn grows_but_never_shrinks() {
+        let mut pool = ShmPool::new(1024, 2).expect("Failed pool");
+        assert!(pool.resize_buffers(4096).expect("Failed grow"));
+        assert_eq!(pool.len(), 8192);
+
+        assert!(!pool.resize_buffers(512).expect("Failed shrink"));
+        assert_eq!(pool.len(), 8192);
+        assert_eq!(pool.buffer(1).len(), 512);
+    }
+
+    #[test]
+    fn resize_retires_busy_buffers() {
+        let mut pool = ShmPool::new(1024, 2).expect("Failed pool");
+        assert_eq!(pool.acquire(), Some(0));
+        assert_eq!(pool.acquire(), Some(1));
+        pool.release(0);
+
+        // Buffer 1 covered 1024..2048, which both new buffers overlap
+        pool.resize_buffers(1536).expect("Failed grow");
+        assert!(pool.has_retired());
+        assert!(!pool.is_busy(0));
+        assert_eq!(pool.acquire(), None);
+
+        pool.release_retired(1024);
+        assert!(!pool.has_retired());
+        assert_eq!(pool.acquire(), Some(0));
+        assert_eq!(pool.acquire(), Some(1));
+    }
+
+    #[test]
+    fn retired_buffers_only_block_overlapping_ones() {
+        let mut pool = ShmPool::new(1024, 2).expect("Failed pool");
+        assert_eq!(pool.acquire(), Some(0));
+        assert_eq!(pool.acquire(), Some(1));
+        pool.release(0);
+
+        // The retired buffer covers 1024..2048, past the new buffers 0..512 and 512..1024
+        pool.resize_buffers(512).expect("Failed shrink");
+        assert_eq!(pool.acquire(), Some(0));
+        assert_eq!(pool.acquire(), Some(1));
+        assert_eq!(pool.acquire(), None);
+    }
+
+    #[test]
+    fn fixed_pool_cant_grow() {
+        let mut pool = ShmPool::fixed(1024, 2).expect("Failed fixed pool");
+        assert!(pool.resize_buffers(4096).is_err());
+        assert_eq!(pool.buffer_size(), 1024);
+        assert!(!pool.resize_buffers(512).expect("Fits in the pool"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn empty_pool() {
+        ShmPool::new(1024, 0).expect("Empty pools should fail");
+    }
+}
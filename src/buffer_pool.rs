@@ -0,0 +1,221 @@
+use std::io::Result as IoResult;
+
+use memfd::ShmPool;
+use wayland_client::{
+    Connection, Dispatch, QueueHandle,
+    backend::ObjectId,
+    protocol::{
+        wl_buffer::{self, WlBuffer},
+        wl_shm::{Format, WlShm},
+        wl_shm_pool::WlShmPool,
+        wl_surface::WlSurface,
+    },
+};
+
+use crate::{software::Pixmap, state::WaylandState};
+
+const BYTES_PER_PIXEL: u32 = 4;
+/// Enough to draw the next frame while the compositor still reads the previous one
+const BUFFER_COUNT: usize = 2;
+
+/// The `wl_shm` buffers of a surface, shared with the compositor through a `memfd::ShmPool`
+///
+/// Every `WlBuffer` carries the `ObjectId` of its layer surface, so the `wl_buffer.release` can be
+/// routed back to the pool it came from.
+#[derive(Debug)]
+pub struct BufferPool {
+    shm: ShmPool,
+    pool: WlShmPool,
+    /// Created lazily, a slot is `None` until it is first used or after the pool was resized
+    buffers: Vec<Option<WlBuffer>>,
+    /// Buffers of an outdated size the compositor still holds, along with their offset
+    retired: Vec<(WlBuffer, usize)>,
+    width: u32,
+    height: u32,
+    /// The buffer which was acquired last
+    current: Option<usize>,
+    /// Whether the current buffer was attached, it is only released by the compositor if so
+    attached: bool,
+    surface_id: ObjectId,
+}
+
+impl BufferPool {
+    pub fn new(
+        shm: &WlShm,
+        width: u32,
+        height: u32,
+        surface_id: ObjectId,
+        queue_handle: &QueueHandle<WaylandState>,
+    ) -> IoResult<Self> {
+        let memory = ShmPool::new(buffer_size(width, height), BUFFER_COUNT)?;
+        let pool = shm.create_pool(
+            memory.get_shm().get_fd(),
+            memory.len() as i32,
+            queue_handle,
+            (),
+        );
+        Ok(BufferPool {
+            shm: memory,
+            pool,
+            buffers: vec![None; BUFFER_COUNT],
+            retired: Vec::new(),
+            width,
+            height,
+            current: None,
+            attached: false,
+            surface_id,
+        })
+    }
+
+    /// Changes the dimensions of every buffer, growing the pool in place if they no longer fit
+    ///
+    /// The buffers of the old dimensions are destroyed, `acquire` creates new ones. Buffers the
+    /// compositor still reads from are destroyed once it releases them, until then their memory
+    /// isn't drawn into.
+    pub fn resize(&mut self, width: u32, height: u32) -> IoResult<()> {
+        if self.width == width && self.height == height {
+            return Ok(());
+        }
+
+        // A current buffer which was never attached is not held by the compositor
+        if let Some(index) = self.current
+            && !self.attached
+        {
+            self.shm.release(index);
+        }
+        // Busy buffers are retired by the resize, their offsets belong to the old layout
+        let busy: Vec<(bool, usize)> = (0..self.buffers.len())
+            .map(|index| (self.shm.is_busy(index), self.shm.offset(index)))
+            .collect();
+        if self.shm.resize_buffers(buffer_size(width, height))? {
+            self.pool.resize(self.shm.len() as i32);
+        }
+        self.width = width;
+        self.height = height;
+        self.current = None;
+        for (index, slot) in self.buffers.iter_mut().enumerate() {
+            let Some(buffer) = slot.take() else {
+                continue;
+            };
+            match busy[index] {
+                (true, offset) => self.retired.push((buffer, offset)),
+                (false, _) => buffer.destroy(),
+            }
+        }
+        Ok(())
+    }
+
+    /// Picks a buffer the compositor is not using as the current one, `false` if it holds on to
+    /// all of them
+    ///
+    /// A current buffer which was never attached is given back first, so skipped frames don't
+    /// leak buffers.
+    pub fn acquire(&mut self, queue_handle: &QueueHandle<WaylandState>) -> bool {
+        if let Some(index) = self.current.take()
+            && !self.attached
+        {
+            self.shm.release(index);
+        }
+        let Some(index) = self.shm.acquire() else {
+            return false;
+        };
+        self.current = Some(index);
+        self.attached = false;
+
+        self.buffers[index].get_or_insert_with(|| {
+            self.pool.create_buffer(
+                self.shm.offset(index) as i32,
+                self.width as i32,
+                self.height as i32,
+                (self.width * BYTES_PER_PIXEL) as i32,
+                Format::Argb8888,
+                queue_handle,
+                self.surface_id.clone(),
+            )
+        });
+        true
+    }
+
+    /// Attaches the current buffer to `surface` and damages all of it, takes effect on the next
+    /// commit
+    ///
+    /// The buffer stays busy until the compositor releases it.
+    pub fn attach(&mut self, surface: &WlSurface) {
+        if let Some(buffer) = self.current.and_then(|index| self.buffers[index].as_ref()) {
+            surface.attach(Some(buffer), 0, 0);
+            surface.damage(0, 0, self.width as i32, self.height as i32);
+            self.attached = true;
+        }
+    }
+
+    /// Pixels of the buffer which was acquired last, empty if there is none
+    pub fn data(&self) -> &[u8] {
+        match self.current {
+            Some(index) => self.shm.buffer(index),
+            None => &[],
+        }
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        match self.current {
+            Some(index) => self.shm.buffer_mut(index),
+            None => &mut [],
+        }
+    }
+
+    /// The current buffer wrapped for software rendering
+    pub fn get_pixmap(&mut self) -> Pixmap<'_> {
+        let (width, height) = (self.width, self.height);
+        Pixmap::new(self.data_mut(), width, height)
+    }
+
+    /// Frees the slot of `buffer`, buffers of an outdated size are destroyed
+    fn release(&mut self, buffer: &WlBuffer) {
+        if let Some(index) = self
+            .buffers
+            .iter()
+            .position(|slot| slot.as_ref() == Some(buffer))
+        {
+            self.shm.release(index);
+        } else if let Some(position) = self
+            .retired
+            .iter()
+            .position(|(retired, _)| retired == buffer)
+        {
+            let (retired, offset) = self.retired.swap_remove(position);
+            self.shm.release_retired(offset);
+            retired.destroy();
+        }
+    }
+
+    pub fn destroy(self) {
+        for buffer in self.buffers.into_iter().flatten() {
+            buffer.destroy();
+        }
+        for (buffer, _) in self.retired {
+            buffer.destroy();
+        }
+        self.pool.destroy();
+    }
+}
+
+fn buffer_size(width: u32, height: u32) -> usize {
+    width as usize * height as usize * BYTES_PER_PIXEL as usize
+}
+
+impl Dispatch<WlBuffer, ObjectId> for WaylandState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlBuffer,
+        event: wl_buffer::Event,
+        surface_id: &ObjectId,
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        if let wl_buffer::Event::Release = event
+            && let Some(pool) = state.get_buffer_pool(surface_id)
+        {
+            pool.release(proxy);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use std::path::Path;
+
+use crate::opengl::{
+    highlevel::ElementsMode,
+    text::{Font, TextOptions},
+    types::{GlResult, Mat3, Vec2, Vec4},
+};
+
+/// How drawn colors are combined with the pixels already there
+///
+/// Surfaces are stored with premultiplied alpha, as Wayland compositors expect.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Overwrites the pixels, alpha included
+    Replace,
+    /// Straight alpha colors are drawn over the pixels
+    #[default]
+    Normal,
+    /// Straight alpha colors are added onto the pixels, brightening them
+    Additive,
+    /// Like `Normal`, but the colors are already multiplied by their alpha
+    Premultiplied,
+}
+
+/// RGBA pixels with straight alpha, rows are not padded
+#[derive(Debug, Clone, Copy)]
+pub struct Image<'a> {
+    pub pixels: &'a [u8],
+    pub width: usize,
+    pub height: usize,
+}
+
+/// An axis aligned rectangle in surface coordinates, empty if `max` is not past `min`
+#[derive(Debug, Clone, Copy)]
+pub struct ClipRect {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl ClipRect {
+    /// The smallest rectangle containing every point
+    fn bounding(points: &[Vec2]) -> ClipRect {
+        let min = points
+            .iter()
+            .fold(Vec2::new(f32::MAX, f32::MAX), |min, point| {
+                Vec2::new(min.x.min(point.x), min.y.min(point.y))
+            });
+        let max = points
+            .iter()
+            .fold(Vec2::new(f32::MIN, f32::MIN), |max, point| {
+                Vec2::new(max.x.max(point.x), max.y.max(point.y))
+            });
+        ClipRect { min, max }
+    }
+
+    fn intersect(&self, other: &ClipRect) -> ClipRect {
+        ClipRect {
+            min: Vec2::new(self.min.x.max(other.min.x), self.min.y.max(other.min.y)),
+            max: Vec2::new(self.max.x.min(other.max.x), self.max.y.min(other.max.y)),
+        }
+    }
+}
+
+/// The transforms and clip rectangles pushed onto a canvas, along with its blend mode
+///
+/// Every entry is already combined with the ones below it, so only the top matters.
+#[derive(Debug, Clone, Default)]
+pub struct CanvasStack {
+    transforms: Vec<Mat3>,
+    clips: Vec<ClipRect>,
+    blend_mode: BlendMode,
+}
+
+impl CanvasStack {
+    pub fn blend_mode(&self) -> BlendMode {
+        self.blend_mode
+    }
+
+    pub fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.blend_mode = mode;
+    }
+
+    pub fn transform(&self) -> Mat3 {
+        self.transforms.last().copied().unwrap_or_default()
+    }
+
+    /// The area drawing is restricted to, `None` if nothing is clipped
+    pub fn clip(&self) -> Option<ClipRect> {
+        self.clips.last().copied()
+    }
+
+    /// Applies `transform` before the current transform, so it is relative to the pushed ones
+    pub fn push_transform(&mut self, transform: Mat3) {
+        self.transforms.push(self.transform() * transform);
+    }
+
+    pub fn pop_transform(&mut self) {
+        self.transforms.pop();
+    }
+
+    /// Restricts drawing to the rectangle at `pos`, which is transformed by the current transform
+    ///
+    /// Clips stay axis aligned, a rotated rectangle clips to the rectangle around it.
+    pub fn push_clip(&mut self, pos: Vec2, size: Vec2) {
+        let transform = self.transform();
+        let corners = [
+            pos,
+            pos + Vec2::new(size.x, 0.0),
+            pos + Vec2::new(0.0, size.y),
+            pos + size,
+        ];
+        let rect = ClipRect::bounding(&corners.map(|corner| transform.transform_point(corner)));
+        let rect = match self.clip() {
+            Some(clip) => clip.intersect(&rect),
+            None => rect,
+        };
+        self.clips.push(rect);
+    }
+
+    pub fn pop_clip(&mut self) {
+        self.clips.pop();
+    }
+}
+
+/// Drawing operations shared by every rendering backend, so drawing code doesn't depend on one
+///
+/// Coordinates are logical pixels of the surface with the origin in the top-left corner and y
+/// pointing down, rectangles are given by their top-left corner. Everything is moved by the pushed
+/// transforms and cut off by the pushed clip rectangles. Colors are straight alpha unless the
+/// blend mode says otherwise.
+pub trait Canvas {
+    /// Overwrites the clipped area with `color`, alpha included, regardless of the blend mode
+    ///
+    /// A `color` with an alpha below 1 leaves the surface see-through.
+    fn clear(&mut self, color: Vec4) -> GlResult<()>;
+
+    /// Changes how everything drawn afterwards is combined with the existing pixels
+    fn set_blend_mode(&mut self, mode: BlendMode) -> GlResult<()>;
+
+    fn fill_rect(&mut self, pos: Vec2, size: Vec2, color: Vec4) -> GlResult<()>;
+
+    /// Draws `points` assembled into primitives the same way `glDrawArrays` does for `mode`
+    fn fill_polygon(&mut self, mode: ElementsMode, points: &[Vec2], color: Vec4) -> GlResult<()>;
+
+    /// Draws one pixel wide lines through `points`, back to the first one if `closed`
+    fn stroke_path(&mut self, points: &[Vec2], closed: bool, color: Vec4) -> GlResult<()>;
+
+    /// Draws `text` with its top-left corner at `pos`, following the transform like any shape
+    fn draw_text(
+        &mut self,
+        font: &Font,
+        text: &str,
+        pos: Vec2,
+        options: &TextOptions,
+    ) -> GlResult<()>;
+
+    /// Draws `image` scaled into the rectangle at `pos`
+    fn draw_image(&mut self, image: Image, pos: Vec2, size: Vec2) -> GlResult<()>;
+
+    /// Draws the PNG, JPEG or SVG at `path` scaled into the rectangle at `pos`, its colors
+    /// multiplied by `tint`
+    ///
+    /// Images are decoded once for every size they are drawn at and cached.
+    fn draw_image_file(&mut self, path: &Path, pos: Vec2, size: Vec2, tint: Vec4) -> GlResult<()>;
+
+    fn push_clip(&mut self, pos: Vec2, size: Vec2) -> GlResult<()>;
+
+    fn pop_clip(&mut self) -> GlResult<()>;
+
+    /// Transforms everything drawn until the matching `pop_transform`, on top of the pushed ones
+    fn push_transform(&mut self, transform: Mat3);
+
+    fn pop_transform(&mut self);
+}
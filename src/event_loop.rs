@@ -0,0 +1,274 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    io,
+    os::fd::RawFd,
+    time::{Duration, Instant},
+};
+
+pub type LoopCallback = Box<dyn FnMut()>;
+pub type FdCallback = Box<dyn FnMut(Readiness)>;
+
+/// Identifies a timer, fd watcher or idle callback registered in an `EventLoop`
+pub type SourceId = u64;
+
+/// Which kind of readiness a watched fd is polled for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Interest {
+    pub read: bool,
+    pub write: bool,
+}
+
+impl Interest {
+    pub const READ: Interest = Interest {
+        read: true,
+        write: false,
+    };
+
+    fn poll_events(&self) -> libc::c_short {
+        let mut events = 0;
+        if self.read {
+            events |= libc::POLLIN;
+        }
+        if self.write {
+            events |= libc::POLLOUT;
+        }
+        events
+    }
+}
+
+/// The state of a watched fd as reported by `poll`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Readiness {
+    pub readable: bool,
+    pub writable: bool,
+    /// The other end was closed or the fd is in an error state, it will stay ready forever
+    pub hangup: bool,
+}
+
+impl Readiness {
+    fn from_poll_events(revents: libc::c_short) -> Readiness {
+        Readiness {
+            readable: revents & libc::POLLIN != 0,
+            writable: revents & libc::POLLOUT != 0,
+            hangup: revents & (libc::POLLHUP | libc::POLLERR | libc::POLLNVAL) != 0,
+        }
+    }
+}
+
+struct Timer {
+    deadline: Instant,
+    /// Repeating timers are rescheduled after firing, one-shot timers are removed
+    interval: Option<Duration>,
+    /// Taken out while the callback runs, so the loop isn't borrowed during the call
+    callback: Option<LoopCallback>,
+}
+
+struct FdWatcher {
+    fd: RawFd,
+    interest: Interest,
+    callback: Option<FdCallback>,
+}
+
+/// Timers, fd watchers and idle callbacks which are waited on together with the Wayland socket
+///
+/// The loop is shared through a `RefCell`, callbacks are always invoked without it being borrowed
+/// so they are free to register or remove sources.
+#[derive(Default)]
+pub struct EventLoop {
+    next_id: SourceId,
+    timers: HashMap<SourceId, Timer>,
+    watchers: HashMap<SourceId, FdWatcher>,
+    idle: VecDeque<(SourceId, LoopCallback)>,
+}
+
+impl EventLoop {
+    fn allocate_id(&mut self) -> SourceId {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    /// Calls `callback` after `delay`, and every `interval` after that if given
+    pub fn add_timer(
+        &mut self,
+        delay: Duration,
+        interval: Option<Duration>,
+        callback: LoopCallback,
+    ) -> SourceId {
+        let id = self.allocate_id();
+        self.timers.insert(
+            id,
+            Timer {
+                deadline: Instant::now() + delay,
+                interval,
+                callback: Some(callback),
+            },
+        );
+        id
+    }
+
+    /// Calls `callback` every time `fd` becomes ready for `interest`
+    ///
+    /// The fd is not owned by the loop, remove the watcher before closing it.
+    pub fn add_watcher(&mut self, fd: RawFd, interest: Interest, callback: FdCallback) -> SourceId {
+        let id = self.allocate_id();
+        self.watchers.insert(
+            id,
+            FdWatcher {
+                fd,
+                interest,
+                callback: Some(callback),
+            },
+        );
+        id
+    }
+
+    /// Calls `callback` once, the next time the loop has nothing else to do
+    pub fn add_idle(&mut self, callback: LoopCallback) -> SourceId {
+        let id = self.allocate_id();
+        self.idle.push_back((id, callback));
+        id
+    }
+
+    /// Removes a timer, watcher or idle callback, returns `false` if it was already gone
+    pub fn remove(&mut self, id: SourceId) -> bool {
+        let idle_count = self.idle.len();
+        self.idle.retain(|(idle_id, _)| *idle_id != id);
+        self.timers.remove(&id).is_some()
+            || self.watchers.remove(&id).is_some()
+            || self.idle.len() != idle_count
+    }
+
+    pub fn has_idle(&self) -> bool {
+        !self.idle.is_empty()
+    }
+
+    /// The earliest moment a timer fires
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.timers.values().map(|timer| timer.deadline).min()
+    }
+
+    /// `pollfd`s for every watcher, in the same order as the returned ids
+    pub fn poll_fds(&self) -> (Vec<SourceId>, Vec<libc::pollfd>) {
+        self.watchers
+            .iter()
+            .map(|(id, watcher)| {
+                let pollfd = libc::pollfd {
+                    fd: watcher.fd,
+                    events: watcher.interest.poll_events(),
+                    revents: 0,
+                };
+                (*id, pollfd)
+            })
+            .unzip()
+    }
+
+    /// Fires every timer whose deadline passed, returns whether any did
+    pub fn dispatch_timers(event_loop: &RefCell<EventLoop>, now: Instant) -> bool {
+        let mut due: Vec<(Instant, SourceId)> = event_loop
+            .borrow()
+            .timers
+            .iter()
+            .filter(|(_, timer)| timer.deadline <= now)
+            .map(|(id, timer)| (timer.deadline, *id))
+            .collect();
+        due.sort();
+
+        for (_, id) in &due {
+            let Some(mut callback) = event_loop
+                .borrow_mut()
+                .timers
+                .get_mut(id)
+                .and_then(|timer| timer.callback.take())
+            else {
+                continue;
+            };
+            callback();
+
+            let mut event_loop = event_loop.borrow_mut();
+            // The callback may have removed its own timer
+            let Some(timer) = event_loop.timers.get_mut(id) else {
+                continue;
+            };
+            match timer.interval {
+                Some(interval) => {
+                    // Skip the ticks which were missed instead of firing them all at once
+                    let next = timer.deadline + interval;
+                    timer.deadline = if next > now { next } else { now + interval };
+                    timer.callback = Some(callback);
+                }
+                None => {
+                    event_loop.timers.remove(id);
+                }
+            }
+        }
+        !due.is_empty()
+    }
+
+    /// Invokes the callbacks of the watchers whose fds became ready
+    pub fn dispatch_watchers(event_loop: &RefCell<EventLoop>, ready: &[(SourceId, Readiness)]) {
+        for (id, readiness) in ready {
+            let Some(mut callback) = event_loop
+                .borrow_mut()
+                .watchers
+                .get_mut(id)
+                .and_then(|watcher| watcher.callback.take())
+            else {
+                continue;
+            };
+            callback(*readiness);
+
+            if let Some(watcher) = event_loop.borrow_mut().watchers.get_mut(id) {
+                watcher.callback.get_or_insert(callback);
+            }
+        }
+    }
+
+    /// Runs the idle callbacks which were queued before this call
+    pub fn dispatch_idle(event_loop: &RefCell<EventLoop>) {
+        let count = event_loop.borrow().idle.len();
+        for _ in 0..count {
+            let idle = event_loop.borrow_mut().idle.pop_front();
+            match idle {
+                Some((_, mut callback)) => callback(),
+                None => break,
+            }
+        }
+    }
+}
+
+/// Converts a deadline into a `poll` timeout in milliseconds, rounding up to avoid early wakeups
+pub fn poll_timeout(deadline: Option<Instant>, now: Instant) -> libc::c_int {
+    match deadline {
+        Some(deadline) => {
+            let remaining = deadline.saturating_duration_since(now);
+            remaining
+                .as_nanos()
+                .div_ceil(1_000_000)
+                .try_into()
+                .unwrap_or(libc::c_int::MAX)
+        }
+        None => -1,
+    }
+}
+
+/// Waits until one of `fds` is ready or `timeout` milliseconds passed, `-1` waits forever
+///
+/// Returns the number of ready fds, an interrupted wait counts as nothing being ready.
+pub fn poll(fds: &mut [libc::pollfd], timeout: libc::c_int) -> io::Result<usize> {
+    let result = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+    if result < 0 {
+        let error = io::Error::last_os_error();
+        return match error.kind() {
+            io::ErrorKind::Interrupted => Ok(0),
+            _ => Err(error),
+        };
+    }
+    Ok(result as usize)
+}
+
+/// Readiness of the fds after a call to `poll`
+pub fn readiness(fds: &[libc::pollfd]) -> impl Iterator<Item = Readiness> + '_ {
+    fds.iter()
+        .map(|pollfd| Readiness::from_poll_events(pollfd.revents))
+}
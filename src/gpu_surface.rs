@@ -1,10 +1,8 @@
-use glcore::GLCore;
-use glutin::config::{Api, GlConfig};
-use glutin::context::{
-    AsRawContext, ContextAttributesBuilder, NotCurrentContext, PossiblyCurrentContext,
-};
+use glcore::{GLCore, GLCoreError};
+use glutin::config::{Api, Config, GlConfig};
+use glutin::context::{ContextApi, ContextAttributesBuilder, PossiblyCurrentContext};
 use glutin::error::{Error as GlutError, ErrorKind as GlutErrorKind};
-use glutin::prelude::NotCurrentGlContext;
+use glutin::prelude::{NotCurrentGlContext, PossiblyCurrentGlContext};
 use glutin::surface::{GlSurface, Surface, SurfaceAttributesBuilder, WindowSurface};
 use glutin::{
     config::ConfigTemplateBuilder,
@@ -13,20 +11,105 @@ use glutin::{
 };
 use raw_window_handle::{HasDisplayHandle, RawWindowHandle, WaylandWindowHandle};
 // use speedy2d::GLRenderer;
+use std::cell::{RefCell, RefMut};
 use std::ffi::CString;
+use std::fmt::{self, Formatter};
 use std::num::NonZero;
+use std::rc::Rc;
 use std::{ffi::c_void, ptr::NonNull};
 use wayland_client::Proxy;
 use wayland_client::protocol::wl_display::WlDisplay;
 use wayland_client::protocol::wl_surface::WlSurface;
 
+use crate::opengl::batch::{Batch, FrameStats};
+use crate::opengl::image::TextureCache;
+use crate::opengl::text::GlyphAtlas;
+use crate::opengl::types::GlResult;
+
+/// Everything that can go wrong while rendering a frame, either in GL itself or in EGL
+#[derive(Debug)]
+pub enum RenderError {
+    Gl(GLCoreError),
+    Egl(GlutError),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Gl(err) => write!(f, "OpenGL error: {err:?}"),
+            RenderError::Egl(err) => write!(f, "EGL error: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl From<GLCoreError> for RenderError {
+    fn from(err: GLCoreError) -> Self {
+        RenderError::Gl(err)
+    }
+}
+
+impl From<GlutError> for RenderError {
+    fn from(err: GlutError) -> Self {
+        RenderError::Egl(err)
+    }
+}
+
+/// Which flavour of OpenGL the context provides
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GlApi {
+    /// OpenGL ES 3
+    Gles,
+    /// Desktop OpenGL 3.3
+    #[default]
+    Gl,
+}
+
+/// What the framebuffers of every surface must support, the EGL config is picked once from these
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GlOptions {
+    /// Bits of alpha per pixel, 0 makes every surface opaque
+    pub alpha_size: u8,
+    /// Samples per pixel for multisampling, `None` picks the most the driver offers
+    pub samples: Option<u8>,
+    pub srgb: bool,
+    pub depth_size: u8,
+    pub stencil_size: u8,
+    pub api: GlApi,
+}
+
+impl Default for GlOptions {
+    fn default() -> Self {
+        Self {
+            alpha_size: 8,
+            samples: None,
+            srgb: false,
+            depth_size: 0,
+            stencil_size: 0,
+            api: GlApi::default(),
+        }
+    }
+}
+
+/// The EGL display of the client and the one GL context every surface renders with
+///
+/// Sharing the context means the shaders and glyph atlas are compiled and filled once for all
+/// surfaces. As a single context only draws to one surface at a time, every render and swap has
+/// to make it current with its own surface first.
 #[derive(Debug, Clone)]
 pub struct GlAbstraction {
     display: Display,
+    /// Used for the context and every surface, they have to agree on it
+    config: Config,
+    options: GlOptions,
+    /// Created along with the first surface, as the context needs a surface to be made current
+    context: Rc<RefCell<Option<SharedContext>>>,
 }
 
 impl GlAbstraction {
-    pub fn new(wl_display: &WlDisplay) -> Result<Self, GlutError> {
+    /// Fails if the display has no config satisfying `options`
+    pub fn new(wl_display: &WlDisplay, options: GlOptions) -> Result<Self, GlutError> {
         let binding = wl_display.backend().upgrade().unwrap();
         let raw_display_handle = match binding.display_handle() {
             Ok(handle) => handle,
@@ -36,37 +119,55 @@ impl GlAbstraction {
         }
         .as_raw();
         let display = unsafe { Display::new(raw_display_handle, DisplayApiPreference::Egl) }?;
-        Ok(GlAbstraction { display })
+        let config = find_config(&display, &options)?;
+        Ok(GlAbstraction {
+            display,
+            config,
+            options,
+            context: Rc::new(RefCell::new(None)),
+        })
     }
 
     pub fn get_display(&self) -> &Display {
         &self.display
     }
 
-    pub fn create_context(&self, surface: &WlSurface) -> Result<NotCurrentContext, GlutError> {
-        let config_template = ConfigTemplateBuilder::new()
-            .with_buffer_type(glutin::config::ColorBufferType::Rgb {
-                r_size: 8,
-                g_size: 8,
-                b_size: 8,
+    /// Creates the shared context and makes it current with `surface`, if there is none yet
+    fn ensure_context(&self, surface: &Surface<WindowSurface>) -> Result<(), GlutError> {
+        let mut shared = self.context.borrow_mut();
+        if shared.is_none() {
+            let context_attrs = ContextAttributesBuilder::new()
+                .with_context_api(match self.options.api {
+                    GlApi::Gles => ContextApi::Gles(None),
+                    GlApi::Gl => ContextApi::OpenGl(None),
+                })
+                .build(None);
+            let context = unsafe { self.display.create_context(&self.config, &context_attrs) }?
+                .make_current(surface)?;
+
+            let renderer = GLCore::new(|fn_name| {
+                let c_str = CString::new(fn_name).expect("GL function name invalid C string");
+                self.display.get_proc_address(&c_str)
             })
-            .with_api(Api::GLES3)
-            .build();
-        let config = unsafe { self.display.find_configs(config_template) }?
-            .reduce(
-                |config, best| match config.num_samples() > best.num_samples() {
-                    true => config,
-                    false => best,
-                },
-            )
-            .ok_or(GlutError::from(GlutErrorKind::BadDisplay))?;
+            .map_err(|_| GlutError::from(GlutErrorKind::BadContext))?;
 
-        let surface_ptr = NonNull::new(surface.id().as_ptr() as *mut c_void)
-            .ok_or(GlutError::from(GlutErrorKind::BadDisplay))?;
-        let raw_window_handle = RawWindowHandle::Wayland(WaylandWindowHandle::new(surface_ptr));
+            *shared = Some(SharedContext {
+                glyph_atlas: None,
+                textures: None,
+                batch: None,
+                context,
+                renderer,
+            });
+        }
+        Ok(())
+    }
 
-        let context_attrs = ContextAttributesBuilder::new().build(Some(raw_window_handle));
-        unsafe { self.display.create_context(&config, &context_attrs) }
+    fn context(&self) -> RefMut<'_, SharedContext> {
+        RefMut::map(self.context.borrow_mut(), |shared| {
+            shared
+                .as_mut()
+                .expect("The context is created along with the first surface")
+        })
     }
 
     pub fn create_surface(
@@ -79,39 +180,76 @@ impl GlAbstraction {
             .ok_or(GlutError::from(GlutErrorKind::BadDisplay))?;
         let raw_window_handle = RawWindowHandle::Wayland(WaylandWindowHandle::new(surface_ptr));
 
-        let surface_attrs = SurfaceAttributesBuilder::<WindowSurface>::new().build(
-            raw_window_handle,
-            width,
-            height,
-        );
-
-        let config_template = ConfigTemplateBuilder::new()
-            .with_buffer_type(glutin::config::ColorBufferType::Rgb {
-                r_size: 8,
-                g_size: 8,
-                b_size: 8,
-            })
-            .with_api(Api::GLES3)
-            .build();
-        let config = unsafe { self.display.find_configs(config_template) }?
-            .reduce(
-                |config, best| match config.num_samples() > best.num_samples() {
-                    true => config,
-                    false => best,
-                },
-            )
-            .ok_or(GlutError::from(GlutErrorKind::BadDisplay))?;
-        unsafe { self.display.create_window_surface(&config, &surface_attrs) }
+        let surface_attrs = SurfaceAttributesBuilder::<WindowSurface>::new()
+            .with_srgb(Some(self.options.srgb))
+            .build(raw_window_handle, width, height);
+        unsafe {
+            self.display
+                .create_window_surface(&self.config, &surface_attrs)
+        }
     }
 }
 
+fn find_config(display: &Display, options: &GlOptions) -> Result<Config, GlutError> {
+    let mut config_template = ConfigTemplateBuilder::new()
+        .with_buffer_type(glutin::config::ColorBufferType::Rgb {
+            r_size: 8,
+            g_size: 8,
+            b_size: 8,
+        })
+        .with_alpha_size(options.alpha_size)
+        .with_depth_size(options.depth_size)
+        .with_stencil_size(options.stencil_size)
+        .with_api(match options.api {
+            GlApi::Gles => Api::GLES3,
+            GlApi::Gl => Api::OPENGL,
+        });
+    if let Some(samples) = options.samples.filter(|samples| *samples > 0) {
+        if !samples.is_power_of_two() {
+            return Err(GlutError::from(GlutErrorKind::BadConfig));
+        }
+        config_template = config_template.with_multisampling(samples);
+    }
+
+    let mut configs = unsafe { display.find_configs(config_template.build()) }?
+        .filter(|config| config.srgb_capable() || !options.srgb);
+    match options.samples {
+        Some(_) => configs.next(),
+        None => configs.max_by_key(|config| config.num_samples()),
+    }
+    .ok_or(GlutError::from(GlutErrorKind::BadConfig))
+}
+
+/// The context shared by every `GpuSurface`, along with what was loaded into it
 #[derive(Debug)]
-pub struct GpuSurface {
+struct SharedContext {
+    // Must be dropped before the context, these own objects of it
+    glyph_atlas: Option<Rc<RefCell<GlyphAtlas>>>,
+    textures: Option<Rc<RefCell<TextureCache>>>,
+    batch: Option<Rc<RefCell<Batch>>>,
     context: PossiblyCurrentContext,
-    surface: Surface<WindowSurface>,
     renderer: GLCore,
 }
 
+impl SharedContext {
+    /// Draws the vertices queued for the surface which is current
+    fn flush(&self) -> GlResult<()> {
+        match &self.batch {
+            Some(batch) => batch
+                .try_borrow_mut()
+                .map_err(|_| GLCoreError::InvalidOperation("Batch is already in use"))?
+                .flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct GpuSurface {
+    gl: GlAbstraction,
+    surface: Surface<WindowSurface>,
+}
+
 impl GpuSurface {
     pub fn new(
         abstraction: &GlAbstraction,
@@ -119,32 +257,85 @@ impl GpuSurface {
         width: NonZero<u32>,
         height: NonZero<u32>,
     ) -> Result<GpuSurface, GlutError> {
-        let not_context = abstraction.create_context(surface)?;
         let surface = abstraction.create_surface(surface, width, height)?;
-        let context = not_context.make_current(&surface)?;
-
-        let renderer = GLCore::new(|fn_name| {
-            let c_str = CString::new(fn_name).expect("GL function name invalid C string");
-            abstraction.display.get_proc_address(&c_str)
-        })
-        .map_err(|_| GlutError::from(GlutErrorKind::BadContext))?;
-
+        abstraction.ensure_context(&surface)?;
         Ok(GpuSurface {
-            context,
+            gl: abstraction.clone(),
             surface,
-            renderer,
         })
     }
 
     pub fn resize(&mut self, width: NonZero<u32>, height: NonZero<u32>) {
-        self.surface.resize(&self.context, width, height);
+        self.surface
+            .resize(&self.gl.context().context, width, height);
     }
 
-    pub fn swap_buffers(&mut self) -> Result<(), GlutError> {
-        self.surface.swap_buffers(&self.context)
+    /// Makes the shared context draw to this surface, GL calls go to whichever surface was made
+    /// current last
+    ///
+    /// Vertices still queued for the previous surface are drawn to it first.
+    pub fn make_current(&self) -> Result<(), RenderError> {
+        let shared = self.gl.context();
+        if !self.surface.is_current(&shared.context) {
+            shared.flush()?;
+        }
+        Ok(shared.context.make_current(&self.surface)?)
+    }
+
+    /// Presents the frame, making the context current with this surface first as EGL requires
+    ///
+    /// Returns the draw calls and vertices submitted since the previous frame of any surface.
+    pub fn swap_buffers(&mut self) -> Result<FrameStats, RenderError> {
+        self.make_current()?;
+        let shared = self.gl.context();
+        shared.flush()?;
+        self.surface.swap_buffers(&shared.context)?;
+        Ok(match &shared.batch {
+            Some(batch) => batch.borrow_mut().take_stats(),
+            None => FrameStats::default(),
+        })
     }
 
     pub fn get_renderer(&self) -> GLCore {
-        self.renderer
+        self.gl.context().renderer
+    }
+
+    /// Returns the batch of the shared context, creating it on first use
+    ///
+    /// Every surface draws through the same batch, `make_current` flushes it before switching.
+    pub fn get_batch(&mut self) -> GlResult<Rc<RefCell<Batch>>> {
+        let mut shared = self.gl.context();
+        match &shared.batch {
+            Some(batch) => Ok(batch.clone()),
+            None => {
+                let batch = Batch::new(shared.renderer, self.gl.options.api)?;
+                let batch = Rc::new(RefCell::new(batch));
+                shared.batch = Some(batch.clone());
+                Ok(batch)
+            }
+        }
+    }
+
+    /// Returns the glyph atlas of the shared context, creating it on first use
+    pub fn get_glyph_atlas(&mut self) -> GlResult<Rc<RefCell<GlyphAtlas>>> {
+        let mut shared = self.gl.context();
+        match &shared.glyph_atlas {
+            Some(atlas) => Ok(atlas.clone()),
+            None => {
+                let atlas = Rc::new(RefCell::new(GlyphAtlas::new(shared.renderer)?));
+                shared.glyph_atlas = Some(atlas.clone());
+                Ok(atlas)
+            }
+        }
+    }
+
+    /// Returns the texture cache of the shared context, creating it on first use
+    pub fn get_texture_cache(&mut self) -> Rc<RefCell<TextureCache>> {
+        let mut shared = self.gl.context();
+        let renderer = shared.renderer;
+        shared
+            .textures
+            .get_or_insert_with(|| Rc::new(RefCell::new(TextureCache::new(renderer))))
+            .clone()
     }
 }
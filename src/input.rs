@@ -0,0 +1,320 @@
+use wayland_backend::client::ObjectId;
+use wayland_client::{
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+    protocol::{
+        wl_pointer::{self, Axis, AxisSource, ButtonState, WlPointer},
+        wl_seat::{self, Capability, WlSeat},
+    },
+};
+
+use crate::{
+    keyboard::{Keyboard, KeyboardEvent},
+    state::WaylandState,
+};
+
+/// Highest `wl_seat` version we know how to handle
+pub const SEAT_VERSION: u32 = 8;
+
+/// Scroll motion along a single axis, accumulated over one pointer frame
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AxisMotion {
+    /// Length of the scroll vector in surface-local coordinates
+    pub value: f64,
+    /// Scroll distance in fractions of 120, where 120 is one wheel detent
+    pub value120: i32,
+    /// The scroll sequence on this axis ended (e.g. the fingers were lifted)
+    pub stop: bool,
+}
+
+impl AxisMotion {
+    /// Number of whole wheel steps
+    pub fn discrete(&self) -> i32 {
+        self.value120 / 120
+    }
+
+    fn is_empty(&self) -> bool {
+        self.value == 0.0 && self.value120 == 0 && !self.stop
+    }
+}
+
+/// Pointer events in surface-local coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PointerEvent {
+    Enter {
+        x: f64,
+        y: f64,
+    },
+    Leave,
+    Motion {
+        x: f64,
+        y: f64,
+    },
+    Button {
+        x: f64,
+        y: f64,
+        /// Linux evdev button code, e.g. `BTN_LEFT`
+        button: u32,
+        pressed: bool,
+    },
+    Axis {
+        x: f64,
+        y: f64,
+        horizontal: AxisMotion,
+        vertical: AxisMotion,
+        source: Option<AxisSource>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct PendingAxis {
+    horizontal: AxisMotion,
+    vertical: AxisMotion,
+    source: Option<AxisSource>,
+}
+
+impl PendingAxis {
+    fn get_mut(&mut self, axis: WEnum<Axis>) -> Option<&mut AxisMotion> {
+        match axis {
+            WEnum::Value(Axis::VerticalScroll) => Some(&mut self.vertical),
+            WEnum::Value(Axis::HorizontalScroll) => Some(&mut self.horizontal),
+            _ => None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.horizontal.is_empty() && self.vertical.is_empty()
+    }
+}
+
+#[derive(Debug)]
+pub struct Seat {
+    seat: WlSeat,
+    pointer: Option<WlPointer>,
+    keyboard: Option<Keyboard>,
+    /// The layer surface currently under the pointer
+    pointer_focus: Option<ObjectId>,
+    pointer_position: (f64, f64),
+    pending_axis: PendingAxis,
+}
+
+impl Seat {
+    pub fn new(seat: WlSeat) -> Seat {
+        Seat {
+            seat,
+            pointer: None,
+            keyboard: None,
+            pointer_focus: None,
+            pointer_position: (0.0, 0.0),
+            pending_axis: PendingAxis::default(),
+        }
+    }
+
+    pub fn get_pointer_focus(&self) -> Option<&ObjectId> {
+        self.pointer_focus.as_ref()
+    }
+
+    pub fn get_keyboard(&self) -> Option<&Keyboard> {
+        self.keyboard.as_ref()
+    }
+
+    pub fn get_keyboard_mut(&mut self) -> Option<&mut Keyboard> {
+        self.keyboard.as_mut()
+    }
+
+    /// Turns the axis events accumulated during the current frame into a single event
+    fn take_axis_event(&mut self) -> Option<PointerEvent> {
+        if self.pending_axis.is_empty() {
+            return None;
+        }
+        let pending = std::mem::take(&mut self.pending_axis);
+        let (x, y) = self.pointer_position;
+        Some(PointerEvent::Axis {
+            x,
+            y,
+            horizontal: pending.horizontal,
+            vertical: pending.vertical,
+            source: pending.source,
+        })
+    }
+
+    /// Forget about a surface which is about to be destroyed
+    pub fn unfocus(&mut self, id: &ObjectId) {
+        if self.pointer_focus.as_ref() == Some(id) {
+            self.pointer_focus = None;
+        }
+        if let Some(keyboard) = &mut self.keyboard {
+            keyboard.unfocus(id);
+        }
+    }
+
+    pub fn destroy(self) {
+        if let Some(pointer) = self.pointer
+            && pointer.version() >= 3
+        {
+            pointer.release();
+        }
+        if let Some(keyboard) = self.keyboard {
+            keyboard.destroy();
+        }
+        if self.seat.version() >= 5 {
+            self.seat.release();
+        }
+    }
+}
+
+impl Dispatch<WlSeat, u32> for WaylandState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlSeat,
+        event: wl_seat::Event,
+        global_name: &u32,
+        _conn: &Connection,
+        qhandle: &QueueHandle<Self>,
+    ) {
+        let Some(seat) = state.seats.get_mut(global_name) else {
+            return;
+        };
+
+        let wl_seat::Event::Capabilities {
+            capabilities: WEnum::Value(capabilities),
+        } = event
+        else {
+            return;
+        };
+
+        let has_pointer = capabilities.contains(Capability::Pointer);
+        let mut pointer_left = None;
+        if has_pointer && seat.pointer.is_none() {
+            seat.pointer = Some(proxy.get_pointer(qhandle, *global_name));
+        } else if !has_pointer && let Some(pointer) = seat.pointer.take() {
+            if pointer.version() >= 3 {
+                pointer.release();
+            }
+            seat.pending_axis = PendingAxis::default();
+            pointer_left = seat.pointer_focus.take();
+        }
+
+        let has_keyboard = capabilities.contains(Capability::Keyboard);
+        let mut keyboard_left = None;
+        if has_keyboard && seat.keyboard.is_none() {
+            seat.keyboard = Some(Keyboard::new(proxy.get_keyboard(qhandle, *global_name)));
+        } else if !has_keyboard && let Some(keyboard) = seat.keyboard.take() {
+            keyboard_left = keyboard.get_focus().cloned();
+            keyboard.destroy();
+        }
+
+        // The compositor won't send leave events for devices which are gone
+        if let Some(focus) = pointer_left {
+            state.notify_pointer(&focus, PointerEvent::Leave);
+        }
+        if let Some(focus) = keyboard_left {
+            state.notify_keyboard(&focus, KeyboardEvent::Leave);
+        }
+    }
+}
+
+impl Dispatch<WlPointer, u32> for WaylandState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlPointer,
+        event: wl_pointer::Event,
+        global_name: &u32,
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        let focus = match &event {
+            wl_pointer::Event::Enter { surface, .. } => state.find_surface_id(surface),
+            _ => state
+                .seats
+                .get(global_name)
+                .and_then(|seat| seat.pointer_focus.clone()),
+        };
+        let Some(seat) = state.seats.get_mut(global_name) else {
+            return;
+        };
+        let (x, y) = seat.pointer_position;
+
+        let pointer_event = match event {
+            wl_pointer::Event::Enter {
+                surface_x,
+                surface_y,
+                ..
+            } => {
+                seat.pointer_focus = focus.clone();
+                seat.pointer_position = (surface_x, surface_y);
+                Some(PointerEvent::Enter {
+                    x: surface_x,
+                    y: surface_y,
+                })
+            }
+            wl_pointer::Event::Leave { .. } => {
+                seat.pointer_focus = None;
+                seat.pending_axis = PendingAxis::default();
+                Some(PointerEvent::Leave)
+            }
+            wl_pointer::Event::Motion {
+                surface_x,
+                surface_y,
+                ..
+            } => {
+                seat.pointer_position = (surface_x, surface_y);
+                Some(PointerEvent::Motion {
+                    x: surface_x,
+                    y: surface_y,
+                })
+            }
+            wl_pointer::Event::Button {
+                button,
+                state: button_state,
+                ..
+            } => Some(PointerEvent::Button {
+                x,
+                y,
+                button,
+                pressed: matches!(button_state, WEnum::Value(ButtonState::Pressed)),
+            }),
+            wl_pointer::Event::Axis { axis, value, .. } => {
+                if let Some(motion) = seat.pending_axis.get_mut(axis) {
+                    motion.value += value;
+                }
+                // Before version 5 there are no frame events, every axis event stands on its own
+                match proxy.version() < 5 {
+                    true => seat.take_axis_event(),
+                    false => None,
+                }
+            }
+            wl_pointer::Event::AxisSource {
+                axis_source: WEnum::Value(source),
+            } => {
+                seat.pending_axis.source = Some(source);
+                None
+            }
+            wl_pointer::Event::AxisStop { axis, .. } => {
+                if let Some(motion) = seat.pending_axis.get_mut(axis) {
+                    motion.stop = true;
+                }
+                None
+            }
+            wl_pointer::Event::AxisDiscrete { axis, discrete } => {
+                if let Some(motion) = seat.pending_axis.get_mut(axis) {
+                    motion.value120 += discrete * 120;
+                }
+                None
+            }
+            wl_pointer::Event::AxisValue120 { axis, value120 } => {
+                if let Some(motion) = seat.pending_axis.get_mut(axis) {
+                    motion.value120 += value120;
+                }
+                None
+            }
+            wl_pointer::Event::Frame => seat.take_axis_event(),
+            _ => None,
+        };
+
+        if let Some(focus) = focus
+            && let Some(pointer_event) = pointer_event
+        {
+            state.notify_pointer(&focus, pointer_event);
+        }
+    }
+}
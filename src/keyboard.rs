@@ -0,0 +1,397 @@
+use std::{
+    ffi::{CStr, c_char},
+    fs::File,
+    os::{fd::OwnedFd, unix::fs::FileExt},
+    time::{Duration, Instant},
+};
+
+use wayland_backend::client::ObjectId;
+use wayland_client::{
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+    protocol::wl_keyboard::{self, KeyState, KeymapFormat, WlKeyboard},
+};
+use xkbcommon_dl::{
+    XKB_MOD_NAME_ALT, XKB_MOD_NAME_CAPS, XKB_MOD_NAME_CTRL, XKB_MOD_NAME_LOGO, XKB_MOD_NAME_NUM,
+    XKB_MOD_NAME_SHIFT, XkbCommon, xkb_context, xkb_context_flags, xkb_keymap,
+    xkb_keymap_compile_flags, xkb_keymap_format, xkb_state, xkb_state_component, xkbcommon_option,
+};
+
+use crate::state::WaylandState;
+
+/// Difference between evdev scancodes and xkb keycodes
+const XKB_KEYCODE_OFFSET: u32 = 8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub logo: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyEvent {
+    /// Linux evdev scancode, independent of the keymap
+    pub keycode: u32,
+    /// The keysym as translated by the keymap, `0` if no keymap could be loaded
+    pub keysym: u32,
+    /// Name of the keysym, e.g. `Return` or `a`
+    pub name: String,
+    /// Text produced by the key, control characters excluded
+    pub text: Option<String>,
+    pub pressed: bool,
+    /// The event was generated by key repeat instead of the compositor
+    pub repeat: bool,
+    pub modifiers: Modifiers,
+}
+
+/// Keyboard events for the layer surface holding the keyboard focus
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyboardEvent {
+    Enter,
+    Leave,
+    Key(KeyEvent),
+    Modifiers(Modifiers),
+}
+
+/// Key repeat settings, in keys per second and milliseconds before repeating starts
+#[derive(Debug, Clone, Copy)]
+struct RepeatInfo {
+    rate: i32,
+    delay: i32,
+}
+
+impl Default for RepeatInfo {
+    /// Used by compositors which don't send `repeat_info`
+    fn default() -> Self {
+        Self {
+            rate: 25,
+            delay: 600,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RepeatingKey {
+    keycode: u32,
+    next: Instant,
+}
+
+/// Keymap and modifier state of a keyboard, backed by a dynamically loaded libxkbcommon
+struct Xkb {
+    lib: &'static XkbCommon,
+    context: *mut xkb_context,
+    keymap: *mut xkb_keymap,
+    state: *mut xkb_state,
+}
+
+impl Xkb {
+    /// Compiles the keymap received from the compositor
+    ///
+    /// Returns `None` if libxkbcommon is not installed or the keymap cannot be compiled.
+    fn from_fd(fd: OwnedFd, size: u32) -> Option<Xkb> {
+        let lib = xkbcommon_option()?;
+
+        // The fd is shared with other clients, reading at an offset leaves its file offset alone
+        let mut source = vec![0; size as usize];
+        File::from(fd).read_exact_at(&mut source, 0).ok()?;
+        // The keymap should be null terminated already, but don't trust it
+        if source.last() != Some(&0) {
+            source.push(0);
+        }
+
+        unsafe {
+            let context = (lib.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                return None;
+            }
+            let keymap = (lib.xkb_keymap_new_from_string)(
+                context,
+                source.as_ptr() as *const c_char,
+                xkb_keymap_format::XKB_KEYMAP_FORMAT_TEXT_V1,
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            );
+            if keymap.is_null() {
+                (lib.xkb_context_unref)(context);
+                return None;
+            }
+            let state = (lib.xkb_state_new)(keymap);
+            if state.is_null() {
+                (lib.xkb_keymap_unref)(keymap);
+                (lib.xkb_context_unref)(context);
+                return None;
+            }
+
+            Some(Xkb {
+                lib,
+                context,
+                keymap,
+                state,
+            })
+        }
+    }
+
+    fn update_mask(&mut self, depressed: u32, latched: u32, locked: u32, group: u32) {
+        unsafe {
+            (self.lib.xkb_state_update_mask)(self.state, depressed, latched, locked, 0, 0, group);
+        }
+    }
+
+    fn is_active(&self, name: &[u8]) -> bool {
+        unsafe {
+            (self.lib.xkb_state_mod_name_is_active)(
+                self.state,
+                name.as_ptr() as *const c_char,
+                xkb_state_component::XKB_STATE_MODS_EFFECTIVE,
+            ) > 0
+        }
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        Modifiers {
+            shift: self.is_active(XKB_MOD_NAME_SHIFT),
+            ctrl: self.is_active(XKB_MOD_NAME_CTRL),
+            alt: self.is_active(XKB_MOD_NAME_ALT),
+            logo: self.is_active(XKB_MOD_NAME_LOGO),
+            caps_lock: self.is_active(XKB_MOD_NAME_CAPS),
+            num_lock: self.is_active(XKB_MOD_NAME_NUM),
+        }
+    }
+
+    fn keysym(&self, keycode: u32) -> u32 {
+        unsafe { (self.lib.xkb_state_key_get_one_sym)(self.state, keycode + XKB_KEYCODE_OFFSET) }
+    }
+
+    fn keysym_name(&self, keysym: u32) -> String {
+        let mut buffer = [0 as c_char; 64];
+        let written =
+            unsafe { (self.lib.xkb_keysym_get_name)(keysym, buffer.as_mut_ptr(), buffer.len()) };
+        if written < 0 {
+            return String::new();
+        }
+        unsafe { CStr::from_ptr(buffer.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn text(&self, keycode: u32) -> Option<String> {
+        let keycode = keycode + XKB_KEYCODE_OFFSET;
+        // The first call only measures the length, excluding the null terminator
+        let length = unsafe {
+            (self.lib.xkb_state_key_get_utf8)(self.state, keycode, std::ptr::null_mut(), 0)
+        };
+        if length <= 0 {
+            return None;
+        }
+
+        let mut buffer = vec![0 as c_char; length as usize + 1];
+        unsafe {
+            (self.lib.xkb_state_key_get_utf8)(
+                self.state,
+                keycode,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+            )
+        };
+        let text = unsafe { CStr::from_ptr(buffer.as_ptr()) }
+            .to_string_lossy()
+            .into_owned();
+        Some(text).filter(|text| !text.chars().any(char::is_control))
+    }
+
+    fn repeats(&self, keycode: u32) -> bool {
+        unsafe { (self.lib.xkb_keymap_key_repeats)(self.keymap, keycode + XKB_KEYCODE_OFFSET) > 0 }
+    }
+}
+
+impl Drop for Xkb {
+    fn drop(&mut self) {
+        unsafe {
+            (self.lib.xkb_state_unref)(self.state);
+            (self.lib.xkb_keymap_unref)(self.keymap);
+            (self.lib.xkb_context_unref)(self.context);
+        }
+    }
+}
+
+impl std::fmt::Debug for Xkb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Xkb").finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug)]
+pub struct Keyboard {
+    keyboard: WlKeyboard,
+    xkb: Option<Xkb>,
+    /// The layer surface currently holding the keyboard focus
+    focus: Option<ObjectId>,
+    modifiers: Modifiers,
+    repeat_info: RepeatInfo,
+    repeating: Option<RepeatingKey>,
+}
+
+impl Keyboard {
+    pub fn new(keyboard: WlKeyboard) -> Keyboard {
+        Keyboard {
+            keyboard,
+            xkb: None,
+            focus: None,
+            modifiers: Modifiers::default(),
+            repeat_info: RepeatInfo::default(),
+            repeating: None,
+        }
+    }
+
+    pub fn get_focus(&self) -> Option<&ObjectId> {
+        self.focus.as_ref()
+    }
+
+    /// Forget about a surface which is about to be destroyed
+    pub fn unfocus(&mut self, id: &ObjectId) {
+        if self.focus.as_ref() == Some(id) {
+            self.focus = None;
+            self.repeating = None;
+        }
+    }
+
+    fn key_event(&self, keycode: u32, pressed: bool, repeat: bool) -> KeyEvent {
+        let (keysym, name, text) = match &self.xkb {
+            Some(xkb) => {
+                let keysym = xkb.keysym(keycode);
+                let text = pressed.then(|| xkb.text(keycode)).flatten();
+                (keysym, xkb.keysym_name(keysym), text)
+            }
+            None => (0, String::new(), None),
+        };
+        KeyEvent {
+            keycode,
+            keysym,
+            name,
+            text,
+            pressed,
+            repeat,
+            modifiers: self.modifiers,
+        }
+    }
+
+    /// The moment the held down key generates its next repeated event
+    pub fn next_repeat(&self) -> Option<Instant> {
+        self.repeating.map(|repeating| repeating.next)
+    }
+
+    /// Generates the key event for a held down key if its repeat is due at `now`
+    ///
+    /// Repeats missed while the loop was busy are dropped rather than sent all at once, the next
+    /// one is a full interval after `now`.
+    pub fn take_repeat(&mut self, now: Instant) -> Option<KeyboardEvent> {
+        let repeating = self.repeating.as_mut()?;
+        if repeating.next > now {
+            return None;
+        }
+        let interval = Duration::from_millis(1000 / self.repeat_info.rate.max(1) as u64);
+        repeating.next = now + interval;
+        let keycode = repeating.keycode;
+        Some(KeyboardEvent::Key(self.key_event(keycode, true, true)))
+    }
+
+    pub fn destroy(self) {
+        if self.keyboard.version() >= 3 {
+            self.keyboard.release();
+        }
+    }
+}
+
+impl Dispatch<WlKeyboard, u32> for WaylandState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlKeyboard,
+        event: wl_keyboard::Event,
+        global_name: &u32,
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        let focus = match &event {
+            wl_keyboard::Event::Enter { surface, .. } => state.find_surface_id(surface),
+            _ => state
+                .seats
+                .get(global_name)
+                .and_then(|seat| seat.get_keyboard())
+                .and_then(|keyboard| keyboard.focus.clone()),
+        };
+        let Some(keyboard) = state
+            .seats
+            .get_mut(global_name)
+            .and_then(|seat| seat.get_keyboard_mut())
+        else {
+            return;
+        };
+
+        let keyboard_event = match event {
+            wl_keyboard::Event::Keymap { format, fd, size } => {
+                keyboard.xkb = match format {
+                    WEnum::Value(KeymapFormat::XkbV1) => Xkb::from_fd(fd, size),
+                    _ => None,
+                };
+                None
+            }
+            wl_keyboard::Event::Enter { .. } => {
+                keyboard.focus = focus.clone();
+                Some(KeyboardEvent::Enter)
+            }
+            wl_keyboard::Event::Leave { .. } => {
+                keyboard.focus = None;
+                keyboard.repeating = None;
+                Some(KeyboardEvent::Leave)
+            }
+            wl_keyboard::Event::Key {
+                key,
+                state: key_state,
+                ..
+            } => {
+                let pressed = matches!(key_state, WEnum::Value(KeyState::Pressed));
+                let repeats = keyboard.xkb.as_ref().is_some_and(|xkb| xkb.repeats(key));
+                if pressed && repeats && keyboard.repeat_info.rate > 0 {
+                    keyboard.repeating = Some(RepeatingKey {
+                        keycode: key,
+                        next: Instant::now()
+                            + Duration::from_millis(keyboard.repeat_info.delay.max(0) as u64),
+                    });
+                } else if keyboard
+                    .repeating
+                    .is_some_and(|repeating| repeating.keycode == key)
+                {
+                    keyboard.repeating = None;
+                }
+                Some(KeyboardEvent::Key(keyboard.key_event(key, pressed, false)))
+            }
+            wl_keyboard::Event::Modifiers {
+                mods_depressed,
+                mods_latched,
+                mods_locked,
+                group,
+                ..
+            } => keyboard.xkb.as_mut().map(|xkb| {
+                xkb.update_mask(mods_depressed, mods_latched, mods_locked, group);
+                keyboard.modifiers = xkb.modifiers();
+                KeyboardEvent::Modifiers(keyboard.modifiers)
+            }),
+            wl_keyboard::Event::RepeatInfo { rate, delay } => {
+                keyboard.repeat_info = RepeatInfo { rate, delay };
+                if rate <= 0 {
+                    keyboard.repeating = None;
+                }
+                None
+            }
+            _ => None,
+        };
+
+        if let Some(focus) = focus
+            && let Some(keyboard_event) = keyboard_event
+        {
+            state.notify_keyboard(&focus, keyboard_event);
+        }
+    }
+}
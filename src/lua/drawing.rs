@@ -0,0 +1,379 @@
+use std::{
+    cell::{Cell, RefCell},
+    path::PathBuf,
+    rc::Rc,
+};
+
+use glcore::GLCoreError;
+use mlua::{Error as LError, ExternalResult, FromLua, Lua, Result as LResult, Table, UserData};
+use wayland_backend::client::ObjectId;
+
+use crate::{
+    canvas::{BlendMode, Canvas, CanvasStack, Image},
+    opengl::{
+        highlevel::{ElementsMode, SimpleGL},
+        text::{FontCache, TextOptions},
+        types::{GlResult, Mat3, Vec2, Vec4},
+    },
+    software::SoftwareCanvas,
+    state::WaylandState,
+};
+
+pub fn into_lua_error(err: GLCoreError) -> LError {
+    LError::RuntimeError(format!("OpenGL error: {err:?}"))
+}
+
+/// What a `LuaDrawContext` draws with, decided by the `RenderTarget` of its surface
+enum DrawBackend {
+    Gl(RefCell<Box<SimpleGL>>),
+    /// Every call draws straight into the current `wl_shm` buffer of the surface, only the stack
+    /// lives on between calls
+    Software(RefCell<CanvasStack>),
+}
+
+/// The drawing context handed to the Lua callback of `surface:draw`
+///
+/// Only valid for the duration of the callback, the context is invalidated as soon as the
+/// callback returns and the buffers are swapped. Every method goes through the `Canvas` of the
+/// surface, so scripts draw the same on every backend.
+///
+/// Coordinates are logical pixels of the surface, with the origin in the top-left corner.
+pub struct LuaDrawContext {
+    backend: DrawBackend,
+    /// Set by `set_color`, used by every shape
+    color: Cell<Vec4>,
+    id: ObjectId,
+    state: Rc<RefCell<WaylandState>>,
+}
+
+impl LuaDrawContext {
+    pub fn new(gl: SimpleGL, id: ObjectId, state: Rc<RefCell<WaylandState>>) -> LuaDrawContext {
+        LuaDrawContext::with_backend(DrawBackend::Gl(RefCell::new(Box::new(gl))), id, state)
+    }
+
+    /// A context for a surface rendered in software, its pixel buffer must be prepared already
+    pub fn software(id: ObjectId, state: Rc<RefCell<WaylandState>>) -> LuaDrawContext {
+        let backend = DrawBackend::Software(RefCell::new(CanvasStack::default()));
+        LuaDrawContext::with_backend(backend, id, state)
+    }
+
+    fn with_backend(
+        backend: DrawBackend,
+        id: ObjectId,
+        state: Rc<RefCell<WaylandState>>,
+    ) -> LuaDrawContext {
+        LuaDrawContext {
+            backend,
+            color: Cell::new(Vec4::new(1.0, 1.0, 1.0, 1.0)),
+            id,
+            state,
+        }
+    }
+
+    /// Runs `draw` on the canvas of the surface, together with the loaded fonts
+    fn with_canvas<R>(
+        &self,
+        draw: impl FnOnce(&mut dyn Canvas, &mut FontCache) -> GlResult<R>,
+    ) -> LResult<R> {
+        let mut state = self.state.try_borrow_mut().into_lua_err()?;
+        let state = &mut *state;
+        match &self.backend {
+            DrawBackend::Gl(gl) => {
+                let mut gl = gl.try_borrow_mut().into_lua_err()?;
+                draw(&mut **gl, &mut state.fonts)
+            }
+            DrawBackend::Software(stack) => {
+                let mut stack = stack.try_borrow_mut().into_lua_err()?;
+                let (pixmap, glyphs, images) = state
+                    .surface_links
+                    .get_mut(&self.id)
+                    .ok_or(LError::MemoryError(
+                        "Surface reference invalid, the surface has been closed".into(),
+                    ))?
+                    .get_pixmap()
+                    .ok_or(LError::RuntimeError(
+                        "The surface is not rendered in software".into(),
+                    ))?;
+                draw(
+                    &mut SoftwareCanvas::new(pixmap, glyphs, images, &mut stack),
+                    &mut state.fonts,
+                )
+            }
+        }
+        .map_err(into_lua_error)
+    }
+
+    fn clear(_: &Lua, context: &Self, (r, g, b, a): (f32, f32, f32, Option<f32>)) -> LResult<()> {
+        let color = Vec4::new(r, g, b, a.unwrap_or(1.0));
+        context.with_canvas(|canvas, _| canvas.clear(color))
+    }
+
+    /// Changes how shapes and text are combined with what was drawn before, `"normal"` at the
+    /// start of every draw
+    fn set_blend_mode(_: &Lua, context: &Self, mode: BlendMode) -> LResult<()> {
+        context.with_canvas(|canvas, _| canvas.set_blend_mode(mode))
+    }
+
+    fn set_color(
+        _: &Lua,
+        context: &Self,
+        (r, g, b, a): (f32, f32, f32, Option<f32>),
+    ) -> LResult<()> {
+        context.color.set(Vec4::new(r, g, b, a.unwrap_or(1.0)));
+        Ok(())
+    }
+
+    fn draw_rectangle(_: &Lua, context: &Self, (x, y, w, h): (f32, f32, f32, f32)) -> LResult<()> {
+        let color = context.color.get();
+        context.with_canvas(|canvas, _| canvas.fill_rect(Vec2::new(x, y), Vec2::new(w, h), color))
+    }
+
+    fn draw_polygon(
+        _: &Lua,
+        context: &Self,
+        (mode, points): (ElementsMode, Vec<Vec2>),
+    ) -> LResult<()> {
+        let color = context.color.get();
+        context.with_canvas(|canvas, _| canvas.fill_polygon(mode, &points, color))
+    }
+
+    /// Draws lines through `points`, back to the first point if `closed`
+    fn stroke_path(
+        _: &Lua,
+        context: &Self,
+        (points, closed): (Vec<Vec2>, Option<bool>),
+    ) -> LResult<()> {
+        let color = context.color.get();
+        context.with_canvas(|canvas, _| canvas.stroke_path(&points, closed.unwrap_or(false), color))
+    }
+
+    fn text(
+        _: &Lua,
+        context: &Self,
+        (text, x, y, options): (String, f32, f32, LuaTextOptions),
+    ) -> LResult<()> {
+        context.with_canvas(|canvas, fonts| {
+            let font = fonts.get_or_load(&options.font)?;
+            canvas.draw_text(font, &text, Vec2::new(x, y), &options.text)
+        })
+    }
+
+    /// Returns the width and height of `text` in logical pixels
+    fn measure_text(
+        _: &Lua,
+        context: &Self,
+        (text, options): (String, LuaTextOptions),
+    ) -> LResult<(f32, f32)> {
+        let mut state = context.state.try_borrow_mut().into_lua_err()?;
+        let size = state
+            .fonts
+            .get_or_load(&options.font)
+            .map_err(into_lua_error)?
+            .measure(&text, &options.text);
+        Ok((size.x, size.y))
+    }
+
+    /// Draws raw RGBA `pixels` of `width` by `height` scaled into the rectangle at `x`, `y`
+    fn draw_pixels(
+        _: &Lua,
+        context: &Self,
+        (pixels, width, height, x, y, w, h): (mlua::String, usize, usize, f32, f32, f32, f32),
+    ) -> LResult<()> {
+        let pixels = pixels.as_bytes();
+        let image = Image {
+            pixels: &pixels,
+            width,
+            height,
+        };
+        context.with_canvas(|canvas, _| canvas.draw_image(image, Vec2::new(x, y), Vec2::new(w, h)))
+    }
+
+    /// Draws the PNG, JPEG or SVG at `path` scaled into the rectangle at `x`, `y`, its colors
+    /// multiplied by the optional `tint`
+    fn image(
+        _: &Lua,
+        context: &Self,
+        (path, x, y, w, h, tint): (PathBuf, f32, f32, f32, f32, Option<Vec4>),
+    ) -> LResult<()> {
+        let tint = tint.unwrap_or(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        context.with_canvas(|canvas, _| {
+            canvas.draw_image_file(&path, Vec2::new(x, y), Vec2::new(w, h), tint)
+        })
+    }
+
+    /// Restricts drawing to the rectangle with its top-left corner at `x`, `y` until the matching
+    /// `pop_clip`
+    fn push_clip(_: &Lua, context: &Self, (x, y, w, h): (f32, f32, f32, f32)) -> LResult<()> {
+        context.with_canvas(|canvas, _| canvas.push_clip(Vec2::new(x, y), Vec2::new(w, h)))
+    }
+
+    fn pop_clip(_: &Lua, context: &Self, _: ()) -> LResult<()> {
+        context.with_canvas(|canvas, _| canvas.pop_clip())
+    }
+
+    /// Moves everything drawn until the matching `pop_transform` by `dx`, `dy` after scaling it
+    /// by `sx`, `sy`
+    fn push_transform(
+        _: &Lua,
+        context: &Self,
+        (dx, dy, sx, sy): (f32, f32, Option<f32>, Option<f32>),
+    ) -> LResult<()> {
+        let transform = Mat3::translate(Vec2::new(dx, dy))
+            * Mat3::scale(Vec2::new(sx.unwrap_or(1.0), sy.or(sx).unwrap_or(1.0)));
+        context.with_canvas(|canvas, _| {
+            canvas.push_transform(transform);
+            Ok(())
+        })
+    }
+
+    /// Rotates everything drawn until the matching `pop_transform` clockwise by `angle` radians
+    /// around `x`, `y`
+    fn push_rotation(
+        _: &Lua,
+        context: &Self,
+        (angle, x, y): (f32, Option<f32>, Option<f32>),
+    ) -> LResult<()> {
+        let center = Vec2::new(x.unwrap_or(0.0), y.unwrap_or(0.0));
+        let transform =
+            Mat3::translate(center) * Mat3::rotate(angle) * Mat3::translate(Vec2::zero() - center);
+        context.with_canvas(|canvas, _| {
+            canvas.push_transform(transform);
+            Ok(())
+        })
+    }
+
+    fn pop_transform(_: &Lua, context: &Self, _: ()) -> LResult<()> {
+        context.with_canvas(|canvas, _| {
+            canvas.pop_transform();
+            Ok(())
+        })
+    }
+}
+
+impl UserData for LuaDrawContext {
+    fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("clear", LuaDrawContext::clear);
+        methods.add_method("set_color", LuaDrawContext::set_color);
+        methods.add_method("set_blend_mode", LuaDrawContext::set_blend_mode);
+        methods.add_method("draw_rectangle", LuaDrawContext::draw_rectangle);
+        methods.add_method("draw_polygon", LuaDrawContext::draw_polygon);
+        methods.add_method("stroke_path", LuaDrawContext::stroke_path);
+        methods.add_method("text", LuaDrawContext::text);
+        methods.add_method("measure_text", LuaDrawContext::measure_text);
+        methods.add_method("draw_pixels", LuaDrawContext::draw_pixels);
+        methods.add_method("image", LuaDrawContext::image);
+        methods.add_method("push_clip", LuaDrawContext::push_clip);
+        methods.add_method("pop_clip", LuaDrawContext::pop_clip);
+        methods.add_method("push_transform", LuaDrawContext::push_transform);
+        methods.add_method("push_rotation", LuaDrawContext::push_rotation);
+        methods.add_method("pop_transform", LuaDrawContext::pop_transform);
+    }
+}
+
+/// Text options as passed from Lua, `font` is a path to a font file
+pub struct LuaTextOptions {
+    font: PathBuf,
+    text: TextOptions,
+}
+
+impl FromLua for LuaTextOptions {
+    fn from_lua(value: mlua::Value, _lua: &mlua::Lua) -> mlua::Result<Self> {
+        let table: &Table = value.as_table().ok_or(LError::FromLuaConversionError {
+            from: value.type_name(),
+            to: "{ font = <path>, size = <number>?, color = <color>?, line_height = <number>? }"
+                .into(),
+            message: None,
+        })?;
+        let defaults = TextOptions::default();
+        Ok(LuaTextOptions {
+            font: table.get::<String>("font")?.into(),
+            text: TextOptions {
+                size: table.get::<Option<f32>>("size")?.unwrap_or(defaults.size),
+                color: table
+                    .get::<Option<Vec4>>("color")?
+                    .unwrap_or(defaults.color),
+                line_height: table
+                    .get::<Option<f32>>("line_height")?
+                    .unwrap_or(defaults.line_height),
+            },
+        })
+    }
+}
+
+impl FromLua for ElementsMode {
+    fn from_lua(value: mlua::Value, _lua: &mlua::Lua) -> mlua::Result<Self> {
+        let conversion_error = |message: Option<String>| LError::FromLuaConversionError {
+            from: value.type_name(),
+            to: "\"points\" | \"lines\" | \"line_loop\" | \"triangles\" | ...".into(),
+            message,
+        };
+        let mode = value
+            .as_string()
+            .ok_or_else(|| conversion_error(None))?
+            .to_str()?;
+        match &*mode {
+            "points" => Ok(ElementsMode::Points),
+            "line_strip" => Ok(ElementsMode::LineStrip),
+            "line_loop" => Ok(ElementsMode::LineLoop),
+            "lines" => Ok(ElementsMode::Lines),
+            "line_strip_adjacency" => Ok(ElementsMode::LineStripAdjacency),
+            "lines_adjacency" => Ok(ElementsMode::LinesAdjacency),
+            "triangle_strip" => Ok(ElementsMode::TriangleStrip),
+            "triangle_fan" => Ok(ElementsMode::TriangleFan),
+            "triangles" => Ok(ElementsMode::Triangles),
+            "triangle_strip_adjacency" => Ok(ElementsMode::TriangleStripAdjacency),
+            "triangles_adjacency" => Ok(ElementsMode::TrianglesAdjacency),
+            other => Err(conversion_error(Some(format!("unknown mode '{other}'")))),
+        }
+    }
+}
+
+impl FromLua for BlendMode {
+    fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        let name = String::from_lua(value, lua)?;
+        match name.as_str() {
+            "replace" => Ok(BlendMode::Replace),
+            "normal" => Ok(BlendMode::Normal),
+            "additive" => Ok(BlendMode::Additive),
+            "premultiplied" => Ok(BlendMode::Premultiplied),
+            _ => Err(LError::FromLuaConversionError {
+                from: "string",
+                to: "BlendMode".into(),
+                message: Some(format!(
+                    "expected \"replace\", \"normal\", \"additive\" or \"premultiplied\", \
+                     got \"{name}\""
+                )),
+            }),
+        }
+    }
+}
+
+impl FromLua for Vec2 {
+    fn from_lua(value: mlua::Value, _lua: &mlua::Lua) -> mlua::Result<Self> {
+        let table: &Table = value.as_table().ok_or(LError::FromLuaConversionError {
+            from: value.type_name(),
+            to: "{ <number>, <number> } | { x = <number>, y = <number> }".into(),
+            message: None,
+        })?;
+        match table.contains_key("x")? {
+            true => Ok(Vec2::new(table.get("x")?, table.get("y")?)),
+            false => Ok(Vec2::new(table.get(1)?, table.get(2)?)),
+        }
+    }
+}
+
+impl FromLua for Vec4 {
+    fn from_lua(value: mlua::Value, _lua: &mlua::Lua) -> mlua::Result<Self> {
+        let table: &Table = value.as_table().ok_or(LError::FromLuaConversionError {
+            from: value.type_name(),
+            to: "{ <r>, <g>, <b>, <a>? }".into(),
+            message: None,
+        })?;
+        Ok(Vec4::new(
+            table.get(1)?,
+            table.get(2)?,
+            table.get(3)?,
+            table.get::<Option<f32>>(4)?.unwrap_or(1.0),
+        ))
+    }
+}
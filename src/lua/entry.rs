@@ -1,29 +1,62 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    io::ErrorKind,
+    os::fd::AsRawFd,
+    rc::Rc,
+    time::Instant,
+};
 
-use glcore::GLCoreError;
 use mlua::{
-    Error as LError, ExternalResult, Function, IntoLua, Lua, Result as LResult, Table, UserData,
-    UserDataMethods,
+    Error as LError, ExternalResult, FromLua, Function, IntoLua, Lua, Result as LResult, Table,
+    UserData, UserDataFields, UserDataMethods,
 };
-use wayland_backend::client::ObjectId;
+use wayland_backend::client::WaylandError;
 use wayland_client::{
-    Connection, DispatchError, EventQueue, Proxy, QueueHandle, protocol::wl_display::WlDisplay,
+    Connection, EventQueue, Proxy, QueueHandle,
+    protocol::{wl_display::WlDisplay, wl_output::Transform},
+};
+
+use super::{
+    event_loop::{self, get_event_loop},
+    process,
+    rendering::{LuaSurfaceOptions, LuaSurfaceReference},
+};
+use crate::{
+    event_loop::{EventLoop, Readiness, poll, poll_timeout, readiness},
+    gpu_surface::{GlApi, GlOptions},
+    output::OutputInfo,
+    state::{RenderBackend, WaylandState},
 };
-use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::Layer;
 
-use super::rendering::LuaSurfaceReference;
-use crate::{opengl::types::GlResult, state::WaylandState};
+/// Lua callbacks triggered by Wayland events are queued here and ran once the state is no longer
+/// borrowed, so they are free to call back into the client and surfaces
+pub type DeferredCalls = Rc<RefCell<VecDeque<Box<dyn FnOnce()>>>>;
 
 struct WaylandClient {
     connection: Connection,
     display: WlDisplay,
-    event_queue: EventQueue<WaylandState>,
+    event_queue: RefCell<EventQueue<WaylandState>>,
     queue_handle: QueueHandle<WaylandState>,
     state: Rc<RefCell<WaylandState>>,
+    deferred: DeferredCalls,
+    stopped: Cell<bool>,
 }
 
 impl WaylandClient {
-    fn init(_: &Lua, _: ()) -> LResult<WaylandClient> {
+    /// `dwr.create_client(options)`, `options.backend` picks the `RenderBackend` and `options.gl`
+    /// the `GlOptions`
+    fn init(_: &Lua, options: Option<Table>) -> LResult<WaylandClient> {
+        let (backend, gl_options) = match options {
+            Some(options) => (
+                options
+                    .get::<Option<RenderBackend>>("backend")?
+                    .unwrap_or_default(),
+                options.get::<Option<GlOptions>>("gl")?.unwrap_or_default(),
+            ),
+            None => (RenderBackend::default(), GlOptions::default()),
+        };
+
         let connection = Connection::connect_to_env().into_lua_err()?;
 
         let display = connection.display();
@@ -32,117 +65,379 @@ impl WaylandClient {
 
         display.get_registry(&queue_handle, ());
 
-        let mut state = WaylandState::new(&display);
+        let mut state = WaylandState::new(&display, backend, gl_options).into_lua_err()?;
+        // The first roundtrip binds the globals, the second receives their initial state
+        event_queue.roundtrip(&mut state).into_lua_err()?;
         event_queue.roundtrip(&mut state).into_lua_err()?;
 
         Ok(WaylandClient {
             connection,
             display,
-            event_queue,
+            event_queue: event_queue.into(),
             queue_handle,
             state: Rc::new(state.into()),
+            deferred: Rc::default(),
+            stopped: Cell::new(false),
         })
     }
 
-    fn is_alive(_: &Lua, client: &Self, _: ()) -> LResult<bool> {
-        Ok(client.display.is_alive())
+    fn run_deferred(&self) {
+        // Callbacks may queue new calls, so the queue must not stay borrowed while calling
+        loop {
+            let call = self.deferred.borrow_mut().pop_front();
+            match call {
+                Some(call) => call(),
+                None => break,
+            }
+        }
     }
 
-    fn render_test(state: &mut WaylandState, surface_id: &ObjectId) {
-        let surface = state.surface_links.get_mut(surface_id).unwrap();
-        let _ = surface.render(|graphics| {
-            let gl = crate::opengl::highlevel::SimpleGL::new(graphics);
-            let shader_program = gl
-                // .new_builtin_shader(builtin::FlatColor)?
-                .new_builtin_shader(crate::opengl::shaders::builtin::QuadColor)?
-                .use_program()?;
-
-            let gl = gl.with_shader(shader_program);
-            gl.clear(0.2, 0.1, 0.0, 1.0)?;
-
-            shader_program.set_color(crate::opengl::types::Vec4::new(0.0, 0.0, 1.0, 1.0))?;
-            gl.draw_polygon(
-                crate::opengl::highlevel::ElementsMode::LineLoop,
-                crate::opengl::types::OwnedVec2Array::new(vec![
-                    crate::opengl::types::Vec2::new(-0.5, 0.5),
-                    crate::opengl::types::Vec2::new(0.5, 0.5),
-                    crate::opengl::types::Vec2::new(0.5, -0.5),
-                ]),
-            )?;
-
-            shader_program.set_color(crate::opengl::types::Vec4::new(0.0, 1.0, 0.5, 1.0))?;
-            gl.draw_polygon(
-                crate::opengl::highlevel::ElementsMode::LineLoop,
-                crate::opengl::types::OwnedVec2Array::new(vec![
-                    crate::opengl::types::Vec2::new(-0.5, 0.5),
-                    crate::opengl::types::Vec2::new(-0.5, -0.5),
-                    crate::opengl::types::Vec2::new(0.5, -0.5),
-                ]),
-            )?;
-
-            shader_program.set_color(crate::opengl::types::Vec4::new(1.0, 0.0, 0.5, 1.0))?;
-            gl.draw_rectangle(
-                crate::opengl::types::Vec2::new(-0.2, -0.2),
-                crate::opengl::types::Vec2::new(0.4, 0.4),
-            )?;
-            shader_program.set_color(crate::opengl::types::Vec4::new(1.0, 0.0, 0.5, 1.0))?;
-            gl.draw_rectangle(
-                crate::opengl::types::Vec2::new(-0.8, -0.8),
-                crate::opengl::types::Vec2::new(0.4, 0.4),
-            )?;
-
-            Ok(())
-        });
-        let _ = surface.swap_buffers();
+    fn is_alive(_: &Lua, client: &Self, _: ()) -> LResult<bool> {
+        Ok(client.display.is_alive())
     }
 
+    /// `client:create_surface(options, callback)`, calls `callback(surface)` once the compositor
+    /// configured the surface
+    ///
+    /// See `LuaSurfaceOptions` for the options, which are all applied before the surface is
+    /// first committed.
     fn create_surface(
         _: &Lua,
-        client: &mut Self,
-        (w, h, callback): (u32, u32, Function),
+        client: &Self,
+        (options, callback): (LuaSurfaceOptions, Function),
     ) -> LResult<()> {
-        let surface_id = client
-            .state
-            .borrow_mut()
-            .create_surface_async(w, h, Layer::Top, &mut client.event_queue)
-            .unwrap_or(ObjectId::null());
+        let mut state = client.state.try_borrow_mut().into_lua_err()?;
+        let mut event_queue = client.event_queue.try_borrow_mut().into_lua_err()?;
+
+        let output = match options.output {
+            Some(name) => Some(
+                state
+                    .find_output(&name)
+                    .ok_or_else(|| LError::RuntimeError(format!("No output named '{name}'")))?
+                    .get_output()
+                    .clone(),
+            ),
+            None => None,
+        };
+        let surface_id = state
+            .create_surface_async(options.options, output.as_ref(), &mut event_queue)
+            // The callback would wait forever for a surface which doesn't exist
+            .ok_or_else(|| {
+                LError::RuntimeError("The compositor or layer shell globals are not bound".into())
+            })?;
 
         let rc_state = client.state.clone();
-        client.state.borrow_mut().surface_creation_callback.insert(
+        let deferred = client.deferred.clone();
+        state.surface_creation_callback.insert(
             surface_id,
-            Box::new(move |state, surface_id| {
-                WaylandClient::render_test(state, &surface_id);
-
-                let reference = LuaSurfaceReference::new(surface_id, rc_state);
-                let _ = callback.call::<()>(reference);
+            Box::new(move |_, surface_id| {
+                let reference = LuaSurfaceReference::new(surface_id, rc_state, deferred.clone());
+                deferred.borrow_mut().push_back(Box::new(move || {
+                    let _ = callback.call::<()>(reference);
+                }));
             }),
         );
 
         Ok(())
     }
 
-    fn render(_: &Lua, client: &mut Self, _: ()) -> LResult<()> {
-        let mut state = client.state.borrow_mut();
-        state
-            .handle_events(&mut client.event_queue)
-            .into_lua_err()?;
-        std::thread::sleep(std::time::Duration::from_millis(16));
+    /// Returns every announced output, ordered by the time they were advertised
+    fn outputs(_: &Lua, client: &Self, _: ()) -> LResult<Vec<OutputInfo>> {
+        let state = client.state.try_borrow().into_lua_err()?;
+        let mut outputs: Vec<_> = state
+            .outputs
+            .iter()
+            .filter(|(_, output)| output.is_ready())
+            .collect();
+        outputs.sort_by_key(|(global_name, _)| **global_name);
+        Ok(outputs
+            .into_iter()
+            .map(|(_, output)| output.get_info().clone())
+            .collect())
+    }
 
+    fn on_output_added(_: &Lua, client: &Self, callback: Function) -> LResult<()> {
+        let mut state = client.state.try_borrow_mut().into_lua_err()?;
+        let deferred = client.deferred.clone();
+        state.output_added_callback = Some(Box::new(move |_, info| {
+            let info = info.clone();
+            let callback = callback.clone();
+            deferred.borrow_mut().push_back(Box::new(move || {
+                let _ = callback.call::<()>(info);
+            }));
+        }));
         Ok(())
     }
+
+    fn on_output_removed(_: &Lua, client: &Self, callback: Function) -> LResult<()> {
+        let mut state = client.state.try_borrow_mut().into_lua_err()?;
+        let deferred = client.deferred.clone();
+        state.output_removed_callback = Some(Box::new(move |_, info| {
+            let info = info.clone();
+            let callback = callback.clone();
+            deferred.borrow_mut().push_back(Box::new(move || {
+                let _ = callback.call::<()>(info);
+            }));
+        }));
+        Ok(())
+    }
+
+    /// Runs a single iteration of the event loop
+    ///
+    /// Draws the surfaces which are due, then sleeps until a Wayland event arrives, a watched fd
+    /// becomes ready or a timer expires, and dispatches whatever woke it up. An idle client doesn't
+    /// wake up at all.
+    fn dispatch(&self, event_loop: &RefCell<EventLoop>) -> LResult<()> {
+        // Redraws requested since the last iteration must not wait for an unrelated event
+        {
+            let mut state = self.state.try_borrow_mut().into_lua_err()?;
+            state.dispatch_redraws();
+        }
+        self.run_deferred();
+
+        let (guard, timeout) = {
+            let mut state = self.state.try_borrow_mut().into_lua_err()?;
+            let mut event_queue = self.event_queue.try_borrow_mut().into_lua_err()?;
+            event_queue.flush().into_lua_err()?;
+
+            let Some(guard) = event_queue.prepare_read() else {
+                // Events were queued while dispatching, handle those before sleeping
+                state.dispatch_pending(&mut event_queue).into_lua_err()?;
+                drop((state, event_queue));
+                self.run_deferred();
+                return Ok(());
+            };
+
+            let now = Instant::now();
+            let event_loop = event_loop.try_borrow().into_lua_err()?;
+            let timeout = match event_loop.has_idle() {
+                true => 0,
+                false => {
+                    let deadline = [event_loop.next_deadline(), state.next_key_repeat()]
+                        .into_iter()
+                        .flatten()
+                        .min();
+                    poll_timeout(deadline, now)
+                }
+            };
+            (guard, timeout)
+        };
+
+        let (watcher_ids, watcher_fds) = event_loop.try_borrow().into_lua_err()?.poll_fds();
+        let mut fds = vec![libc::pollfd {
+            fd: guard.connection_fd().as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        }];
+        fds.extend(watcher_fds);
+        let ready_count = poll(&mut fds, timeout).into_lua_err()?;
+
+        if fds[0].revents != 0 {
+            match guard.read() {
+                Err(WaylandError::Io(error)) if error.kind() == ErrorKind::WouldBlock => {}
+                result => {
+                    result.into_lua_err()?;
+                }
+            }
+        } else {
+            drop(guard);
+        }
+
+        {
+            let mut state = self.state.try_borrow_mut().into_lua_err()?;
+            let mut event_queue = self.event_queue.try_borrow_mut().into_lua_err()?;
+            state.dispatch_pending(&mut event_queue).into_lua_err()?;
+        }
+        self.run_deferred();
+
+        let timers_fired = EventLoop::dispatch_timers(event_loop, Instant::now());
+        let ready: Vec<_> = watcher_ids
+            .into_iter()
+            .zip(readiness(&fds[1..]))
+            .filter(|(_, readiness)| *readiness != Readiness::default())
+            .collect();
+        EventLoop::dispatch_watchers(event_loop, &ready);
+
+        if ready_count == 0 && !timers_fired {
+            EventLoop::dispatch_idle(event_loop);
+        }
+
+        Ok(())
+    }
+
+    /// Runs the event loop until the connection is lost or `stop` is called
+    fn run(lua: &Lua, client: &Self, _: ()) -> LResult<()> {
+        let event_loop = get_event_loop(lua);
+        client.stopped.set(false);
+        while client.display.is_alive() && !client.stopped.get() {
+            client.dispatch(&event_loop)?;
+        }
+        Ok(())
+    }
+
+    /// Makes `run` return after the current iteration
+    fn stop(_: &Lua, client: &Self, _: ()) -> LResult<()> {
+        client.stopped.set(true);
+        Ok(())
+    }
+
+    /// Runs a single iteration of the event loop, for scripts which drive the loop themselves
+    fn render(lua: &Lua, client: &Self, _: ()) -> LResult<()> {
+        client.dispatch(&get_event_loop(lua))
+    }
 }
 
+/// `client:await_surface(options)`, `create_surface` for coroutines, which returns the surface
+/// once it is ready instead of calling back
+///
+/// Yielding is done in Lua, as Rust functions can't yield across the C boundary.
+const AWAIT_SURFACE: &str = r#"
+return function(client, options)
+    local thread, is_main = coroutine.running()
+    if thread == nil or is_main then
+        error("await_surface must be called from within a coroutine", 2)
+    end
+    client:create_surface(options, function(surface)
+        local ok, err = coroutine.resume(thread, surface)
+        if not ok then
+            error(err)
+        end
+    end)
+    return coroutine.yield()
+end
+"#;
+
+const AWAIT_SURFACE_KEY: &str = "dwr.await_surface";
+
 impl UserData for WaylandClient {
+    fn add_fields<F: UserDataFields<Self>>(fields: &mut F) {
+        // A field holding a Lua function still reads as a method call, `client:await_surface()`
+        fields.add_field_function_get("await_surface", |lua, _| {
+            lua.named_registry_value::<Function>(AWAIT_SURFACE_KEY)
+        });
+    }
+
     fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method("is_alive", WaylandClient::is_alive);
-        methods.add_method_mut("create_surface", WaylandClient::create_surface);
-        methods.add_method_mut("render", WaylandClient::render);
+        methods.add_method("create_surface", WaylandClient::create_surface);
+        methods.add_method("outputs", WaylandClient::outputs);
+        methods.add_method("on_output_added", WaylandClient::on_output_added);
+        methods.add_method("on_output_removed", WaylandClient::on_output_removed);
+        methods.add_method("run", WaylandClient::run);
+        methods.add_method("stop", WaylandClient::stop);
+        methods.add_method("render", WaylandClient::render);
+    }
+}
+
+impl FromLua for RenderBackend {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> LResult<Self> {
+        let name = String::from_lua(value, lua)?;
+        match name.as_str() {
+            "auto" => Ok(RenderBackend::Auto),
+            "gl" => Ok(RenderBackend::Gl),
+            "software" => Ok(RenderBackend::Software),
+            _ => Err(LError::FromLuaConversionError {
+                from: "string",
+                to: "RenderBackend".into(),
+                message: Some(format!(
+                    "expected \"auto\", \"gl\" or \"software\", got \"{name}\""
+                )),
+            }),
+        }
+    }
+}
+
+/// `{ alpha = 8, samples = 4, srgb = false, depth = 0, stencil = 0, api = "gl" }`, every key
+/// is optional
+impl FromLua for GlOptions {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> LResult<Self> {
+        let table = Table::from_lua(value, lua)?;
+        let defaults = GlOptions::default();
+        let api = match table.get::<Option<String>>("api")?.as_deref() {
+            None => defaults.api,
+            Some("gl") => GlApi::Gl,
+            Some("gles") => GlApi::Gles,
+            Some(name) => {
+                return Err(LError::FromLuaConversionError {
+                    from: "string",
+                    to: "GlApi".into(),
+                    message: Some(format!("expected \"gl\" or \"gles\", got \"{name}\"")),
+                });
+            }
+        };
+        Ok(GlOptions {
+            alpha_size: table
+                .get::<Option<u8>>("alpha")?
+                .unwrap_or(defaults.alpha_size),
+            samples: table.get::<Option<u8>>("samples")?,
+            srgb: table.get::<Option<bool>>("srgb")?.unwrap_or(defaults.srgb),
+            depth_size: table
+                .get::<Option<u8>>("depth")?
+                .unwrap_or(defaults.depth_size),
+            stencil_size: table
+                .get::<Option<u8>>("stencil")?
+                .unwrap_or(defaults.stencil_size),
+            api,
+        })
+    }
+}
+
+impl IntoLua for OutputInfo {
+    fn into_lua(self, lua: &Lua) -> LResult<mlua::Value> {
+        let table = lua.create_table()?;
+        table.set("name", self.name)?;
+        table.set("description", self.description)?;
+        table.set("make", self.make)?;
+        table.set("model", self.model)?;
+        table.set("x", self.x)?;
+        table.set("y", self.y)?;
+        table.set("physical_width", self.physical_width)?;
+        table.set("physical_height", self.physical_height)?;
+        table.set("scale", self.scale)?;
+        table.set(
+            "transform",
+            match self.transform {
+                Transform::_90 => "90",
+                Transform::_180 => "180",
+                Transform::_270 => "270",
+                Transform::Flipped => "flipped",
+                Transform::Flipped90 => "flipped_90",
+                Transform::Flipped180 => "flipped_180",
+                Transform::Flipped270 => "flipped_270",
+                _ => "normal",
+            },
+        )?;
+        if let Some(mode) = self.mode {
+            table.set("width", mode.width)?;
+            table.set("height", mode.height)?;
+            table.set("refresh", mode.refresh)?;
+        }
+        if let Some((x, y)) = self.logical_position {
+            table.set("logical_x", x)?;
+            table.set("logical_y", y)?;
+        }
+        if let Some((width, height)) = self.logical_size {
+            table.set("logical_width", width)?;
+            table.set("logical_height", height)?;
+        }
+        table.into_lua(lua)
     }
 }
 
 #[mlua::lua_module]
 fn dwr(lua: &Lua) -> LResult<Table> {
     let exports = lua.create_table()?;
+    let await_surface = lua
+        .load(AWAIT_SURFACE)
+        .set_name("await_surface")
+        .call::<Function>(())?;
+    lua.set_named_registry_value(AWAIT_SURFACE_KEY, await_surface)?;
     exports.set("create_client", lua.create_function(WaylandClient::init)?)?;
+    exports.set("timer", lua.create_function(event_loop::timer)?)?;
+    exports.set("timeout", lua.create_function(event_loop::timeout)?)?;
+    exports.set("idle", lua.create_function(event_loop::idle)?)?;
+    exports.set("watch_fd", lua.create_function(event_loop::watch_fd)?)?;
+    exports.set("spawn", lua.create_function(process::spawn)?)?;
+    exports.set("easy_async", lua.create_function(process::easy_async)?)?;
     Ok(exports)
 }
@@ -0,0 +1,155 @@
+use std::{
+    cell::RefCell,
+    rc::{Rc, Weak},
+    time::Duration,
+};
+
+use mlua::{
+    Error as LError, ExternalResult, FromLua, Function, IntoLua, Lua, Result as LResult, UserData,
+    UserDataMethods,
+};
+
+use crate::event_loop::{EventLoop, Interest, Readiness, SourceId};
+
+/// The event loop shared by every client of a Lua state, created on first use
+pub fn get_event_loop(lua: &Lua) -> Rc<RefCell<EventLoop>> {
+    if let Some(event_loop) = lua.app_data_ref::<Rc<RefCell<EventLoop>>>() {
+        return event_loop.clone();
+    }
+    let event_loop = Rc::new(RefCell::new(EventLoop::default()));
+    lua.set_app_data(event_loop.clone());
+    event_loop
+}
+
+fn duration_from_secs(seconds: f64) -> LResult<Duration> {
+    Duration::try_from_secs_f64(seconds).into_lua_err()
+}
+
+/// Handle to a timer, fd watcher or idle callback, used to cancel it
+pub struct LuaEventSource {
+    id: SourceId,
+    event_loop: Weak<RefCell<EventLoop>>,
+}
+
+impl LuaEventSource {
+    fn new(id: SourceId, event_loop: &Rc<RefCell<EventLoop>>) -> LuaEventSource {
+        LuaEventSource {
+            id,
+            event_loop: Rc::downgrade(event_loop),
+        }
+    }
+
+    /// Returns `false` if the source already fired (one-shot) or was cancelled before
+    fn cancel(_: &Lua, source: &Self, _: ()) -> LResult<bool> {
+        match source.event_loop.upgrade() {
+            Some(event_loop) => Ok(event_loop
+                .try_borrow_mut()
+                .into_lua_err()?
+                .remove(source.id)),
+            None => Ok(false),
+        }
+    }
+}
+
+impl UserData for LuaEventSource {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("cancel", LuaEventSource::cancel);
+    }
+}
+
+/// `dwr.timer(interval, callback)`, calls `callback` every `interval` seconds
+pub fn timer(lua: &Lua, (interval, callback): (f64, Function)) -> LResult<LuaEventSource> {
+    if interval <= 0.0 {
+        return Err(LError::RuntimeError(
+            "Timer interval must be larger than 0".into(),
+        ));
+    }
+    let interval = duration_from_secs(interval)?;
+    let event_loop = get_event_loop(lua);
+    let id = event_loop.try_borrow_mut().into_lua_err()?.add_timer(
+        interval,
+        Some(interval),
+        Box::new(move || {
+            let _ = callback.call::<()>(());
+        }),
+    );
+    Ok(LuaEventSource::new(id, &event_loop))
+}
+
+/// `dwr.timeout(delay, callback)`, calls `callback` once after `delay` seconds
+pub fn timeout(lua: &Lua, (delay, callback): (f64, Function)) -> LResult<LuaEventSource> {
+    let delay = duration_from_secs(delay)?;
+    let event_loop = get_event_loop(lua);
+    let id = event_loop.try_borrow_mut().into_lua_err()?.add_timer(
+        delay,
+        None,
+        Box::new(move || {
+            let _ = callback.call::<()>(());
+        }),
+    );
+    Ok(LuaEventSource::new(id, &event_loop))
+}
+
+/// `dwr.idle(callback)`, calls `callback` once the loop has nothing else to do
+pub fn idle(lua: &Lua, callback: Function) -> LResult<LuaEventSource> {
+    let event_loop = get_event_loop(lua);
+    let id = event_loop
+        .try_borrow_mut()
+        .into_lua_err()?
+        .add_idle(Box::new(move || {
+            let _ = callback.call::<()>(());
+        }));
+    Ok(LuaEventSource::new(id, &event_loop))
+}
+
+/// `dwr.watch_fd(fd, interest, callback)`, calls `callback(readiness)` whenever `fd` is ready
+///
+/// `interest` is one of `"read"` (default), `"write"` or `"read_write"`.
+pub fn watch_fd(
+    lua: &Lua,
+    (fd, interest, callback): (i32, Option<Interest>, Function),
+) -> LResult<LuaEventSource> {
+    let event_loop = get_event_loop(lua);
+    let id = event_loop.try_borrow_mut().into_lua_err()?.add_watcher(
+        fd,
+        interest.unwrap_or(Interest::READ),
+        Box::new(move |readiness| {
+            let _ = callback.call::<()>(readiness);
+        }),
+    );
+    Ok(LuaEventSource::new(id, &event_loop))
+}
+
+impl FromLua for Interest {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> LResult<Self> {
+        let name = String::from_lua(value, lua)?;
+        match name.as_str() {
+            "read" => Ok(Interest::READ),
+            "write" => Ok(Interest {
+                read: false,
+                write: true,
+            }),
+            "read_write" => Ok(Interest {
+                read: true,
+                write: true,
+            }),
+            _ => Err(LError::FromLuaConversionError {
+                from: "string",
+                to: "Interest".into(),
+                message: Some(format!(
+                    "expected \"read\", \"write\" or \"read_write\", got \"{name}\""
+                )),
+            }),
+        }
+    }
+}
+
+impl IntoLua for Readiness {
+    fn into_lua(self, lua: &Lua) -> LResult<mlua::Value> {
+        let table = lua.create_table()?;
+        table.set("readable", self.readable)?;
+        table.set("writable", self.writable)?;
+        table.set("hangup", self.hangup)?;
+        table.into_lua(lua)
+    }
+}
@@ -0,0 +1,131 @@
+use mlua::{IntoLua, Lua, Result as LResult, Table};
+use wayland_client::protocol::wl_pointer::AxisSource;
+
+use crate::{
+    input::{AxisMotion, PointerEvent},
+    keyboard::{KeyboardEvent, Modifiers},
+};
+
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+const BTN_MIDDLE: u32 = 0x112;
+const BTN_SIDE: u32 = 0x113;
+const BTN_EXTRA: u32 = 0x114;
+
+/// Keyboard event as passed to the Lua `on_keyboard` callbacks
+pub struct LuaKeyboardEvent(pub KeyboardEvent);
+
+/// Pointer event as passed to the Lua `on_pointer` callbacks
+pub struct LuaPointerEvent(pub PointerEvent);
+
+fn set_position(table: &Table, x: f64, y: f64) -> LResult<()> {
+    table.set("x", x)?;
+    table.set("y", y)
+}
+
+fn axis_into_lua(lua: &Lua, motion: AxisMotion) -> LResult<Table> {
+    let table = lua.create_table()?;
+    table.set("value", motion.value)?;
+    table.set("discrete", motion.discrete())?;
+    table.set("value120", motion.value120)?;
+    table.set("stop", motion.stop)?;
+    Ok(table)
+}
+
+impl IntoLua for LuaPointerEvent {
+    fn into_lua(self, lua: &Lua) -> LResult<mlua::Value> {
+        let table = lua.create_table()?;
+        match self.0 {
+            PointerEvent::Enter { x, y } => {
+                table.set("type", "enter")?;
+                set_position(&table, x, y)?;
+            }
+            PointerEvent::Leave => table.set("type", "leave")?,
+            PointerEvent::Motion { x, y } => {
+                table.set("type", "motion")?;
+                set_position(&table, x, y)?;
+            }
+            PointerEvent::Button {
+                x,
+                y,
+                button,
+                pressed,
+            } => {
+                table.set("type", "button")?;
+                set_position(&table, x, y)?;
+                table.set("button", button)?;
+                table.set(
+                    "button_name",
+                    match button {
+                        BTN_LEFT => Some("left"),
+                        BTN_RIGHT => Some("right"),
+                        BTN_MIDDLE => Some("middle"),
+                        BTN_SIDE => Some("side"),
+                        BTN_EXTRA => Some("extra"),
+                        _ => None,
+                    },
+                )?;
+                table.set("pressed", pressed)?;
+            }
+            PointerEvent::Axis {
+                x,
+                y,
+                horizontal,
+                vertical,
+                source,
+            } => {
+                table.set("type", "axis")?;
+                set_position(&table, x, y)?;
+                table.set("horizontal", axis_into_lua(lua, horizontal)?)?;
+                table.set("vertical", axis_into_lua(lua, vertical)?)?;
+                table.set(
+                    "source",
+                    source.map(|source| match source {
+                        AxisSource::Wheel => "wheel",
+                        AxisSource::Finger => "finger",
+                        AxisSource::Continuous => "continuous",
+                        AxisSource::WheelTilt => "wheel_tilt",
+                        _ => "unknown",
+                    }),
+                )?;
+            }
+        }
+        table.into_lua(lua)
+    }
+}
+
+fn modifiers_into_lua(lua: &Lua, modifiers: Modifiers) -> LResult<Table> {
+    let table = lua.create_table()?;
+    table.set("shift", modifiers.shift)?;
+    table.set("ctrl", modifiers.ctrl)?;
+    table.set("alt", modifiers.alt)?;
+    table.set("logo", modifiers.logo)?;
+    table.set("caps_lock", modifiers.caps_lock)?;
+    table.set("num_lock", modifiers.num_lock)?;
+    Ok(table)
+}
+
+impl IntoLua for LuaKeyboardEvent {
+    fn into_lua(self, lua: &Lua) -> LResult<mlua::Value> {
+        let table = lua.create_table()?;
+        match self.0 {
+            KeyboardEvent::Enter => table.set("type", "enter")?,
+            KeyboardEvent::Leave => table.set("type", "leave")?,
+            KeyboardEvent::Key(key) => {
+                table.set("type", "key")?;
+                table.set("keycode", key.keycode)?;
+                table.set("keysym", key.keysym)?;
+                table.set("key", key.name)?;
+                table.set("text", key.text)?;
+                table.set("pressed", key.pressed)?;
+                table.set("repeat", key.repeat)?;
+                table.set("modifiers", modifiers_into_lua(lua, key.modifiers)?)?;
+            }
+            KeyboardEvent::Modifiers(modifiers) => {
+                table.set("type", "modifiers")?;
+                table.set("modifiers", modifiers_into_lua(lua, modifiers)?)?;
+            }
+        }
+        table.into_lua(lua)
+    }
+}
@@ -1,2 +1,6 @@
 pub mod rendering;
 pub mod entry;
+pub mod drawing;
+pub mod input;
+pub mod event_loop;
+pub mod process;
@@ -0,0 +1,197 @@
+use std::{cell::RefCell, collections::HashMap, path::PathBuf, rc::Rc};
+
+use mlua::{
+    Error as LError, ExternalResult, FromLua, Function, Lua, Result as LResult, Table, UserData,
+    UserDataMethods,
+};
+
+use super::event_loop::get_event_loop;
+use crate::process::{
+    ExitReason, LineCallback, Process, ProcessCallbacks, ProcessHandle, SpawnOptions,
+};
+
+/// A command as either a shell string or an argument list, e.g. `{ "ls", "-l" }`
+pub struct LuaCommand(SpawnOptions);
+
+impl FromLua for LuaCommand {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> LResult<Self> {
+        match value {
+            mlua::Value::String(command) => Ok(LuaCommand(SpawnOptions::shell(&command.to_str()?))),
+            mlua::Value::Table(_) => {
+                let mut argv = Vec::<String>::from_lua(value, lua)?.into_iter();
+                let program = argv.next().ok_or(LError::FromLuaConversionError {
+                    from: "table",
+                    to: "command".into(),
+                    message: Some("the argument list is empty".into()),
+                })?;
+                Ok(LuaCommand(SpawnOptions {
+                    program,
+                    args: argv.collect(),
+                    ..SpawnOptions::default()
+                }))
+            }
+            _ => Err(LError::FromLuaConversionError {
+                from: value.type_name(),
+                to: "command".into(),
+                message: Some("expected a string or a list of arguments".into()),
+            }),
+        }
+    }
+}
+
+/// Accepts a signal number or one of the common signal names, e.g. `"TERM"` or `"SIGKILL"`
+struct LuaSignal(i32);
+
+impl FromLua for LuaSignal {
+    fn from_lua(value: mlua::Value, lua: &Lua) -> LResult<Self> {
+        if let mlua::Value::Integer(signal) = value {
+            return Ok(LuaSignal(signal as i32));
+        }
+        let name = String::from_lua(value, lua)?;
+        let signal = match name.trim_start_matches("SIG") {
+            "HUP" => libc::SIGHUP,
+            "INT" => libc::SIGINT,
+            "QUIT" => libc::SIGQUIT,
+            "KILL" => libc::SIGKILL,
+            "USR1" => libc::SIGUSR1,
+            "USR2" => libc::SIGUSR2,
+            "TERM" => libc::SIGTERM,
+            "CONT" => libc::SIGCONT,
+            "STOP" => libc::SIGSTOP,
+            _ => {
+                return Err(LError::FromLuaConversionError {
+                    from: "string",
+                    to: "signal".into(),
+                    message: Some(format!("unknown signal \"{name}\"")),
+                });
+            }
+        };
+        Ok(LuaSignal(signal))
+    }
+}
+
+fn exit_reason_parts(reason: ExitReason) -> (&'static str, i32) {
+    match reason {
+        ExitReason::Exit(code) => ("exit", code),
+        ExitReason::Signal(signal) => ("signal", signal),
+    }
+}
+
+pub struct LuaProcess {
+    process: ProcessHandle,
+}
+
+impl LuaProcess {
+    fn pid(_: &Lua, process: &Self, _: ()) -> LResult<u32> {
+        Ok(process.process.try_borrow().into_lua_err()?.pid())
+    }
+
+    fn is_running(_: &Lua, process: &Self, _: ()) -> LResult<bool> {
+        Ok(process.process.try_borrow().into_lua_err()?.is_running())
+    }
+
+    fn write(_: &Lua, process: &Self, data: mlua::String) -> LResult<()> {
+        Process::write(&process.process, &data.as_bytes()).into_lua_err()
+    }
+
+    fn close_stdin(_: &Lua, process: &Self, _: ()) -> LResult<()> {
+        process
+            .process
+            .try_borrow_mut()
+            .into_lua_err()?
+            .close_stdin();
+        Ok(())
+    }
+
+    /// Sends `signal` to the process, `SIGTERM` by default
+    fn kill(_: &Lua, process: &Self, signal: Option<LuaSignal>) -> LResult<()> {
+        let signal = signal.map_or(libc::SIGTERM, |signal| signal.0);
+        process
+            .process
+            .try_borrow()
+            .into_lua_err()?
+            .kill(signal)
+            .into_lua_err()
+    }
+}
+
+impl UserData for LuaProcess {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("pid", LuaProcess::pid);
+        methods.add_method("is_running", LuaProcess::is_running);
+        methods.add_method("write", LuaProcess::write);
+        methods.add_method("close_stdin", LuaProcess::close_stdin);
+        methods.add_method("kill", LuaProcess::kill);
+    }
+}
+
+fn line_callback(callback: Function) -> LineCallback {
+    Box::new(move |line, _| {
+        let _ = callback.call::<()>(line);
+    })
+}
+
+/// `dwr.spawn(command, options)`, starts `command` without waiting for it
+///
+/// Every option is optional: `stdout(line)`, `stderr(line)` and `exit(reason, code)` callbacks,
+/// `env` (a table of variables), `cwd`, `stdin` (pipe stdin for `process:write`) and `timeout`
+/// in seconds.
+pub fn spawn(lua: &Lua, (command, options): (LuaCommand, Option<Table>)) -> LResult<LuaProcess> {
+    let mut spawn_options = command.0;
+    let mut callbacks = ProcessCallbacks::default();
+
+    if let Some(options) = options {
+        callbacks.stdout = options
+            .get::<Option<Function>>("stdout")?
+            .map(line_callback);
+        callbacks.stderr = options
+            .get::<Option<Function>>("stderr")?
+            .map(line_callback);
+        if let Some(exit) = options.get::<Option<Function>>("exit")? {
+            callbacks.exit = Some(Box::new(move |reason| {
+                let _ = exit.call::<()>(exit_reason_parts(reason));
+            }));
+        }
+        spawn_options.env = options
+            .get::<Option<HashMap<String, String>>>("env")?
+            .unwrap_or_default();
+        spawn_options.cwd = options.get::<Option<PathBuf>>("cwd")?;
+        spawn_options.stdin = options.get::<Option<bool>>("stdin")?.unwrap_or(false);
+        spawn_options.timeout = options
+            .get::<Option<f64>>("timeout")?
+            .map(std::time::Duration::try_from_secs_f64)
+            .transpose()
+            .into_lua_err()?;
+    }
+
+    let process = Process::spawn(&get_event_loop(lua), spawn_options, callbacks).into_lua_err()?;
+    Ok(LuaProcess { process })
+}
+
+/// `dwr.easy_async(command, callback)`, calls `callback(stdout, stderr, reason, code)` once the
+/// command finished, with the complete output
+pub fn easy_async(lua: &Lua, (command, callback): (LuaCommand, Function)) -> LResult<LuaProcess> {
+    let stdout = Rc::new(RefCell::new(String::new()));
+    let stderr = Rc::new(RefCell::new(String::new()));
+    let collect = |output: &Rc<RefCell<String>>| -> LineCallback {
+        let output = output.clone();
+        Box::new(move |line, terminated| {
+            let mut output = output.borrow_mut();
+            output.push_str(&line);
+            if terminated {
+                output.push('\n');
+            }
+        })
+    };
+
+    let callbacks = ProcessCallbacks {
+        stdout: Some(collect(&stdout)),
+        stderr: Some(collect(&stderr)),
+        exit: Some(Box::new(move |reason| {
+            let (reason, code) = exit_reason_parts(reason);
+            let _ = callback.call::<()>((stdout.take(), stderr.take(), reason, code));
+        })),
+    };
+    let process = Process::spawn(&get_event_loop(lua), command.0, callbacks).into_lua_err()?;
+    Ok(LuaProcess { process })
+}
@@ -4,42 +4,389 @@ use std::{
 };
 
 use mlua::{
-    Error as LError, ExternalResult, FromLua, FromLuaMulti, Lua, Result as LResult, UserData,
+    Error as LError, ExternalResult, FromLua, FromLuaMulti, Function, IntoLua, Lua,
+    Result as LResult, Table, UserData,
 };
 use wayland_backend::client::ObjectId;
+use wayland_client::protocol::wl_compositor::WlCompositor;
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1::Layer,
+    zwlr_layer_surface_v1::{Anchor, KeyboardInteractivity},
+};
 
+use super::{
+    drawing::{LuaDrawContext, into_lua_error},
+    entry::DeferredCalls,
+    input::{LuaKeyboardEvent, LuaPointerEvent},
+};
 use crate::{
+    canvas::BlendMode,
+    opengl::{highlevel::SimpleGL, types::Vec2},
     state::WaylandState,
-    surface::{Margins, Surface},
+    surface::{Margins, Rect, Sizes, Surface, SurfaceOptions},
 };
 
+/// Runs `callback` with a drawing context for the surface and presents the result afterwards
+fn draw_surface(
+    lua: &Lua,
+    id: &ObjectId,
+    state: &Rc<RefCell<WaylandState>>,
+    callback: &Function,
+) -> LResult<()> {
+    let (context, batch) = {
+        let mut borrowed = state.try_borrow_mut().into_lua_err()?;
+        let surface = get_surface(&mut borrowed, id)?;
+        match surface.is_software() {
+            true => {
+                if !surface.prepare_pixel_buffer() {
+                    // Every buffer is still in use, try again once the compositor releases one
+                    surface.request_redraw();
+                    return Ok(());
+                }
+                (LuaDrawContext::software(id.clone(), state.clone()), None)
+            }
+            false => {
+                surface.make_current().into_lua_err()?;
+                let core = surface.get_renderer().map_err(into_lua_error)?;
+                let atlas = surface.get_glyph_atlas().map_err(into_lua_error)?;
+                let textures = surface.get_texture_cache().map_err(into_lua_error)?;
+                let batch = surface.get_batch().map_err(into_lua_error)?;
+                // Drawing is in logical pixels, stretched over the scaled up buffer
+                let sizes = surface.get_properties().sizes;
+                let gl = SimpleGL::new(core)
+                    .with_glyphs(atlas)
+                    .with_textures(textures)
+                    .with_batch(batch.clone())
+                    .with_projection(Vec2::new(sizes.width as f32, sizes.height as f32));
+                // The viewport, clipping and blending are left behind by whichever surface was
+                // drawn last, they must not affect this one
+                let size = surface.get_buffer_size();
+                gl.set_viewport(size.width, size.height)
+                    .map_err(into_lua_error)?;
+                gl.apply_clip().map_err(into_lua_error)?;
+                gl.set_blend_mode(BlendMode::default())
+                    .map_err(into_lua_error)?;
+                (
+                    LuaDrawContext::new(gl, id.clone(), state.clone()),
+                    Some(batch),
+                )
+            }
+        }
+    };
+
+    // The state is not borrowed while the callback runs, so it may freely use the surface
+    let drawn = lua.scope(|scope| callback.call::<()>(scope.create_userdata(context)?));
+    if drawn.is_err()
+        && let Some(batch) = batch
+    {
+        // The frame is never presented, what it queued must not end up in the next one
+        batch.try_borrow_mut().into_lua_err()?.clear();
+    }
+    drawn?;
+
+    let mut state = state.try_borrow_mut().into_lua_err()?;
+    let surface = get_surface(&mut state, id)?;
+    surface.request_frame();
+    surface.swap_buffers().into_lua_err()
+}
+
+/// A width or height of 0 without both opposite anchors is a protocol error, which would
+/// disconnect the client
+fn size_error() -> LError {
+    LError::RuntimeError(
+        "A width and height are required, unless the surface is anchored to both opposite edges"
+            .into(),
+    )
+}
+
+fn get_compositor(state: &WaylandState) -> LResult<WlCompositor> {
+    Ok(state
+        .bound
+        .as_ref()
+        .ok_or(LError::RuntimeError(
+            "The compositor globals are not bound".into(),
+        ))?
+        .get_compositor()
+        .clone())
+}
+
+fn get_surface<'a>(state: &'a mut WaylandState, id: &ObjectId) -> LResult<&'a mut Surface> {
+    state.surface_links.get_mut(id).ok_or(LError::MemoryError(
+        "Surface reference invalid, the surface has been closed".into(),
+    ))
+}
+
 #[derive(Clone)]
 pub struct LuaSurfaceReference {
     id: ObjectId,
     state: Rc<RefCell<WaylandState>>,
+    deferred: DeferredCalls,
 }
 
 impl LuaSurfaceReference {
-    pub fn new(id: ObjectId, state: Rc<RefCell<WaylandState>>) -> LuaSurfaceReference {
-        LuaSurfaceReference { id, state }
+    pub fn new(
+        id: ObjectId,
+        state: Rc<RefCell<WaylandState>>,
+        deferred: DeferredCalls,
+    ) -> LuaSurfaceReference {
+        LuaSurfaceReference {
+            id,
+            state,
+            deferred,
+        }
     }
 
     fn set_margin(_: &Lua, reference: &mut Self, margins: Margins) -> LResult<()> {
         let mut state = reference.state.try_borrow_mut().into_lua_err()?;
-        let surface = state
-            .surface_links
-            .get_mut(&reference.id)
-            .ok_or(LError::MemoryError(
-                "Surface reference invalid, this should never be possible".into(),
-            ))?;
+        let surface = get_surface(&mut state, &reference.id)?;
         surface.set_margin(margins);
         Ok(())
     }
+
+    /// Only the `rects` receive pointer input, elsewhere it goes to whatever is below the surface
+    ///
+    /// `nil` makes the whole surface receive input again. Applies with the next draw.
+    fn set_input_region(_: &Lua, reference: &mut Self, rects: Option<Vec<Rect>>) -> LResult<()> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        let compositor = get_compositor(&state)?;
+        get_surface(&mut state, &reference.id)?.set_input_region(&compositor, rects.as_deref());
+        Ok(())
+    }
+
+    /// Tells the compositor the `rects` are fully opaque, so it can skip drawing what's below
+    ///
+    /// `nil`, the default, is right for surfaces with transparent parts. Applies with the next
+    /// draw.
+    fn set_opaque_region(_: &Lua, reference: &mut Self, rects: Option<Vec<Rect>>) -> LResult<()> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        let compositor = get_compositor(&state)?;
+        get_surface(&mut state, &reference.id)?.set_opaque_region(&compositor, rects.as_deref());
+        Ok(())
+    }
+
+    /// Changes the properties present in `properties`, the others keep their current values
+    ///
+    /// Takes the same table as `get_properties` returns, `exclusive_edge = false` or `"none"`
+    /// clears the edge.
+    fn set_properties(_: &Lua, reference: &mut Self, properties: Table) -> LResult<()> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        let surface = get_surface(&mut state, &reference.id)?;
+        let mut merged = *surface.get_properties();
+        if let Some(margins) = properties.get::<Option<Margins>>("margins")? {
+            merged.margins = margins;
+        }
+        if let Some(anchor) = properties.get::<Option<LuaAnchor>>("anchor")? {
+            merged.anchor = anchor.0;
+        }
+        if let Some(interactivity) =
+            properties.get::<Option<LuaKeyboardInteractivity>>("keyboard_interactivity")?
+        {
+            merged.interactivity = interactivity.0;
+        }
+        if let Some(layer) = properties.get::<Option<LuaLayer>>("layer")? {
+            merged.layer = layer.0;
+        }
+        if let Some(sizes) = properties.get::<Option<Sizes>>("size")? {
+            merged.sizes = sizes;
+        }
+        if let Some(zone) = properties.get::<Option<i32>>("exclusive_zone")? {
+            merged.exclusive_zone = zone;
+        }
+        if let Some(edge) = properties.get::<Option<LuaExclusiveEdge>>("exclusive_edge")? {
+            merged.exclusive_edge = edge.0;
+        }
+        if !merged.has_valid_size() {
+            return Err(size_error());
+        }
+        surface.set_properties(merged);
+        Ok(())
+    }
+
+    /// Returns the number of buffer pixels per logical pixel, e.g. `1.5` on a HiDPI output
+    fn get_scale(_: &Lua, reference: &mut Self, _: ()) -> LResult<f64> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        Ok(get_surface(&mut state, &reference.id)?.get_scale())
+    }
+
+    /// Returns `{ draw_calls, vertices }` submitted for the last frame, `nil` for surfaces
+    /// rendered in software or which weren't drawn yet
+    fn get_frame_stats(lua: &Lua, reference: &mut Self, _: ()) -> LResult<Option<Table>> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        let Some(stats) = get_surface(&mut state, &reference.id)?.get_frame_stats() else {
+            return Ok(None);
+        };
+        let table = lua.create_table()?;
+        table.set("draw_calls", stats.draw_calls)?;
+        table.set("vertices", stats.vertices)?;
+        Ok(Some(table))
+    }
+
+    /// Returns `{ margins, anchor, keyboard_interactivity, layer, size, exclusive_zone,
+    /// exclusive_edge }`, where `size` is the size the surface currently has
+    fn get_properties(lua: &Lua, reference: &mut Self, _: ()) -> LResult<Table> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        let properties = *get_surface(&mut state, &reference.id)?.get_properties();
+
+        let table = lua.create_table()?;
+        table.set("margins", properties.margins)?;
+        table.set("anchor", LuaAnchor(properties.anchor))?;
+        table.set(
+            "keyboard_interactivity",
+            LuaKeyboardInteractivity(properties.interactivity),
+        )?;
+        table.set("layer", LuaLayer(properties.layer))?;
+        table.set("size", properties.sizes)?;
+        table.set("exclusive_zone", properties.exclusive_zone)?;
+        table.set("exclusive_edge", properties.exclusive_edge.map(LuaEdge))?;
+        Ok(table)
+    }
+
+    fn on_closed(_: &Lua, reference: &mut Self, callback: Function) -> LResult<()> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        let deferred = reference.deferred.clone();
+        state.surface_closed_callback.insert(
+            reference.id.clone(),
+            Box::new(move |_, _| {
+                deferred.borrow_mut().push_back(Box::new(move || {
+                    let _ = callback.call::<()>(());
+                }));
+            }),
+        );
+        Ok(())
+    }
+
+    /// Registers `callback(event)` for pointer events on this surface, replacing the previous one
+    fn on_pointer(_: &Lua, reference: &mut Self, callback: Function) -> LResult<()> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        let deferred = reference.deferred.clone();
+        state.pointer_callbacks.insert(
+            reference.id.clone(),
+            Box::new(move |_, _, event| {
+                let callback = callback.clone();
+                deferred.borrow_mut().push_back(Box::new(move || {
+                    let _ = callback.call::<()>(LuaPointerEvent(event));
+                }));
+            }),
+        );
+        Ok(())
+    }
+
+    /// Registers `callback(event)` for keyboard events on this surface, replacing the previous one
+    ///
+    /// The surface only receives keyboard focus if its keyboard interactivity allows it.
+    fn on_keyboard(_: &Lua, reference: &mut Self, callback: Function) -> LResult<()> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        let deferred = reference.deferred.clone();
+        state.keyboard_callbacks.insert(
+            reference.id.clone(),
+            Box::new(move |_, _, event| {
+                let callback = callback.clone();
+                deferred.borrow_mut().push_back(Box::new(move || {
+                    let _ = callback.call::<()>(LuaKeyboardEvent(event));
+                }));
+            }),
+        );
+        Ok(())
+    }
+
+    fn set_keyboard_interactivity(
+        _: &Lua,
+        reference: &mut Self,
+        interactivity: LuaKeyboardInteractivity,
+    ) -> LResult<()> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        let surface = get_surface(&mut state, &reference.id)?;
+        surface.set_keyboard_interactivity(interactivity.0);
+        Ok(())
+    }
+
+    /// Reserves `zone` pixels at the anchored edge, so windows don't overlap the surface
+    fn set_exclusive_zone(_: &Lua, reference: &mut Self, zone: i32) -> LResult<()> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        get_surface(&mut state, &reference.id)?.set_exclusive_zone(zone);
+        Ok(())
+    }
+
+    /// Picks the edge the exclusive zone applies to, returns `false` if the surface isn't anchored
+    /// to `edge` or on compositors which only support the anchored edge
+    fn set_exclusive_edge(_: &Lua, reference: &mut Self, edge: LuaEdge) -> LResult<bool> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        Ok(get_surface(&mut state, &reference.id)?.set_exclusive_edge(edge.0))
+    }
+
+    /// Runs `callback` with a drawing context and presents the result afterwards
+    fn draw(lua: &Lua, reference: &mut Self, callback: Function) -> LResult<()> {
+        draw_surface(lua, &reference.id, &reference.state, &callback)
+    }
+
+    /// Registers `callback(ctx)` to draw the surface whenever it needs to be redrawn
+    ///
+    /// Redraws are throttled by the compositor's frame callbacks, so at most one draw happens per
+    /// frame of the output the surface is on.
+    fn on_draw(lua: &Lua, reference: &mut Self, callback: Function) -> LResult<()> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        // Weak references, the callback is owned by the state itself
+        let weak_lua = lua.weak();
+        let weak_state = Rc::downgrade(&reference.state);
+        let deferred = reference.deferred.clone();
+        state.draw_callbacks.insert(
+            reference.id.clone(),
+            Box::new(move |_, id| {
+                let (weak_lua, weak_state) = (weak_lua.clone(), weak_state.clone());
+                let callback = callback.clone();
+                deferred.borrow_mut().push_back(Box::new(move || {
+                    if let Some(lua) = weak_lua.try_upgrade()
+                        && let Some(state) = weak_state.upgrade()
+                    {
+                        let _ = draw_surface(&lua, &id, &state, &callback);
+                    }
+                }));
+            }),
+        );
+        get_surface(&mut state, &reference.id)?.request_redraw();
+        Ok(())
+    }
+
+    fn request_redraw(_: &Lua, reference: &mut Self, _: ()) -> LResult<()> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        get_surface(&mut state, &reference.id)?.request_redraw();
+        Ok(())
+    }
+
+    fn destroy(_: &Lua, reference: &mut Self, _: ()) -> LResult<bool> {
+        let mut state = reference.state.try_borrow_mut().into_lua_err()?;
+        Ok(state.destroy_surface(&reference.id))
+    }
 }
 
 impl UserData for LuaSurfaceReference {
     fn add_methods<M: mlua::UserDataMethods<Self>>(methods: &mut M) {
         methods.add_method_mut("set_margin", LuaSurfaceReference::set_margin);
+        methods.add_method_mut("set_properties", LuaSurfaceReference::set_properties);
+        methods.add_method_mut("get_properties", LuaSurfaceReference::get_properties);
+        methods.add_method_mut("get_scale", LuaSurfaceReference::get_scale);
+        methods.add_method_mut("get_frame_stats", LuaSurfaceReference::get_frame_stats);
+        methods.add_method_mut("set_input_region", LuaSurfaceReference::set_input_region);
+        methods.add_method_mut("set_opaque_region", LuaSurfaceReference::set_opaque_region);
+        methods.add_method_mut("on_closed", LuaSurfaceReference::on_closed);
+        methods.add_method_mut("on_pointer", LuaSurfaceReference::on_pointer);
+        methods.add_method_mut("on_keyboard", LuaSurfaceReference::on_keyboard);
+        methods.add_method_mut(
+            "set_keyboard_interactivity",
+            LuaSurfaceReference::set_keyboard_interactivity,
+        );
+        methods.add_method_mut(
+            "set_exclusive_zone",
+            LuaSurfaceReference::set_exclusive_zone,
+        );
+        methods.add_method_mut(
+            "set_exclusive_edge",
+            LuaSurfaceReference::set_exclusive_edge,
+        );
+        methods.add_method_mut("draw", LuaSurfaceReference::draw);
+        methods.add_method_mut("on_draw", LuaSurfaceReference::on_draw);
+        methods.add_method_mut("request_redraw", LuaSurfaceReference::request_redraw);
+        methods.add_method_mut("destroy", LuaSurfaceReference::destroy);
     }
 }
 
@@ -49,18 +396,270 @@ impl FromLuaMulti for LuaSurfaceReference {
     }
 }
 
+impl FromLua for Rect {
+    fn from_lua(value: mlua::Value, _lua: &mlua::Lua) -> mlua::Result<Self> {
+        let table = value.as_table().ok_or(LError::FromLuaConversionError {
+            from: value.type_name(),
+            to: "{ <x>, <y>, <width>, <height> }".into(),
+            message: None,
+        })?;
+        Ok(Rect {
+            x: table.get(1)?,
+            y: table.get(2)?,
+            width: table.get(3)?,
+            height: table.get(4)?,
+        })
+    }
+}
+
+/// `{ width, height, layer, anchor, margins, exclusive_zone, exclusive_edge, keyboard, output,
+/// namespace }`, every key is optional
+///
+/// `width` is required unless the surface is anchored to the left and right edges, which stretch
+/// it instead, the same goes for `height` with the top and bottom edges.
+///
+/// `output` is the name of the output to place the surface on, the compositor picks one if it is
+/// left out.
+pub struct LuaSurfaceOptions {
+    pub options: SurfaceOptions,
+    pub output: Option<String>,
+}
+
+impl FromLua for LuaSurfaceOptions {
+    fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        let table = Table::from_lua(value, lua)?;
+        let mut options = SurfaceOptions::new(
+            table.get::<Option<u32>>("width")?.unwrap_or_default(),
+            table.get::<Option<u32>>("height")?.unwrap_or_default(),
+        );
+        let properties = &mut options.properties;
+        if let Some(layer) = table.get::<Option<LuaLayer>>("layer")? {
+            properties.layer = layer.0;
+        }
+        if let Some(anchor) = table.get::<Option<LuaAnchor>>("anchor")? {
+            properties.anchor = anchor.0;
+        }
+        if let Some(margins) = table.get::<Option<Margins>>("margins")? {
+            properties.margins = margins;
+        }
+        if let Some(zone) = table.get::<Option<i32>>("exclusive_zone")? {
+            properties.exclusive_zone = zone;
+        }
+        properties.exclusive_edge = table
+            .get::<Option<LuaExclusiveEdge>>("exclusive_edge")?
+            .and_then(|edge| edge.0);
+        if let Some(interactivity) = table.get::<Option<LuaKeyboardInteractivity>>("keyboard")? {
+            properties.interactivity = interactivity.0;
+        }
+        if let Some(namespace) = table.get::<Option<String>>("namespace")? {
+            options.namespace = namespace;
+        }
+        if !options.properties.has_valid_size() {
+            return Err(size_error());
+        }
+        Ok(LuaSurfaceOptions {
+            options,
+            output: table.get("output")?,
+        })
+    }
+}
+
+/// Missing margins are 0
 impl FromLua for Margins {
     fn from_lua(value: mlua::Value, _lua: &mlua::Lua) -> mlua::Result<Self> {
-        let table = value.as_table().ok_or(LError::ToLuaConversionError {
-            from: value.type_name().to_string(),
-            to: "{ top = <number>, right = <number>, left = <number>, bottom = <number> }",
+        let table = value.as_table().ok_or(LError::FromLuaConversionError {
+            from: value.type_name(),
+            to: "{ top = <number>?, right = <number>?, left = <number>?, bottom = <number>? }"
+                .into(),
             message: None,
         })?;
         Ok(Margins {
-            top: table.get("top")?,
-            left: table.get("left")?,
-            right: table.get("right")?,
-            bottom: table.get("bottom")?,
+            top: table.get::<Option<i32>>("top")?.unwrap_or_default(),
+            left: table.get::<Option<i32>>("left")?.unwrap_or_default(),
+            right: table.get::<Option<i32>>("right")?.unwrap_or_default(),
+            bottom: table.get::<Option<i32>>("bottom")?.unwrap_or_default(),
+        })
+    }
+}
+
+impl IntoLua for Margins {
+    fn into_lua(self, lua: &Lua) -> LResult<mlua::Value> {
+        let table = lua.create_table()?;
+        table.set("top", self.top)?;
+        table.set("right", self.right)?;
+        table.set("bottom", self.bottom)?;
+        table.set("left", self.left)?;
+        table.into_lua(lua)
+    }
+}
+
+/// A missing width or height is 0, which lets the compositor decide it
+impl FromLua for Sizes {
+    fn from_lua(value: mlua::Value, _lua: &mlua::Lua) -> mlua::Result<Self> {
+        let table = value.as_table().ok_or(LError::FromLuaConversionError {
+            from: value.type_name(),
+            to: "{ width = <number>?, height = <number>? }".into(),
+            message: None,
+        })?;
+        Ok(Sizes {
+            width: table.get::<Option<u32>>("width")?.unwrap_or_default(),
+            height: table.get::<Option<u32>>("height")?.unwrap_or_default(),
         })
     }
 }
+
+impl IntoLua for Sizes {
+    fn into_lua(self, lua: &Lua) -> LResult<mlua::Value> {
+        let table = lua.create_table()?;
+        table.set("width", self.width)?;
+        table.set("height", self.height)?;
+        table.into_lua(lua)
+    }
+}
+
+const EDGES: [(Anchor, &str); 4] = [
+    (Anchor::Top, "top"),
+    (Anchor::Bottom, "bottom"),
+    (Anchor::Left, "left"),
+    (Anchor::Right, "right"),
+];
+
+/// `Anchor` as a list of edges, e.g. `{ "top", "left", "right" }`
+pub struct LuaAnchor(pub Anchor);
+
+impl FromLua for LuaAnchor {
+    fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        Ok(LuaAnchor(
+            Vec::<LuaEdge>::from_lua(value, lua)?
+                .into_iter()
+                .fold(Anchor::empty(), |anchor, edge| anchor | edge.0),
+        ))
+    }
+}
+
+impl IntoLua for LuaAnchor {
+    fn into_lua(self, lua: &Lua) -> LResult<mlua::Value> {
+        EDGES
+            .iter()
+            .filter(|(edge, _)| self.0.contains(*edge))
+            .map(|(_, name)| *name)
+            .collect::<Vec<_>>()
+            .into_lua(lua)
+    }
+}
+
+/// `Layer` as one of `"background"`, `"bottom"`, `"top"` or `"overlay"`
+pub struct LuaLayer(pub Layer);
+
+impl FromLua for LuaLayer {
+    fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        let name = String::from_lua(value, lua)?;
+        match name.as_str() {
+            "background" => Ok(Layer::Background),
+            "bottom" => Ok(Layer::Bottom),
+            "top" => Ok(Layer::Top),
+            "overlay" => Ok(Layer::Overlay),
+            _ => Err(LError::FromLuaConversionError {
+                from: "string",
+                to: "Layer".into(),
+                message: Some(format!(
+                    "expected \"background\", \"bottom\", \"top\" or \"overlay\", got \"{name}\""
+                )),
+            }),
+        }
+        .map(LuaLayer)
+    }
+}
+
+impl IntoLua for LuaLayer {
+    fn into_lua(self, lua: &Lua) -> LResult<mlua::Value> {
+        match self.0 {
+            Layer::Background => "background",
+            Layer::Bottom => "bottom",
+            Layer::Overlay => "overlay",
+            _ => "top",
+        }
+        .into_lua(lua)
+    }
+}
+
+/// A single edge `Anchor` as one of `"top"`, `"bottom"`, `"left"` or `"right"`
+pub struct LuaEdge(pub Anchor);
+
+impl FromLua for LuaEdge {
+    fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        let name = String::from_lua(value, lua)?;
+        match name.as_str() {
+            "top" => Ok(Anchor::Top),
+            "bottom" => Ok(Anchor::Bottom),
+            "left" => Ok(Anchor::Left),
+            "right" => Ok(Anchor::Right),
+            _ => Err(LError::FromLuaConversionError {
+                from: "string",
+                to: "Anchor".into(),
+                message: Some(format!(
+                    "expected \"top\", \"bottom\", \"left\" or \"right\", got \"{name}\""
+                )),
+            }),
+        }
+        .map(LuaEdge)
+    }
+}
+
+/// An exclusive edge, `false` or `"none"` for no edge at all
+///
+/// `nil` can't be used for that, as it means the key is absent.
+pub struct LuaExclusiveEdge(pub Option<Anchor>);
+
+impl FromLua for LuaExclusiveEdge {
+    fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        match &value {
+            mlua::Value::Boolean(false) => Ok(LuaExclusiveEdge(None)),
+            mlua::Value::String(name) if name.to_str()? == "none" => Ok(LuaExclusiveEdge(None)),
+            _ => LuaEdge::from_lua(value, lua).map(|edge| LuaExclusiveEdge(Some(edge.0))),
+        }
+    }
+}
+
+impl IntoLua for LuaEdge {
+    fn into_lua(self, lua: &Lua) -> LResult<mlua::Value> {
+        EDGES
+            .iter()
+            .find(|(edge, _)| *edge == self.0)
+            .map(|(_, name)| *name)
+            .into_lua(lua)
+    }
+}
+
+/// `KeyboardInteractivity` as one of `"none"`, `"exclusive"` or `"on_demand"`
+pub struct LuaKeyboardInteractivity(pub KeyboardInteractivity);
+
+impl FromLua for LuaKeyboardInteractivity {
+    fn from_lua(value: mlua::Value, lua: &mlua::Lua) -> mlua::Result<Self> {
+        let name = String::from_lua(value, lua)?;
+        match name.as_str() {
+            "none" => Ok(KeyboardInteractivity::None),
+            "exclusive" => Ok(KeyboardInteractivity::Exclusive),
+            "on_demand" => Ok(KeyboardInteractivity::OnDemand),
+            _ => Err(LError::FromLuaConversionError {
+                from: "string",
+                to: "KeyboardInteractivity".into(),
+                message: Some(format!(
+                    "expected \"none\", \"exclusive\" or \"on_demand\", got \"{name}\""
+                )),
+            }),
+        }
+        .map(LuaKeyboardInteractivity)
+    }
+}
+
+impl IntoLua for LuaKeyboardInteractivity {
+    fn into_lua(self, lua: &Lua) -> LResult<mlua::Value> {
+        match self.0 {
+            KeyboardInteractivity::Exclusive => "exclusive",
+            KeyboardInteractivity::OnDemand => "on_demand",
+            _ => "none",
+        }
+        .into_lua(lua)
+    }
+}
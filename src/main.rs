@@ -1,21 +1,33 @@
+use std::{cell::RefCell, rc::Rc};
+
 use wayland_backend::client::ObjectId;
 use wayland_client::{self, Connection, Proxy};
-use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::Layer;
 
 use crate::{
+    canvas::Canvas,
+    gpu_surface::GlOptions,
     opengl::{
-        highlevel::{ElementsMode, SimpleGL},
-        shaders::builtin,
-        types::{OwnedVec2Array, Vec2, Vec4},
+        batch::Batch,
+        highlevel::SimpleGL,
+        types::{Vec2, Vec4},
     },
-    state::WaylandState,
-    surface::Margins,
+    state::{RenderBackend, WaylandState},
+    surface::{Margins, SurfaceOptions},
 };
+mod buffer_pool;
+mod canvas;
 mod gpu_surface;
 mod opengl;
+mod output;
 mod state;
 mod surface;
 mod lua;
+mod input;
+mod keyboard;
+mod event_loop;
+mod process;
+mod scale;
+mod software;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let connection = Connection::connect_to_env()?;
@@ -26,11 +38,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     display.get_registry(&queue_handle, ());
 
-    let mut wayland_state = WaylandState::new(&display);
+    let mut wayland_state = WaylandState::new(&display, RenderBackend::default(), GlOptions::default())?;
     event_queue.roundtrip(&mut wayland_state)?;
 
     let surface_id = wayland_state
-        .create_surface_async(500, 300, Layer::Top, &mut event_queue)
+        .create_surface_async(SurfaceOptions::new(500, 300), None, &mut event_queue)
         .unwrap_or(ObjectId::null());
 
     let mut has_surface = false;
@@ -48,54 +60,47 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                     left: 0,
                 });
 
-                let _ = surface.render(|graphics| {
-                    let gl = SimpleGL::new(graphics);
-                    let shader_program = gl
-                        // .new_builtin_shader(builtin::FlatColor)?
-                        .new_builtin_shader(builtin::QuadColor)?
-                        .use_program()?;
-
-                    let gl = gl.with_shader(shader_program);
+                let outlines = [
+                    (
+                        Vec4::new(0.0, 0.0, 1.0, 1.0),
+                        [
+                            Vec2::new(125.0, 75.0),
+                            Vec2::new(375.0, 75.0),
+                            Vec2::new(375.0, 225.0),
+                        ],
+                    ),
+                    (
+                        Vec4::new(0.0, 1.0, 0.5, 1.0),
+                        [
+                            Vec2::new(125.0, 75.0),
+                            Vec2::new(125.0, 225.0),
+                            Vec2::new(375.0, 225.0),
+                        ],
+                    ),
+                ];
+                let sizes = surface.get_properties().sizes;
+                surface.render(move |graphics| {
+                    let batch = Rc::new(RefCell::new(Batch::new(graphics, GlOptions::default().api)?));
+                    let mut gl = SimpleGL::new(graphics)
+                        .with_batch(batch)
+                        .with_projection(Vec2::new(sizes.width as f32, sizes.height as f32));
                     gl.clear(0.2, 0.1, 0.0, 1.0)?;
 
-                    shader_program.set_color(Vec4::new(0.0, 0.0, 1.0, 1.0))?;
-                    gl.draw_polygon(
-                        ElementsMode::LineLoop,
-                        OwnedVec2Array::new(vec![
-                            Vec2::new(-0.5, 0.5),
-                            Vec2::new(0.5, 0.5),
-                            Vec2::new(0.5, -0.5),
-                        ]),
-                    )?;
-
-                    shader_program.set_color(Vec4::new(0.0, 1.0, 0.5, 1.0))?;
-                    gl.draw_polygon(
-                        ElementsMode::LineLoop,
-                        OwnedVec2Array::new(vec![
-                            Vec2::new(-0.5, 0.5),
-                            Vec2::new(-0.5, -0.5),
-                            Vec2::new(0.5, -0.5),
-                        ]),
-                    )?;
-
-                    shader_program.set_color(Vec4::new(1.0, 0.0, 0.5, 1.0))?;
-                    gl.draw_rectangle(Vec2::new(-0.2, -0.2), Vec2::new(0.4, 0.4))?;
-                    shader_program.set_color(Vec4::new(1.0, 0.0, 0.5, 1.0))?;
-                    gl.draw_rectangle(Vec2::new(-0.8, -0.8), Vec2::new(0.4, 0.4))?;
-
-                    // shader_program.set_color(Vec4::new(1.0, 0.0, 0.5, 1.0))?;
-                    // gl.draw_rectangle_generic(Vec2::new(0.3, 0.3), Vec2::new(0.4, 0.4))?;
-                    // shader_program.set_color(Vec4::new(1.0, 0.0, 0.5, 1.0))?;
-                    // gl.draw_rectangle_generic(Vec2::new(-0.3, 0.3), Vec2::new(0.4, 0.4))?;
-
-                    Ok(())
-                });
+                    for (color, points) in outlines {
+                        gl.stroke_path(&points, true, color)?;
+                    }
+
+                    let pink = Vec4::new(1.0, 0.0, 0.5, 1.0);
+                    gl.fill_rect(Vec2::new(200.0, 120.0), Vec2::new(100.0, 60.0), pink)?;
+                    gl.fill_rect(Vec2::new(50.0, 210.0), Vec2::new(100.0, 60.0), pink)?;
+
+                    gl.flush()
+                })?;
                 surface.swap_buffers()?;
             }
 
             println!("frame with surface");
         }
-        std::thread::sleep(std::time::Duration::from_millis(16));
     }
 
     Ok(())
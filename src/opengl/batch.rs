@@ -0,0 +1,237 @@
+use std::ffi::c_void;
+
+use glcore::{GL_1_1_g, GL_1_3_g, GL_1_5_g, GL_2_0_g, GL_3_0_g, GLCore};
+
+use crate::gpu_surface::GlApi;
+use crate::opengl::{
+    highlevel::Primitive,
+    shaders::{
+        ShaderBundle, UniformKind, UninitShaderProgram,
+        builtin::{Batched, BatchedEs, BuiltinShader},
+    },
+    types::{GlResult, Vec2, Vec4},
+};
+
+/// Vertices the buffer has room for before the first flush, it grows to fit bigger batches
+const INITIAL_CAPACITY: usize = 1024;
+
+/// One vertex of a batch, laid out like the attributes of the `Batched` shader
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct BatchVertex {
+    pub pos: Vec2,
+    /// Straight alpha
+    pub color: Vec4,
+    /// Ignored unless the batch samples a texture
+    pub uv: Vec2,
+}
+
+impl BatchVertex {
+    pub fn new(pos: Vec2, color: Vec4, uv: Vec2) -> BatchVertex {
+        BatchVertex { pos, color, uv }
+    }
+
+    /// A vertex of an untextured shape
+    pub fn flat(pos: Vec2, color: Vec4) -> BatchVertex {
+        BatchVertex::new(pos, color, Vec2::zero())
+    }
+}
+
+/// What the fragments of a batch sample, vertices sampling different textures can't be batched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchTexture {
+    None,
+    /// A single channel texture whose red channel multiplies the alpha, like the glyph atlas
+    Coverage(u32),
+    /// An RGBA texture multiplied by the vertex color, which tints it
+    Color(u32),
+}
+
+impl BatchTexture {
+    /// The `sampling` uniform of the `Batched` shader
+    fn sampling(self) -> i32 {
+        match self {
+            BatchTexture::None => 0,
+            BatchTexture::Coverage(_) => 1,
+            BatchTexture::Color(_) => 2,
+        }
+    }
+
+    fn texture(self) -> Option<u32> {
+        match self {
+            BatchTexture::None => None,
+            BatchTexture::Coverage(texture) | BatchTexture::Color(texture) => Some(texture),
+        }
+    }
+}
+
+/// Draw calls and vertices submitted by the GPU for a frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub draw_calls: u32,
+    pub vertices: u32,
+}
+
+/// Queues vertices in memory and submits them through one persistent streaming buffer
+///
+/// Queued vertices are drawn together once they can't share a draw call with the next ones, i.e.
+/// the primitive, texture or projection changes. Anything else affecting the drawing, like the
+/// blend mode, clip or current surface, has to `flush` the batch before changing.
+#[derive(Debug)]
+pub struct Batch {
+    core: GLCore,
+    program: UninitShaderProgram<Batched>,
+    vertex_array: u32,
+    vertex_buffer: u32,
+    /// Size of the buffer in vertices
+    capacity: usize,
+    vertices: Vec<BatchVertex>,
+    primitive: Primitive,
+    texture: BatchTexture,
+    projection: Vec2,
+    stats: FrameStats,
+}
+
+impl Batch {
+    /// Compiles the shaders in the GLSL flavour of `api`, which must match the context of `core`
+    pub fn new(core: GLCore, api: GlApi) -> GlResult<Batch> {
+        // Both flavours have the same inputs and uniforms, so they share the program type
+        let program = match api {
+            GlApi::Gl => Batched.into_program(core)?,
+            GlApi::Gles => ShaderBundle::new_from_sources(
+                core,
+                BatchedEs.get_vertex(),
+                BatchedEs.get_fragment(),
+            )?
+            .link()?,
+        };
+
+        let mut vertex_array = 0;
+        core.glGenVertexArrays(1, &mut vertex_array)?;
+        core.glBindVertexArray(vertex_array)?;
+        let mut vertex_buffer = 0;
+        core.glGenBuffers(1, &mut vertex_buffer)?;
+        core.glBindBuffer(glcore::GL_ARRAY_BUFFER, vertex_buffer)?;
+        core.glBufferData(
+            glcore::GL_ARRAY_BUFFER,
+            INITIAL_CAPACITY * size_of::<BatchVertex>(),
+            std::ptr::null(),
+            glcore::GL_STREAM_DRAW,
+        )?;
+
+        // The vertex array remembers the layout, so it is only described once
+        let stride = size_of::<BatchVertex>() as i32;
+        for (index, floats, offset) in [(0, 2, 0), (1, 4, 2), (2, 2, 6)] {
+            core.glEnableVertexAttribArray(index)?;
+            core.glVertexAttribPointer(
+                index,
+                floats,
+                glcore::GL_FLOAT,
+                glcore::GL_FALSE as u8,
+                stride,
+                (offset * size_of::<f32>()) as *const c_void,
+            )?;
+        }
+        core.glBindVertexArray(0)?;
+
+        Ok(Batch {
+            core,
+            program,
+            vertex_array,
+            vertex_buffer,
+            capacity: INITIAL_CAPACITY,
+            vertices: Vec::with_capacity(INITIAL_CAPACITY),
+            primitive: Primitive::Triangles,
+            texture: BatchTexture::None,
+            projection: Vec2::new(1.0, 1.0),
+            stats: FrameStats::default(),
+        })
+    }
+
+    /// Sets the size of the coordinate space, see `SimpleGL::with_projection`
+    pub fn set_projection(&mut self, size: Vec2) -> GlResult<()> {
+        if (size.x, size.y) != (self.projection.x, self.projection.y) {
+            self.flush()?;
+            self.projection = size;
+        }
+        Ok(())
+    }
+
+    /// Queues `vertices`, every `primitive` takes the next 1, 2 or 3 of them
+    pub fn push(
+        &mut self,
+        primitive: Primitive,
+        texture: BatchTexture,
+        vertices: &[BatchVertex],
+    ) -> GlResult<()> {
+        if primitive != self.primitive || texture != self.texture {
+            self.flush()?;
+            self.primitive = primitive;
+            self.texture = texture;
+        }
+        self.vertices.extend_from_slice(vertices);
+        Ok(())
+    }
+
+    /// Draws the queued vertices with one draw call
+    pub fn flush(&mut self) -> GlResult<()> {
+        if self.vertices.is_empty() {
+            return Ok(());
+        }
+
+        let program = self.program.use_program()?;
+        program.set_projection(self.projection)?;
+        program.set_uniform(c"sampling", UniformKind::Uniform1i(self.texture.sampling()))?;
+        if let Some(texture) = self.texture.texture() {
+            self.core.glActiveTexture(glcore::GL_TEXTURE0)?;
+            self.core.glBindTexture(glcore::GL_TEXTURE_2D, texture)?;
+        }
+
+        self.core.glBindVertexArray(self.vertex_array)?;
+        self.core
+            .glBindBuffer(glcore::GL_ARRAY_BUFFER, self.vertex_buffer)?;
+        // Orphaning the storage lets the driver hand out fresh memory instead of waiting for the
+        // previous draw to finish reading it
+        self.capacity = self.capacity.max(self.vertices.len());
+        self.core.glBufferData(
+            glcore::GL_ARRAY_BUFFER,
+            self.capacity * size_of::<BatchVertex>(),
+            std::ptr::null(),
+            glcore::GL_STREAM_DRAW,
+        )?;
+        self.core.glBufferSubData(
+            glcore::GL_ARRAY_BUFFER,
+            0,
+            size_of_val(self.vertices.as_slice()),
+            self.vertices.as_ptr() as *const c_void,
+        )?;
+        self.core.glDrawArrays(
+            self.primitive.into_opengl_mode(),
+            0,
+            self.vertices.len() as i32,
+        )?;
+        self.core.glBindVertexArray(0)?;
+
+        self.stats.draw_calls += 1;
+        self.stats.vertices += self.vertices.len() as u32;
+        self.vertices.clear();
+        Ok(())
+    }
+
+    /// Drops the queued vertices without drawing them, e.g. when the frame is abandoned
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// Returns what was submitted since the last call, the end of the previous frame
+    pub fn take_stats(&mut self) -> FrameStats {
+        std::mem::take(&mut self.stats)
+    }
+}
+
+impl Drop for Batch {
+    fn drop(&mut self) {
+        let _ = self.core.glDeleteBuffers(1, &self.vertex_buffer);
+        let _ = self.core.glDeleteVertexArrays(1, &self.vertex_array);
+    }
+}
@@ -1,17 +1,19 @@
-use std::ffi::c_void;
+use std::cell::{RefCell, RefMut};
 use std::path::Path;
+use std::rc::Rc;
 
-use glcore::{GL_1_0_g, GL_1_1_g, GL_1_5_g, GL_2_0_g, GL_3_0_g, GLCore, GLCoreError};
+use glcore::{GL_1_0_g, GL_1_4_g, GLCore, GLCoreError};
 
-use crate::opengl::shaders::builtin::{BuiltinShader, NoShader};
-use crate::opengl::shaders::{MatrixShader, NoMatrixShader, UninitShaderProgram};
-use crate::opengl::types::{AsFloatArray, Indices, IndicesBackend, Vec2, Vec2Array};
+use crate::canvas::{BlendMode, Canvas, CanvasStack, Image};
+use crate::opengl::batch::{Batch, BatchTexture, BatchVertex};
+use crate::opengl::image::{Texture, TextureCache};
+use crate::opengl::shaders::UninitShaderProgram;
+use crate::opengl::shaders::builtin::BuiltinShader;
+use crate::opengl::text::{Font, GlyphAtlas, TextOptions};
+use crate::opengl::types::{Mat3, Vec2, Vec4};
 
+use super::shaders::ShaderBundle;
 use super::types::GlResult;
-use super::{
-    shaders::ColorShader,
-    shaders::{ShaderBundle, ShaderProgram},
-};
 
 #[derive(Debug, Clone, Copy)]
 pub enum ElementsMode {
@@ -28,41 +30,125 @@ pub enum ElementsMode {
     TrianglesAdjacency,
 }
 
-impl ElementsMode {
+/// The kinds of primitives `glDrawArrays` assembles every `ElementsMode` into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Primitive {
+    Points,
+    Lines,
+    Triangles,
+}
+
+impl Primitive {
     pub fn into_opengl_mode(self) -> u32 {
         match self {
-            ElementsMode::Points => glcore::GL_POINTS,
-            ElementsMode::LineStrip => glcore::GL_LINE_STRIP,
-            ElementsMode::LineLoop => glcore::GL_LINE_LOOP,
-            ElementsMode::Lines => glcore::GL_LINES,
-            ElementsMode::LineStripAdjacency => glcore::GL_LINE_STRIP_ADJACENCY,
-            ElementsMode::LinesAdjacency => glcore::GL_LINES_ADJACENCY,
-            ElementsMode::TriangleStrip => glcore::GL_TRIANGLE_STRIP,
-            ElementsMode::TriangleFan => glcore::GL_TRIANGLE_FAN,
-            ElementsMode::Triangles => glcore::GL_TRIANGLES,
-            ElementsMode::TriangleStripAdjacency => glcore::GL_TRIANGLE_STRIP_ADJACENCY,
-            ElementsMode::TrianglesAdjacency => glcore::GL_TRIANGLES_ADJACENCY,
+            Primitive::Points => glcore::GL_POINTS,
+            Primitive::Lines => glcore::GL_LINES,
+            Primitive::Triangles => glcore::GL_TRIANGLES,
+        }
+    }
+}
+
+impl ElementsMode {
+    /// Splits `points` into separate primitives the way `glDrawArrays` does for this mode
+    ///
+    /// Every 1, 2 or 3 returned points form a primitive, so shapes of different modes can be
+    /// drawn together. Incomplete primitives at the end are dropped.
+    pub fn assemble(self, points: &[Vec2]) -> (Primitive, Vec<Vec2>) {
+        let count = points.len();
+        match self {
+            ElementsMode::Points => (Primitive::Points, points.to_vec()),
+            ElementsMode::Lines => (
+                Primitive::Lines,
+                points.chunks_exact(2).flatten().copied().collect(),
+            ),
+            ElementsMode::LineStrip => (
+                Primitive::Lines,
+                points.windows(2).flatten().copied().collect(),
+            ),
+            ElementsMode::LineLoop => {
+                let mut lines: Vec<Vec2> = points.windows(2).flatten().copied().collect();
+                if count > 2 {
+                    lines.extend_from_slice(&[points[count - 1], points[0]]);
+                }
+                (Primitive::Lines, lines)
+            }
+            ElementsMode::LinesAdjacency => (
+                Primitive::Lines,
+                points
+                    .chunks_exact(4)
+                    .flat_map(|line| [line[1], line[2]])
+                    .collect(),
+            ),
+            ElementsMode::LineStripAdjacency => {
+                let inner = match count >= 4 {
+                    true => &points[1..count - 1],
+                    false => &[],
+                };
+                (
+                    Primitive::Lines,
+                    inner.windows(2).flatten().copied().collect(),
+                )
+            }
+            ElementsMode::Triangles => (
+                Primitive::Triangles,
+                points.chunks_exact(3).flatten().copied().collect(),
+            ),
+            ElementsMode::TriangleStrip => (
+                Primitive::Triangles,
+                points.windows(3).flatten().copied().collect(),
+            ),
+            ElementsMode::TriangleFan => (
+                Primitive::Triangles,
+                (1..count.saturating_sub(1))
+                    .flat_map(|index| [points[0], points[index], points[index + 1]])
+                    .collect(),
+            ),
+            ElementsMode::TrianglesAdjacency => (
+                Primitive::Triangles,
+                points
+                    .chunks_exact(6)
+                    .flat_map(|triangle| [triangle[0], triangle[2], triangle[4]])
+                    .collect(),
+            ),
+            ElementsMode::TriangleStripAdjacency => (
+                Primitive::Triangles,
+                (0..count.saturating_sub(4))
+                    .step_by(2)
+                    .flat_map(|index| [points[index], points[index + 2], points[index + 4]])
+                    .collect(),
+            ),
         }
     }
 }
 
+/// Draws with the builtin shaders, coordinates are pixels with the origin in the top-left corner
+/// and y pointing down
+///
+/// Everything is drawn through a `Batch`, which applies the projection when it is flushed.
 #[derive(Debug, Clone)]
-pub struct SimpleGL<State> {
+pub struct SimpleGL {
     core: GLCore,
-    current_shader: Option<ShaderProgram<State>>,
+    stack: CanvasStack,
+    glyphs: Option<Rc<RefCell<GlyphAtlas>>>,
+    textures: Option<Rc<RefCell<TextureCache>>>,
+    batch: Option<Rc<RefCell<Batch>>>,
+    /// Size of the coordinate space, the viewport in pixels if `None`
+    projection: Option<Vec2>,
 }
 
-impl SimpleGL<NoShader> {
-    pub fn new(core: GLCore) -> SimpleGL<NoShader> {
+impl SimpleGL {
+    pub fn new(core: GLCore) -> SimpleGL {
         SimpleGL {
             core,
-            current_shader: None,
+            stack: CanvasStack::default(),
+            glyphs: None,
+            textures: None,
+            batch: None,
+            projection: None,
         }
     }
-}
 
-impl<S> SimpleGL<S> {
-    pub fn new_shader_program(
+    pub fn new_shader_program<S>(
         &self,
         vertex: String,
         fragment: String,
@@ -77,7 +163,7 @@ impl<S> SimpleGL<S> {
         builtin.into_program(self.core)
     }
 
-    pub fn new_shader_program_from_files<P0: AsRef<Path>, P1: AsRef<Path>>(
+    pub fn new_shader_program_from_files<S, P0: AsRef<Path>, P1: AsRef<Path>>(
         &self,
         vertex: P0,
         fragment: P1,
@@ -85,172 +171,349 @@ impl<S> SimpleGL<S> {
         ShaderBundle::new_from_files(self.core, vertex, fragment)?.link()
     }
 
+    /// Overwrites the framebuffer with the straight alpha color, which is stored premultiplied
     pub fn clear(&self, r: f32, g: f32, b: f32, a: f32) -> GlResult<()> {
-        self.core.glClearColor(r, g, b, a)?;
+        self.flush()?;
+        self.core.glClearColor(r * a, g * a, b * a, a)?;
         self.core
             .glClear(glcore::GL_COLOR_BUFFER_BIT | glcore::GL_DEPTH_BUFFER_BIT)
     }
 
-    pub fn with_shader<N>(self, shader: ShaderProgram<N>) -> SimpleGL<N> {
+    /// Sets the blend state of the context, which stays until it is changed again
+    ///
+    /// The alpha channel is always blended as premultiplied, so the framebuffer holds
+    /// premultiplied colors for the compositor whatever the mode.
+    pub fn set_blend_mode(&self, mode: BlendMode) -> GlResult<()> {
+        self.flush()?;
+        let (source, destination) = match mode {
+            BlendMode::Replace => return self.core.glDisable(glcore::GL_BLEND),
+            BlendMode::Normal => (glcore::GL_SRC_ALPHA, glcore::GL_ONE_MINUS_SRC_ALPHA),
+            BlendMode::Additive => (glcore::GL_SRC_ALPHA, glcore::GL_ONE),
+            BlendMode::Premultiplied => (glcore::GL_ONE, glcore::GL_ONE_MINUS_SRC_ALPHA),
+        };
+        self.core.glEnable(glcore::GL_BLEND)?;
+        self.core.glBlendFuncSeparate(
+            source,
+            destination,
+            glcore::GL_ONE,
+            glcore::GL_ONE_MINUS_SRC_ALPHA,
+        )
+    }
+
+    /// Size of the current viewport in pixels
+    pub fn viewport_size(&self) -> GlResult<Vec2> {
+        let mut viewport = [0; 4];
+        self.core
+            .glGetIntegerv(glcore::GL_VIEWPORT, viewport.as_mut_ptr())?;
+        Ok(Vec2::new(viewport[2] as f32, viewport[3] as f32))
+    }
+
+    /// Stretches `size` coordinates over the viewport, e.g. the logical size of a surface whose
+    /// buffer is scaled up
+    pub fn with_projection(self, size: Vec2) -> SimpleGL {
         SimpleGL {
-            core: self.core,
-            current_shader: Some(shader),
+            projection: Some(size),
+            ..self
+        }
+    }
+
+    /// Size of the coordinate space
+    pub fn projection_size(&self) -> GlResult<Vec2> {
+        match self.projection {
+            Some(size) => Ok(size),
+            None => self.viewport_size(),
         }
     }
-}
 
-impl<S: ColorShader + MatrixShader> SimpleGL<S> {
-    pub fn draw_rectangle(&self, pos: Vec2, size: Vec2) -> GlResult<()> {
-        self.current_shader
+    /// Pixels of the viewport per unit of the coordinate space
+    fn pixel_ratio(&self) -> GlResult<Vec2> {
+        Ok(self.viewport_size()? / self.projection_size()?)
+    }
+
+    /// Maps device coordinates onto `width` by `height` pixels, the viewport is kept by the
+    /// context so it has to be set for every surface drawn to
+    pub fn set_viewport(&self, width: u32, height: u32) -> GlResult<()> {
+        self.core.glViewport(0, 0, width as i32, height as i32)
+    }
+
+    /// Sets the glyph atlas used by `Canvas::draw_text`
+    pub fn with_glyphs(self, atlas: Rc<RefCell<GlyphAtlas>>) -> SimpleGL {
+        SimpleGL {
+            glyphs: Some(atlas),
+            ..self
+        }
+    }
+
+    /// Sets the texture cache used by `Canvas::draw_image_file`
+    pub fn with_textures(self, textures: Rc<RefCell<TextureCache>>) -> SimpleGL {
+        SimpleGL {
+            textures: Some(textures),
+            ..self
+        }
+    }
+
+    /// Sets the batch every `Canvas` method draws through
+    pub fn with_batch(self, batch: Rc<RefCell<Batch>>) -> SimpleGL {
+        SimpleGL {
+            batch: Some(batch),
+            ..self
+        }
+    }
+
+    /// The batch, with its projection matching this one
+    fn batch(&self) -> GlResult<RefMut<'_, Batch>> {
+        let batch = self
+            .batch
             .as_ref()
-            .ok_or(GLCoreError::InvalidOperation("No shader loaded"))?
-            .set_matrix(pos, size)?;
-
-        let vertices_backend = [
-            Vec2::new(0.0, 0.0),
-            Vec2::new(0.0, 1.0),
-            Vec2::new(1.0, 0.0),
-            Vec2::new(1.0, 1.0),
-        ];
-        let indices_backend = [0, 1, 2, 3];
-        let indices = Indices::<u32>::new(&indices_backend);
-        let vertices = Vec2Array::new(&vertices_backend);
+            .ok_or(GLCoreError::InvalidOperation("No batch loaded"))?;
+        let mut batch = batch
+            .try_borrow_mut()
+            .map_err(|_| GLCoreError::InvalidOperation("Batch is already in use"))?;
+        batch.set_projection(self.projection_size()?)?;
+        Ok(batch)
+    }
 
-        self.draw_polygon_indices(ElementsMode::TriangleStrip, vertices, indices)
+    /// Draws everything queued in the batch, required before changing any GL state it depends on
+    pub fn flush(&self) -> GlResult<()> {
+        match &self.batch {
+            Some(batch) => batch
+                .try_borrow_mut()
+                .map_err(|_| GLCoreError::InvalidOperation("Batch is already in use"))?
+                .flush(),
+            None => Ok(()),
+        }
     }
-}
 
-impl<S: ColorShader + NoMatrixShader> SimpleGL<S> {
-    pub fn draw_rectangle_generic(&self, topleft: Vec2, size: Vec2) -> GlResult<()> {
+    /// Draws `texture` stretched over the rectangle at `pos`, its colors multiplied by `tint`
+    ///
+    /// The texture is only sampled once the batch is flushed, it must stay alive until then.
+    pub fn draw_image(&self, texture: &Texture, pos: Vec2, size: Vec2, tint: Vec4) -> GlResult<()> {
+        let transform = self.stack.transform();
+        let vertex = |u: f32, v: f32| {
+            let corner = transform.transform_point(pos + size * Vec2::new(u, v));
+            BatchVertex::new(corner, tint, Vec2::new(u, v))
+        };
         let vertices = [
-            topleft,
-            topleft + size * Vec2::new(1.0, 0.0),
-            topleft + size * Vec2::new(0.0, 1.0),
-            topleft + size,
+            vertex(0.0, 0.0),
+            vertex(1.0, 0.0),
+            vertex(0.0, 1.0),
+            vertex(1.0, 0.0),
+            vertex(1.0, 1.0),
+            vertex(0.0, 1.0),
         ];
-        let indices = [0, 1, 2, 3];
-        self.draw_polygon_indices(
-            ElementsMode::TriangleStrip,
-            Vec2Array::new(&vertices),
-            Indices::<u32>::new(&indices),
+        let texture = BatchTexture::Color(texture.get_texture());
+        self.batch()?.push(Primitive::Triangles, texture, &vertices)
+    }
+
+    /// Transforms the rectangles drawn until the matching `pop_transform`, on top of the pushed
+    /// ones
+    pub fn push_transform(&mut self, transform: Mat3) {
+        self.stack.push_transform(transform);
+    }
+
+    pub fn pop_transform(&mut self) {
+        self.stack.pop_transform();
+    }
+
+    /// Restricts drawing to the rectangle at `pos` until the matching `pop_clip`
+    pub fn push_clip(&mut self, pos: Vec2, size: Vec2) -> GlResult<()> {
+        self.stack.push_clip(pos, size);
+        self.apply_clip()
+    }
+
+    pub fn pop_clip(&mut self) -> GlResult<()> {
+        self.stack.pop_clip();
+        self.apply_clip()
+    }
+
+    /// Restricts drawing to the clip rectangle on top of the stack, disables clipping if empty
+    pub fn apply_clip(&self) -> GlResult<()> {
+        self.flush()?;
+        let Some(clip) = self.stack.clip() else {
+            return self.core.glDisable(glcore::GL_SCISSOR_TEST);
+        };
+        // Scissor boxes are in pixels of the viewport, with the origin in the bottom-left corner
+        let viewport = self.viewport_size()?;
+        let ratio = self.pixel_ratio()?;
+        let min = clip.min * ratio;
+        let max = clip.max * ratio;
+        self.core.glEnable(glcore::GL_SCISSOR_TEST)?;
+        self.core.glScissor(
+            min.x.round() as i32,
+            (viewport.y - max.y).round() as i32,
+            (max.x.round() - min.x.round()).max(0.0) as i32,
+            (max.y.round() - min.y.round()).max(0.0) as i32,
         )
     }
 }
 
-impl<S: ColorShader> SimpleGL<S> {
-    pub fn draw_polygon<V>(&self, mode: ElementsMode, vertices: V) -> GlResult<()>
-    where
-        V: AsFloatArray<Backend = Vec2>,
-    {
-        let vert_ref = vertices
-            .as_contiguous_block()
-            .ok_or(GLCoreError::InvalidValue(
-                "Polygon vector cannot be zero sized",
-            ))?;
-
-        // Create the attribute buffer
-        let mut vertex_attributes = 0;
-        self.core.glGenVertexArrays(1, &mut vertex_attributes)?;
-        self.core.glBindVertexArray(vertex_attributes)?;
-
-        // Create & copy over data to the data buffer
-        let mut vertex_buffer = 0;
-        self.core.glGenBuffers(1, &mut vertex_buffer)?;
-        self.core
-            .glBindBuffer(glcore::GL_ARRAY_BUFFER, vertex_buffer)?;
-        self.core.glBufferData(
-            glcore::GL_ARRAY_BUFFER,
-            std::mem::size_of_val(vert_ref),
-            vert_ref.as_ptr() as *const c_void,
-            glcore::GL_STATIC_DRAW,
-        )?;
-
-        // Assign attribute to attribute buffer
-        self.core.glEnableVertexAttribArray(0)?;
-        self.core.glVertexAttribPointer(
-            0,
-            V::FLOATS_PER_ELEMENT as i32,
-            glcore::GL_FLOAT,
-            glcore::GL_FALSE as u8,
-            0,
-            std::ptr::null(),
-        )?;
-
-        self.core.glDrawArrays(
-            mode.into_opengl_mode(),
-            0,
-            (vert_ref.len() / V::FLOATS_PER_ELEMENT) as i32,
-        )?;
-        self.core.glDisableVertexAttribArray(0)?;
-        self.core.glDeleteBuffers(1, [vertex_buffer].as_ptr())?;
-        Ok(())
-    }
-
-    pub fn draw_polygon_indices<V, B>(
-        &self,
-        mode: ElementsMode,
-        vertices: V,
-        indices: Indices<B>,
-    ) -> GlResult<()>
-    where
-        V: AsFloatArray<Backend = Vec2>,
-        B: IndicesBackend,
-    {
-        let vert_ref = vertices
-            .as_contiguous_block()
-            .ok_or(GLCoreError::InvalidValue(
-                "Polygon vector cannot be zero sized",
-            ))?;
-
-        // Create the attribute buffer
-        let mut vertex_attributes = 0;
-        self.core.glGenVertexArrays(1, &mut vertex_attributes)?;
-        self.core.glBindVertexArray(vertex_attributes)?;
-
-        // Create & copy over data to the data buffer
-        let mut vertex_buffer = 0;
-        self.core.glGenBuffers(1, &mut vertex_buffer)?;
-        self.core
-            .glBindBuffer(glcore::GL_ARRAY_BUFFER, vertex_buffer)?;
-        self.core.glBufferData(
-            glcore::GL_ARRAY_BUFFER,
-            std::mem::size_of_val(vert_ref),
-            vert_ref.as_ptr() as *const c_void,
-            glcore::GL_STATIC_DRAW,
-        )?;
-
-        // Same as data buffer, but for indices
-        let mut index_buffer = 0;
-        self.core.glGenBuffers(1, &mut index_buffer)?;
-        self.core
-            .glBindBuffer(glcore::GL_ELEMENT_ARRAY_BUFFER, index_buffer)?;
-        self.core.glBufferData(
-            glcore::GL_ELEMENT_ARRAY_BUFFER,
-            indices.len() * std::mem::size_of::<B::Backend>(),
-            indices.ptr(),
-            glcore::GL_STATIC_DRAW,
-        )?;
-
-        // Assign attribute to attribute buffer
-        self.core.glEnableVertexAttribArray(0)?;
-        self.core.glVertexAttribPointer(
-            0,
-            V::FLOATS_PER_ELEMENT as i32,
-            glcore::GL_FLOAT,
-            glcore::GL_FALSE as u8,
-            0,
-            std::ptr::null(),
-        )?;
-
-        self.core.glDrawElements(
-            mode.into_opengl_mode(),
-            indices.len() as i32,
-            B::get_opengl_type(),
-            std::ptr::null(),
-        )?;
-        self.core.glDisableVertexAttribArray(0)?;
-        self.core
-            .glDeleteBuffers(2, [vertex_buffer, index_buffer].as_ptr())?;
-        Ok(())
+impl Canvas for SimpleGL {
+    fn clear(&mut self, color: Vec4) -> GlResult<()> {
+        SimpleGL::clear(self, color.x, color.y, color.z, color.w)
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) -> GlResult<()> {
+        self.stack.set_blend_mode(mode);
+        SimpleGL::set_blend_mode(self, mode)
+    }
+
+    fn fill_rect(&mut self, pos: Vec2, size: Vec2, color: Vec4) -> GlResult<()> {
+        let corners = [
+            pos,
+            pos + Vec2::new(size.x, 0.0),
+            pos + Vec2::new(0.0, size.y),
+            pos + size,
+        ];
+        self.fill_polygon(ElementsMode::TriangleStrip, &corners, color)
+    }
+
+    fn fill_polygon(&mut self, mode: ElementsMode, points: &[Vec2], color: Vec4) -> GlResult<()> {
+        let (primitive, points) = mode.assemble(points);
+        if points.is_empty() {
+            return Ok(());
+        }
+        // Transformed here rather than in the shader, so differently transformed shapes still
+        // share a draw call
+        let transform = self.stack.transform();
+        let vertices: Vec<BatchVertex> = points
+            .iter()
+            .map(|point| BatchVertex::flat(transform.transform_point(*point), color))
+            .collect();
+        self.batch()?.push(primitive, BatchTexture::None, &vertices)
+    }
+
+    fn stroke_path(&mut self, points: &[Vec2], closed: bool, color: Vec4) -> GlResult<()> {
+        let mode = match closed {
+            true => ElementsMode::LineLoop,
+            false => ElementsMode::LineStrip,
+        };
+        self.fill_polygon(mode, points, color)
+    }
+
+    /// Glyphs are rasterized at the size they cover in the viewport, so text stays sharp when the
+    /// coordinate space or the transform scales it up
+    fn draw_text(
+        &mut self,
+        font: &Font,
+        text: &str,
+        pos: Vec2,
+        options: &TextOptions,
+    ) -> GlResult<()> {
+        let atlas = self
+            .glyphs
+            .as_ref()
+            .ok_or(GLCoreError::InvalidOperation("No glyph atlas loaded"))?;
+        let mut atlas = atlas
+            .try_borrow_mut()
+            .map_err(|_| GLCoreError::InvalidOperation("Glyph atlas is already in use"))?;
+        let mut batch = self.batch()?;
+
+        let transform = self.stack.transform();
+        let scale = self.pixel_ratio()?.y * transform.scale_factor();
+        if !scale.is_normal() {
+            // Squashed onto a line or a point, there is nothing to rasterize
+            return Ok(());
+        }
+        let options = &TextOptions {
+            size: options.size * scale,
+            ..*options
+        };
+        let layout = font.layout(text, options);
+        let glyphs = match layout
+            .glyphs()
+            .iter()
+            .map(|glyph| atlas.get_or_insert(font, glyph.key))
+            .collect::<GlResult<Vec<_>>>()
+        {
+            Ok(glyphs) => glyphs,
+            Err(GLCoreError::OutOfMemory(_)) => {
+                // Queued text still samples the glyphs which are about to be overwritten
+                batch.flush()?;
+                atlas.clear();
+                layout
+                    .glyphs()
+                    .iter()
+                    .map(|glyph| atlas.get_or_insert(font, glyph.key))
+                    .collect::<GlResult<Vec<_>>>()?
+            }
+            Err(err) => return Err(err),
+        };
+
+        // The layout is in pixels of the viewport, convert it back to the coordinate space and
+        // transform every corner, so glyphs rotate along with everything else
+        let pixel = Vec2::new(1.0 / scale, 1.0 / scale);
+        let mut vertices = Vec::with_capacity(glyphs.len() * 6);
+        for (position, glyph) in layout.glyphs().iter().zip(glyphs) {
+            if position.width == 0 || position.height == 0 {
+                continue;
+            }
+            let min = pos + Vec2::new(position.x, position.y) * pixel;
+            let max = min + Vec2::new(position.width as f32, position.height as f32) * pixel;
+            let (uv_min, uv_max) = (glyph.uv_min, glyph.uv_max);
+            let vertex = |x: f32, y: f32, u: f32, v: f32| {
+                let point = transform.transform_point(Vec2::new(x, y));
+                BatchVertex::new(point, options.color, Vec2::new(u, v))
+            };
+            vertices.extend_from_slice(&[
+                vertex(min.x, min.y, uv_min.x, uv_min.y),
+                vertex(max.x, min.y, uv_max.x, uv_min.y),
+                vertex(min.x, max.y, uv_min.x, uv_max.y),
+                vertex(max.x, min.y, uv_max.x, uv_min.y),
+                vertex(max.x, max.y, uv_max.x, uv_max.y),
+                vertex(min.x, max.y, uv_min.x, uv_max.y),
+            ]);
+        }
+        let texture = BatchTexture::Coverage(atlas.get_texture());
+        batch.push(Primitive::Triangles, texture, &vertices)
+    }
+
+    /// Uploads `image` for this draw only, use `draw_image_file` for images drawn every frame
+    fn draw_image(&mut self, image: Image, pos: Vec2, size: Vec2) -> GlResult<()> {
+        let texture = Texture::from_image(self.core, image)?;
+        let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
+        SimpleGL::draw_image(self, &texture, pos, size, white)?;
+        // The texture is deleted once it goes out of scope
+        self.flush()
+    }
+
+    /// Images are decoded at the size they cover in the viewport
+    fn draw_image_file(&mut self, path: &Path, pos: Vec2, size: Vec2, tint: Vec4) -> GlResult<()> {
+        let textures = self
+            .textures
+            .as_ref()
+            .ok_or(GLCoreError::InvalidOperation("No texture cache loaded"))?;
+        let mut textures = textures
+            .try_borrow_mut()
+            .map_err(|_| GLCoreError::InvalidOperation("Texture cache is already in use"))?;
+
+        let pixels = size * self.pixel_ratio()?;
+        let (width, height) = (pixels.x.abs().round() as u32, pixels.y.abs().round() as u32);
+        let texture = match textures.get_or_load(path, width, height) {
+            Ok(texture) => texture,
+            Err(GLCoreError::OutOfMemory(_)) => {
+                // Queued images still sample the textures which are about to be deleted
+                self.flush()?;
+                textures.clear();
+                textures.get_or_load(path, width, height)?
+            }
+            Err(err) => return Err(err),
+        };
+        SimpleGL::draw_image(self, &texture, pos, size, tint)
+    }
+
+    fn push_clip(&mut self, pos: Vec2, size: Vec2) -> GlResult<()> {
+        SimpleGL::push_clip(self, pos, size)
+    }
+
+    fn pop_clip(&mut self) -> GlResult<()> {
+        SimpleGL::pop_clip(self)
+    }
+
+    fn push_transform(&mut self, transform: Mat3) {
+        SimpleGL::push_transform(self, transform);
+    }
+
+    fn pop_transform(&mut self) {
+        SimpleGL::pop_transform(self);
     }
 }
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use glcore::{GL_1_0_g, GL_1_1_g, GLCore, GLCoreError};
+use image::imageops::FilterType;
+use resvg::{tiny_skia, usvg};
+
+use super::types::GlResult;
+use crate::canvas::Image;
+
+/// Cached images are dropped all at once past this amount, icons rarely come in that many sizes
+pub const MAX_CACHED_IMAGES: usize = 256;
+
+/// The path and size in pixels an image was decoded at
+pub type ImageKey = (PathBuf, u32, u32);
+
+/// RGBA pixels with straight alpha decoded from a file, rows are not padded
+#[derive(Debug, Clone)]
+pub struct DecodedImage {
+    pixels: Vec<u8>,
+    width: usize,
+    height: usize,
+}
+
+impl DecodedImage {
+    /// Decodes the PNG, JPEG or SVG at `path` to exactly `width` by `height` pixels
+    ///
+    /// SVGs are rasterized at that size, so they stay sharp at any scale. Other images are
+    /// resized, which looks better than letting the sampler stretch them.
+    pub fn load<P: AsRef<Path>>(path: P, width: u32, height: u32) -> GlResult<DecodedImage> {
+        let path = path.as_ref();
+        let (width, height) = (width.max(1), height.max(1));
+        let bytes = std::fs::read(path)
+            .map_err(|_| GLCoreError::InvalidValue("Invalid image file path"))?;
+        let is_svg = path
+            .extension()
+            .is_some_and(|extension| extension == "svg" || extension == "svgz");
+        let pixels = match is_svg {
+            true => rasterize_svg(&bytes, width, height)?,
+            false => image::load_from_memory(&bytes)
+                .map_err(|_| GLCoreError::InvalidValue("Image data could not be decoded"))?
+                .resize_exact(width, height, FilterType::Triangle)
+                .into_rgba8()
+                .into_raw(),
+        };
+        Ok(DecodedImage {
+            pixels,
+            width: width as usize,
+            height: height as usize,
+        })
+    }
+
+    pub fn as_image(&self) -> Image<'_> {
+        Image {
+            pixels: &self.pixels,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+fn rasterize_svg(bytes: &[u8], width: u32, height: u32) -> GlResult<Vec<u8>> {
+    let tree = usvg::Tree::from_data(bytes, &usvg::Options::default())
+        .map_err(|_| GLCoreError::InvalidValue("SVG data could not be parsed"))?;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or(GLCoreError::InvalidValue("SVG size is too large"))?;
+    let size = tree.size();
+    let transform = tiny_skia::Transform::from_scale(
+        width as f32 / size.width(),
+        height as f32 / size.height(),
+    );
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+    // tiny-skia draws premultiplied, everything else deals in straight alpha
+    Ok(pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect())
+}
+
+/// An RGBA texture with straight alpha, deleted when dropped
+#[derive(Debug)]
+pub struct Texture {
+    core: GLCore,
+    texture: u32,
+}
+
+impl Texture {
+    /// Uploads `pixels`, which must hold `width * height` RGBA pixels without row padding
+    pub fn from_rgba(core: GLCore, pixels: &[u8], width: u32, height: u32) -> GlResult<Texture> {
+        if width == 0 || height == 0 || pixels.len() < width as usize * height as usize * 4 {
+            return Err(GLCoreError::InvalidValue(
+                "Texture pixels don't match its size",
+            ));
+        }
+
+        let mut texture = 0;
+        core.glGenTextures(1, &mut texture)?;
+        // Owned right away, so the texture is deleted if the upload fails
+        let texture = Texture { core, texture };
+        core.glBindTexture(glcore::GL_TEXTURE_2D, texture.texture)?;
+        core.glPixelStorei(glcore::GL_UNPACK_ALIGNMENT, 1)?;
+        core.glTexImage2D(
+            glcore::GL_TEXTURE_2D,
+            0,
+            glcore::GL_RGBA8 as i32,
+            width as i32,
+            height as i32,
+            0,
+            glcore::GL_RGBA,
+            glcore::GL_UNSIGNED_BYTE,
+            pixels.as_ptr() as *const c_void,
+        )?;
+        for (pname, param) in [
+            (glcore::GL_TEXTURE_MIN_FILTER, glcore::GL_LINEAR),
+            (glcore::GL_TEXTURE_MAG_FILTER, glcore::GL_LINEAR),
+            (glcore::GL_TEXTURE_WRAP_S, glcore::GL_CLAMP_TO_EDGE),
+            (glcore::GL_TEXTURE_WRAP_T, glcore::GL_CLAMP_TO_EDGE),
+        ] {
+            core.glTexParameteri(glcore::GL_TEXTURE_2D, pname, param)?;
+        }
+        Ok(texture)
+    }
+
+    pub fn from_image(core: GLCore, image: Image) -> GlResult<Texture> {
+        Texture::from_rgba(core, image.pixels, image.width as u32, image.height as u32)
+    }
+
+    pub fn get_texture(&self) -> u32 {
+        self.texture
+    }
+}
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        let _ = self.core.glDeleteTextures(1, &self.texture);
+    }
+}
+
+/// Image files uploaded as textures, keyed by their path and the size they were decoded at
+#[derive(Debug)]
+pub struct TextureCache {
+    core: GLCore,
+    textures: HashMap<ImageKey, Rc<Texture>>,
+}
+
+impl TextureCache {
+    pub fn new(core: GLCore) -> TextureCache {
+        TextureCache {
+            core,
+            textures: HashMap::new(),
+        }
+    }
+
+    /// Deletes every texture, anything still drawing with them must be flushed first
+    pub fn clear(&mut self) {
+        self.textures.clear();
+    }
+
+    /// Returns the texture of the image at `path` decoded at `width` by `height` pixels, loading
+    /// it if it isn't present yet
+    ///
+    /// Fails with `GLCoreError::OutOfMemory` when the cache is full, `clear` it and try again.
+    pub fn get_or_load<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        width: u32,
+        height: u32,
+    ) -> GlResult<Rc<Texture>> {
+        let key = (path.as_ref().to_path_buf(), width, height);
+        if let Some(texture) = self.textures.get(&key) {
+            return Ok(texture.clone());
+        }
+        if self.textures.len() >= MAX_CACHED_IMAGES {
+            return Err(GLCoreError::OutOfMemory("Texture cache is full"));
+        }
+
+        let image = DecodedImage::load(&key.0, width, height)?;
+        let texture = Rc::new(Texture::from_image(self.core, image.as_image())?);
+        self.textures.insert(key, texture.clone());
+        Ok(texture)
+    }
+}
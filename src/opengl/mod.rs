@@ -1,3 +1,6 @@
 pub mod shaders;
 pub mod types;
 pub mod highlevel;
+pub mod text;
+pub mod batch;
+pub mod image;
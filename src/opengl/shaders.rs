@@ -4,7 +4,7 @@ use std::ffi::{CStr, CString};
 use std::marker::PhantomData;
 use std::path::Path;
 
-use crate::opengl::types::{Vec2, Vec4};
+use crate::opengl::types::{Mat4, Vec2};
 
 use super::types::GlResult;
 
@@ -57,37 +57,8 @@ pub mod builtin {
         fn into_program(self, core: GLCore) -> GlResult<UninitShaderProgram<Self::Properties>>;
     }
 
-    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-    pub struct NoShader;
-
-    impl BuiltinShader for NoShader {
-        type Properties = NoShader;
-
-        fn get_vertex_static(self) -> &'static str {
-            ""
-        }
-
-        fn get_fragment_static(self) -> &'static str {
-            ""
-        }
-
-        fn get_vertex(self) -> String {
-            String::new()
-        }
-
-        fn get_fragment(self) -> String {
-            String::new()
-        }
-
-        fn into_program(self, _: GLCore) -> GlResult<UninitShaderProgram<Self::Properties>> {
-            Err(glcore::GLCoreError::InvalidOperation(
-                "Cannot create a shader program for the NoShader builtin",
-            ))
-        }
-    }
-
-    builtin_shader!(FlatColor <- "flat_color" | ColorShader:NoMatrixShader);
-    builtin_shader!(QuadColor <- "quad_color" | ColorShader:MatrixShader);
+    builtin_shader!(Batched <- "batch" | ProjectionShader);
+    builtin_shader!(BatchedEs <- "batch_es" | ProjectionShader);
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -461,9 +432,8 @@ impl<F> UninitShaderProgram<F> {
     }
 }
 
-pub trait ColorShader {}
-pub trait MatrixShader {}
-pub trait NoMatrixShader {}
+/// Has a `projection` matrix uniform, which is the identity until set
+pub trait ProjectionShader {}
 
 #[derive(Debug, Clone, Copy)]
 pub struct ShaderProgram<F> {
@@ -481,24 +451,14 @@ impl<F> ShaderProgram<F> {
     }
 }
 
-impl<F: ColorShader> ShaderProgram<F> {
-    pub fn set_color(&self, color: Vec4) -> GlResult<()> {
-        self.set_uniform(
-            c"color",
-            UniformKind::Uniform4f(color.x, color.y, color.z, color.w),
-        )
-    }
-
-    pub fn set_color_rgba(&self, r: f32, g: f32, b: f32, a: f32) -> GlResult<()> {
-        self.set_uniform(c"color", UniformKind::Uniform4f(r, g, b, a))
-    }
-}
-
-impl<F: ColorShader> ShaderProgram<F> {
-    pub fn set_matrix(&self, pos: Vec2, size: Vec2) -> GlResult<()> {
+impl<F: ProjectionShader> ShaderProgram<F> {
+    /// Maps `size` pixels with the origin in the top-left corner and y pointing down onto the
+    /// viewport, with an orthographic projection
+    pub fn set_projection(&self, size: Vec2) -> GlResult<()> {
+        let projection = Mat4::ortho(0.0, size.x, size.y, 0.0, -1.0, 1.0);
         self.set_uniform(
-            c"matrix",
-            UniformKind::Uniform4f(pos.x, pos.y, size.x, size.y),
+            c"projection",
+            UniformKind::UniformMatrix4fv(1, false, projection.as_slice()),
         )
     }
 }
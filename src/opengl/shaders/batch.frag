@@ -0,0 +1,18 @@
+#version 330 core
+
+uniform sampler2D tex;
+uniform int sampling = 0; // 0: color only, 1: alpha multiplied by the red channel, 2: color multiplied by the texture
+
+in vec4 vertexColor;
+in vec2 vertexUv;
+out vec4 outColor;
+
+void main() {
+    if (sampling == 1) {
+        outColor = vec4(vertexColor.rgb, vertexColor.a * texture(tex, vertexUv).r);
+    } else if (sampling == 2) {
+        outColor = vertexColor * texture(tex, vertexUv);
+    } else {
+        outColor = vertexColor;
+    }
+}
@@ -0,0 +1,16 @@
+#version 330 core
+
+layout(location = 0) in vec2 pos;
+layout(location = 1) in vec4 color;
+layout(location = 2) in vec2 uv;
+
+uniform mat4 projection = mat4(1.0f);
+
+out vec4 vertexColor;
+out vec2 vertexUv;
+
+void main() {
+    vertexColor = color;
+    vertexUv = uv;
+    gl_Position = projection * vec4(pos.xy, 0.0, 1.0);
+}
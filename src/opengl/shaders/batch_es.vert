@@ -0,0 +1,17 @@
+#version 300 es
+
+layout(location = 0) in vec2 pos;
+layout(location = 1) in vec4 color;
+layout(location = 2) in vec2 uv;
+
+// GLSL ES has no uniform initializers, the batch sets every uniform before drawing
+uniform mat4 projection;
+
+out vec4 vertexColor;
+out vec2 vertexUv;
+
+void main() {
+    vertexColor = color;
+    vertexUv = uv;
+    gl_Position = projection * vec4(pos.xy, 0.0, 1.0);
+}
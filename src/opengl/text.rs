@@ -0,0 +1,217 @@
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::path::{Path, PathBuf};
+
+use fontdue::layout::{CoordinateSystem, GlyphRasterConfig, Layout, LayoutSettings, TextStyle};
+use fontdue::{Font as FontdueFont, FontSettings, Metrics};
+use glcore::{GL_1_0_g, GL_1_1_g, GLCore, GLCoreError};
+
+use super::types::{GlResult, Vec2, Vec4};
+
+const ATLAS_SIZE: u32 = 1024;
+/// Empty pixels between glyphs, prevents linear filtering from bleeding into neighbours
+const ATLAS_PADDING: u32 = 1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct TextOptions {
+    /// Font size in pixels
+    pub size: f32,
+    pub color: Vec4,
+    /// Height of a line as a multiplier of the font's default line height
+    pub line_height: f32,
+}
+
+impl Default for TextOptions {
+    fn default() -> Self {
+        Self {
+            size: 16.0,
+            color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+            line_height: 1.0,
+        }
+    }
+}
+
+pub struct Font {
+    inner: FontdueFont,
+}
+
+impl Font {
+    pub fn from_bytes(bytes: &[u8]) -> GlResult<Font> {
+        FontdueFont::from_bytes(bytes, FontSettings::default())
+            .map(|inner| Font { inner })
+            .map_err(|_| GLCoreError::InvalidValue("Font data could not be parsed"))
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> GlResult<Font> {
+        let bytes =
+            std::fs::read(path).map_err(|_| GLCoreError::InvalidValue("Invalid font file path"))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Lays out `text` with its top-left corner at the origin, in pixels
+    pub fn layout(&self, text: &str, options: &TextOptions) -> Layout {
+        let mut layout = Layout::new(CoordinateSystem::PositiveYDown);
+        layout.reset(&LayoutSettings {
+            line_height: options.line_height,
+            ..LayoutSettings::default()
+        });
+        layout.append(&[&self.inner], &TextStyle::new(text, options.size, 0));
+        layout
+    }
+
+    /// Rasterizes a single glyph of a layout, one byte of coverage per pixel
+    pub fn rasterize(&self, key: GlyphRasterConfig) -> (Metrics, Vec<u8>) {
+        self.inner.rasterize_config(key)
+    }
+
+    /// Size in pixels of the bounding box of `text`
+    pub fn measure(&self, text: &str, options: &TextOptions) -> Vec2 {
+        let layout = self.layout(text, options);
+        let width = layout
+            .glyphs()
+            .iter()
+            .map(|glyph| glyph.x + glyph.width as f32)
+            .fold(0.0, f32::max);
+        Vec2::new(width, layout.height())
+    }
+}
+
+/// Fonts loaded from disk, keyed by their path
+#[derive(Default)]
+pub struct FontCache {
+    fonts: HashMap<PathBuf, Font>,
+}
+
+impl FontCache {
+    pub fn get_or_load<P: AsRef<Path>>(&mut self, path: P) -> GlResult<&Font> {
+        let path = path.as_ref();
+        if !self.fonts.contains_key(path) {
+            self.fonts
+                .insert(path.to_path_buf(), Font::from_file(path)?);
+        }
+        Ok(&self.fonts[path])
+    }
+}
+
+/// Location of a rasterized glyph inside the atlas, in texture coordinates
+#[derive(Debug, Clone, Copy)]
+pub struct AtlasGlyph {
+    pub uv_min: Vec2,
+    pub uv_max: Vec2,
+}
+
+/// Single channel texture holding rasterized glyphs, packed in rows (shelves)
+#[derive(Debug)]
+pub struct GlyphAtlas {
+    core: GLCore,
+    texture: u32,
+    cursor_x: u32,
+    cursor_y: u32,
+    row_height: u32,
+    glyphs: HashMap<GlyphRasterConfig, AtlasGlyph>,
+}
+
+impl GlyphAtlas {
+    pub fn new(core: GLCore) -> GlResult<GlyphAtlas> {
+        let mut texture = 0;
+        core.glGenTextures(1, &mut texture)?;
+        core.glBindTexture(glcore::GL_TEXTURE_2D, texture)?;
+        core.glTexImage2D(
+            glcore::GL_TEXTURE_2D,
+            0,
+            glcore::GL_R8 as i32,
+            ATLAS_SIZE as i32,
+            ATLAS_SIZE as i32,
+            0,
+            glcore::GL_RED,
+            glcore::GL_UNSIGNED_BYTE,
+            std::ptr::null(),
+        )?;
+        for (pname, param) in [
+            (glcore::GL_TEXTURE_MIN_FILTER, glcore::GL_LINEAR),
+            (glcore::GL_TEXTURE_MAG_FILTER, glcore::GL_LINEAR),
+            (glcore::GL_TEXTURE_WRAP_S, glcore::GL_CLAMP_TO_EDGE),
+            (glcore::GL_TEXTURE_WRAP_T, glcore::GL_CLAMP_TO_EDGE),
+        ] {
+            core.glTexParameteri(glcore::GL_TEXTURE_2D, pname, param)?;
+        }
+
+        Ok(GlyphAtlas {
+            core,
+            texture,
+            cursor_x: 0,
+            cursor_y: 0,
+            row_height: 0,
+            glyphs: HashMap::new(),
+        })
+    }
+
+    pub fn get_texture(&self) -> u32 {
+        self.texture
+    }
+
+    /// Forget every glyph, their space is reused by the next insertions
+    pub fn clear(&mut self) {
+        self.glyphs.clear();
+        self.cursor_x = 0;
+        self.cursor_y = 0;
+        self.row_height = 0;
+    }
+
+    /// Returns the glyph from the atlas, rasterizing and uploading it if it isn't present yet
+    ///
+    /// Fails with `GLCoreError::OutOfMemory` when the atlas is full, `clear` it and try again.
+    pub fn get_or_insert(&mut self, font: &Font, key: GlyphRasterConfig) -> GlResult<AtlasGlyph> {
+        if let Some(glyph) = self.glyphs.get(&key) {
+            return Ok(*glyph);
+        }
+
+        let (metrics, bitmap) = font.rasterize(key);
+        let (width, height) = (metrics.width as u32, metrics.height as u32);
+
+        if self.cursor_x + width + ATLAS_PADDING > ATLAS_SIZE {
+            self.cursor_x = 0;
+            self.cursor_y += self.row_height + ATLAS_PADDING;
+            self.row_height = 0;
+        }
+        if width + ATLAS_PADDING > ATLAS_SIZE || self.cursor_y + height > ATLAS_SIZE {
+            return Err(GLCoreError::OutOfMemory("Glyph atlas is full"));
+        }
+
+        if width > 0 && height > 0 {
+            self.core
+                .glBindTexture(glcore::GL_TEXTURE_2D, self.texture)?;
+            self.core.glPixelStorei(glcore::GL_UNPACK_ALIGNMENT, 1)?;
+            self.core.glTexSubImage2D(
+                glcore::GL_TEXTURE_2D,
+                0,
+                self.cursor_x as i32,
+                self.cursor_y as i32,
+                width as i32,
+                height as i32,
+                glcore::GL_RED,
+                glcore::GL_UNSIGNED_BYTE,
+                bitmap.as_ptr() as *const c_void,
+            )?;
+        }
+
+        let size = ATLAS_SIZE as f32;
+        let glyph = AtlasGlyph {
+            uv_min: Vec2::new(self.cursor_x as f32 / size, self.cursor_y as f32 / size),
+            uv_max: Vec2::new(
+                (self.cursor_x + width) as f32 / size,
+                (self.cursor_y + height) as f32 / size,
+            ),
+        };
+        self.cursor_x += width + ATLAS_PADDING;
+        self.row_height = self.row_height.max(height);
+        self.glyphs.insert(key, glyph);
+        Ok(glyph)
+    }
+}
+
+impl Drop for GlyphAtlas {
+    fn drop(&mut self) {
+        let _ = self.core.glDeleteTextures(1, &self.texture);
+    }
+}
@@ -1,5 +1,3 @@
-use std::ffi::c_void;
-
 use glcore::GLCoreError;
 
 pub type GlResult<T> = Result<T, GLCoreError>;
@@ -182,7 +180,12 @@ impl std::ops::Add for Vec4 {
     type Output = Vec4;
 
     fn add(self, rhs: Self) -> Self::Output {
-        Vec4::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z, self.w + rhs.w)
+        Vec4::new(
+            self.x + rhs.x,
+            self.y + rhs.y,
+            self.z + rhs.z,
+            self.w + rhs.w,
+        )
     }
 }
 
@@ -190,7 +193,12 @@ impl std::ops::Sub for Vec4 {
     type Output = Vec4;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        Vec4::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z, self.w - rhs.w)
+        Vec4::new(
+            self.x - rhs.x,
+            self.y - rhs.y,
+            self.z - rhs.z,
+            self.w - rhs.w,
+        )
     }
 }
 
@@ -198,7 +206,12 @@ impl std::ops::Mul for Vec4 {
     type Output = Vec4;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        Vec4::new(self.x * rhs.x, self.y * rhs.y, self.z * rhs.z, self.w * rhs.w)
+        Vec4::new(
+            self.x * rhs.x,
+            self.y * rhs.y,
+            self.z * rhs.z,
+            self.w * rhs.w,
+        )
     }
 }
 
@@ -206,7 +219,12 @@ impl std::ops::Div for Vec4 {
     type Output = Vec4;
 
     fn div(self, rhs: Self) -> Self::Output {
-        Vec4::new(self.x / rhs.x, self.y / rhs.y, self.z / rhs.z, self.w / rhs.w)
+        Vec4::new(
+            self.x / rhs.x,
+            self.y / rhs.y,
+            self.z / rhs.z,
+            self.w / rhs.w,
+        )
     }
 }
 
@@ -220,120 +238,425 @@ impl VecDemotion<Vec3> for Vec4 {
     }
 }
 
-pub trait AsFloatArray {
-    const FLOATS_PER_ELEMENT: usize;
-    type Backend;
-
-    fn as_contiguous_block(&self) -> Option<&[f32]>;
+/// A 3x3 matrix stored column by column like OpenGL expects, used for 2D affine transforms
+///
+/// Matrices are applied right to left, `a * b` transforms by `b` first and `a` afterwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat3 {
+    columns: [[f32; 3]; 3],
 }
 
-macro_rules! make_continguous {
-    ($borrowed:ident, $collection:ident, $singular:ty, $per_elem:expr) => {
-        #[repr(transparent)]
-        pub struct $collection(Vec<$singular>);
+impl Default for Mat3 {
+    fn default() -> Self {
+        Mat3::identity()
+    }
+}
 
-        impl $collection {
-            pub fn new(verts: Vec<$singular>) -> $collection {
-                $collection(verts)
+impl Mat3 {
+    /// Takes the elements row by row, as matrices are usually written down
+    pub fn from_rows(rows: [[f32; 3]; 3]) -> Mat3 {
+        let mut columns = [[0.0; 3]; 3];
+        for (row, values) in rows.iter().enumerate() {
+            for (column, value) in values.iter().enumerate() {
+                columns[column][row] = *value;
             }
         }
+        Mat3 { columns }
+    }
 
-        impl AsFloatArray for $collection {
-            const FLOATS_PER_ELEMENT: usize = $per_elem;
-            type Backend = $singular;
-
-            fn as_contiguous_block(&self) -> Option<&[f32]> {
-                if self.0.len() == 0 {
-                    return None;
-                }
-                Some(unsafe {
-                    std::slice::from_raw_parts(
-                        self.0.as_ptr() as *const f32,
-                        self.0.len() * Self::FLOATS_PER_ELEMENT,
-                    )
-                })
-            }
-        }
+    pub fn identity() -> Mat3 {
+        Mat3::scale(Vec2::new(1.0, 1.0))
+    }
+
+    pub fn translate(offset: Vec2) -> Mat3 {
+        Mat3::from_rows([[1.0, 0.0, offset.x], [0.0, 1.0, offset.y], [0.0, 0.0, 1.0]])
+    }
 
-        impl<'a> $collection {
-            pub fn weaken(&'a self) -> $borrowed<'a> {
-                $borrowed::new(&self.0)
+    pub fn scale(scale: Vec2) -> Mat3 {
+        Mat3::from_rows([[scale.x, 0.0, 0.0], [0.0, scale.y, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    /// Rotates by `angle` radians, clockwise on screen as y points down
+    pub fn rotate(angle: f32) -> Mat3 {
+        let (sin, cos) = angle.sin_cos();
+        Mat3::from_rows([[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]])
+    }
+
+    fn at(&self, row: usize, column: usize) -> f32 {
+        self.columns[column][row]
+    }
+
+    /// The transform which applies `inner` first and `self` afterwards, the same as `self * inner`
+    pub fn multiply(&self, inner: &Mat3) -> Mat3 {
+        let mut columns = [[0.0; 3]; 3];
+        for (column, values) in columns.iter_mut().enumerate() {
+            for (row, value) in values.iter_mut().enumerate() {
+                *value = (0..3)
+                    .map(|index| self.at(row, index) * inner.at(index, column))
+                    .sum();
             }
         }
-    };
+        Mat3 { columns }
+    }
 
-    ($collection:ident, $singular:ty, $per_elem:expr) => {
-        #[repr(transparent)]
-        pub struct $collection<'a>(&'a [$singular]);
+    /// The transform undoing this one, `None` if it squashes everything onto a line or point
+    pub fn inverse(&self) -> Option<Mat3> {
+        let a = |row: usize, column: usize| self.at(row, column);
+        let determinant = a(0, 0) * (a(1, 1) * a(2, 2) - a(1, 2) * a(2, 1))
+            - a(0, 1) * (a(1, 0) * a(2, 2) - a(1, 2) * a(2, 0))
+            + a(0, 2) * (a(1, 0) * a(2, 1) - a(1, 1) * a(2, 0));
+        if determinant == 0.0 || !determinant.is_finite() {
+            return None;
+        }
+        let inverse = Mat3::from_rows([
+            [
+                a(1, 1) * a(2, 2) - a(1, 2) * a(2, 1),
+                a(0, 2) * a(2, 1) - a(0, 1) * a(2, 2),
+                a(0, 1) * a(1, 2) - a(0, 2) * a(1, 1),
+            ],
+            [
+                a(1, 2) * a(2, 0) - a(1, 0) * a(2, 2),
+                a(0, 0) * a(2, 2) - a(0, 2) * a(2, 0),
+                a(0, 2) * a(1, 0) - a(0, 0) * a(1, 2),
+            ],
+            [
+                a(1, 0) * a(2, 1) - a(1, 1) * a(2, 0),
+                a(0, 1) * a(2, 0) - a(0, 0) * a(2, 1),
+                a(0, 0) * a(1, 1) - a(0, 1) * a(1, 0),
+            ],
+        ]);
+        Some(inverse.map(|value| value / determinant))
+    }
 
-        impl<'a> $collection<'a> {
-            pub fn new(verts: &'a [$singular]) -> $collection<'a> {
-                $collection(verts)
-            }
+    fn map(&self, function: impl Fn(f32) -> f32) -> Mat3 {
+        Mat3 {
+            columns: self.columns.map(|column| column.map(&function)),
         }
+    }
+
+    pub fn transform_point(&self, point: Vec2) -> Vec2 {
+        Vec2::new(
+            self.at(0, 0) * point.x + self.at(0, 1) * point.y + self.at(0, 2),
+            self.at(1, 0) * point.x + self.at(1, 1) * point.y + self.at(1, 2),
+        )
+    }
+
+    /// Whether the axes stay horizontal and vertical, i.e. there is no rotation or skew
+    pub fn is_axis_aligned(&self) -> bool {
+        self.at(0, 1) == 0.0 && self.at(1, 0) == 0.0
+    }
 
-        impl<'a> AsFloatArray for $collection<'a> {
-            const FLOATS_PER_ELEMENT: usize = $per_elem;
-            type Backend = $singular;
-
-            fn as_contiguous_block(&self) -> Option<&[f32]> {
-                if self.0.len() == 0 {
-                    return None;
-                }
-                Some(unsafe {
-                    std::slice::from_raw_parts(
-                        self.0.as_ptr() as *const f32,
-                        self.0.len() * Self::FLOATS_PER_ELEMENT,
-                    )
-                })
+    /// How much lengths grow on average, the square root of how much areas grow
+    pub fn scale_factor(&self) -> f32 {
+        (self.at(0, 0) * self.at(1, 1) - self.at(0, 1) * self.at(1, 0))
+            .abs()
+            .sqrt()
+    }
+}
+
+impl std::ops::Mul for Mat3 {
+    type Output = Mat3;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        self.multiply(&rhs)
+    }
+}
+
+/// A 4x4 matrix stored column by column like OpenGL expects
+///
+/// Matrices are applied right to left, `a * b` transforms by `b` first and `a` afterwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mat4 {
+    columns: [[f32; 4]; 4],
+}
+
+impl Default for Mat4 {
+    fn default() -> Self {
+        Mat4::identity()
+    }
+}
+
+impl Mat4 {
+    /// Takes the elements row by row, as matrices are usually written down
+    pub fn from_rows(rows: [[f32; 4]; 4]) -> Mat4 {
+        let mut columns = [[0.0; 4]; 4];
+        for (row, values) in rows.iter().enumerate() {
+            for (column, value) in values.iter().enumerate() {
+                columns[column][row] = *value;
             }
         }
-    };
-}
+        Mat4 { columns }
+    }
 
-make_continguous!(Vec2Array, Vec2, 2);
-make_continguous!(Vec3Array, Vec3, 3);
-make_continguous!(Vec4Array, Vec4, 4);
-make_continguous!(Vec2Array, OwnedVec2Array, Vec2, 2);
-make_continguous!(Vec3Array, OwnedVec3Array, Vec3, 3);
-make_continguous!(Vec4Array, OwnedVec4Array, Vec4, 4);
+    pub fn identity() -> Mat4 {
+        Mat4::scale(Vec3::new(1.0, 1.0, 1.0))
+    }
 
-#[repr(transparent)]
-pub struct Indices<'a, B: IndicesBackend>(&'a [B::Backend]);
+    pub fn translate(offset: Vec3) -> Mat4 {
+        Mat4::from_rows([
+            [1.0, 0.0, 0.0, offset.x],
+            [0.0, 1.0, 0.0, offset.y],
+            [0.0, 0.0, 1.0, offset.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
 
-impl<'a, B: IndicesBackend> Indices<'a, B> {
-    pub fn new(indices: &'a [B::Backend]) -> Indices<'a, B> {
-        Indices(indices)
+    pub fn scale(scale: Vec3) -> Mat4 {
+        Mat4::from_rows([
+            [scale.x, 0.0, 0.0, 0.0],
+            [0.0, scale.y, 0.0, 0.0],
+            [0.0, 0.0, scale.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
     }
 
-    pub fn len(&'a self) -> usize {
-        self.0.len()
+    /// Rotates by `angle` radians around the z axis, like `Mat3::rotate`
+    pub fn rotate(angle: f32) -> Mat4 {
+        let (sin, cos) = angle.sin_cos();
+        Mat4::from_rows([
+            [cos, -sin, 0.0, 0.0],
+            [sin, cos, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
     }
 
-    pub fn ptr(&'a self) -> *const c_void {
-        self.0.as_ptr() as *const c_void
+    /// Maps the box between the planes onto device coordinates, like `glOrtho`
+    pub fn ortho(left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> Mat4 {
+        Mat4::from_rows([
+            [
+                2.0 / (right - left),
+                0.0,
+                0.0,
+                -(right + left) / (right - left),
+            ],
+            [
+                0.0,
+                2.0 / (top - bottom),
+                0.0,
+                -(top + bottom) / (top - bottom),
+            ],
+            [0.0, 0.0, -2.0 / (far - near), -(far + near) / (far - near)],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    fn at(&self, row: usize, column: usize) -> f32 {
+        self.columns[column][row]
+    }
+
+    /// The transform which applies `inner` first and `self` afterwards, the same as `self * inner`
+    pub fn multiply(&self, inner: &Mat4) -> Mat4 {
+        let mut columns = [[0.0; 4]; 4];
+        for (column, values) in columns.iter_mut().enumerate() {
+            for (row, value) in values.iter_mut().enumerate() {
+                *value = (0..4)
+                    .map(|index| self.at(row, index) * inner.at(index, column))
+                    .sum();
+            }
+        }
+        Mat4 { columns }
     }
-}
 
-pub trait IndicesBackend {
-    type Backend;
-    fn get_opengl_type() -> u32;
+    /// The transform undoing this one, `None` if it isn't invertible
+    pub fn inverse(&self) -> Option<Mat4> {
+        let a = |row: usize, column: usize| self.at(row, column);
+        // Determinants of the 2x2 minors of the top and bottom two rows
+        let s0 = a(0, 0) * a(1, 1) - a(1, 0) * a(0, 1);
+        let s1 = a(0, 0) * a(1, 2) - a(1, 0) * a(0, 2);
+        let s2 = a(0, 0) * a(1, 3) - a(1, 0) * a(0, 3);
+        let s3 = a(0, 1) * a(1, 2) - a(1, 1) * a(0, 2);
+        let s4 = a(0, 1) * a(1, 3) - a(1, 1) * a(0, 3);
+        let s5 = a(0, 2) * a(1, 3) - a(1, 2) * a(0, 3);
+        let c5 = a(2, 2) * a(3, 3) - a(3, 2) * a(2, 3);
+        let c4 = a(2, 1) * a(3, 3) - a(3, 1) * a(2, 3);
+        let c3 = a(2, 1) * a(3, 2) - a(3, 1) * a(2, 2);
+        let c2 = a(2, 0) * a(3, 3) - a(3, 0) * a(2, 3);
+        let c1 = a(2, 0) * a(3, 2) - a(3, 0) * a(2, 2);
+        let c0 = a(2, 0) * a(3, 1) - a(3, 0) * a(2, 1);
+
+        let determinant = s0 * c5 - s1 * c4 + s2 * c3 + s3 * c2 - s4 * c1 + s5 * c0;
+        if determinant == 0.0 || !determinant.is_finite() {
+            return None;
+        }
+        let inverse = Mat4::from_rows([
+            [
+                a(1, 1) * c5 - a(1, 2) * c4 + a(1, 3) * c3,
+                -a(0, 1) * c5 + a(0, 2) * c4 - a(0, 3) * c3,
+                a(3, 1) * s5 - a(3, 2) * s4 + a(3, 3) * s3,
+                -a(2, 1) * s5 + a(2, 2) * s4 - a(2, 3) * s3,
+            ],
+            [
+                -a(1, 0) * c5 + a(1, 2) * c2 - a(1, 3) * c1,
+                a(0, 0) * c5 - a(0, 2) * c2 + a(0, 3) * c1,
+                -a(3, 0) * s5 + a(3, 2) * s2 - a(3, 3) * s1,
+                a(2, 0) * s5 - a(2, 2) * s2 + a(2, 3) * s1,
+            ],
+            [
+                a(1, 0) * c4 - a(1, 1) * c2 + a(1, 3) * c0,
+                -a(0, 0) * c4 + a(0, 1) * c2 - a(0, 3) * c0,
+                a(3, 0) * s4 - a(3, 1) * s2 + a(3, 3) * s0,
+                -a(2, 0) * s4 + a(2, 1) * s2 - a(2, 3) * s0,
+            ],
+            [
+                -a(1, 0) * c3 + a(1, 1) * c1 - a(1, 2) * c0,
+                a(0, 0) * c3 - a(0, 1) * c1 + a(0, 2) * c0,
+                -a(3, 0) * s3 + a(3, 1) * s1 - a(3, 2) * s0,
+                a(2, 0) * s3 - a(2, 1) * s1 + a(2, 2) * s0,
+            ],
+        ]);
+        Some(Mat4 {
+            columns: inverse
+                .columns
+                .map(|column| column.map(|value| value / determinant)),
+        })
+    }
+
+    /// Transforms `point` as a position, so it is moved by translations
+    pub fn transform_point(&self, point: Vec3) -> Vec3 {
+        let row = |row: usize| {
+            self.at(row, 0) * point.x
+                + self.at(row, 1) * point.y
+                + self.at(row, 2) * point.z
+                + self.at(row, 3)
+        };
+        Vec3::new(row(0), row(1), row(2))
+    }
+
+    /// The elements column by column, for `glUniformMatrix4fv`
+    pub fn as_slice(&self) -> &[f32] {
+        self.columns.as_flattened()
+    }
 }
-impl IndicesBackend for u8 {
-    type Backend = u8;
-    fn get_opengl_type() -> u32 {
-        glcore::GL_UNSIGNED_BYTE
+
+impl std::ops::Mul for Mat4 {
+    type Output = Mat4;
+
+    fn mul(self, rhs: Self) -> Self::Output {
+        self.multiply(&rhs)
     }
 }
-impl IndicesBackend for u16 {
-    type Backend = u16;
-    fn get_opengl_type() -> u32 {
-        glcore::GL_UNSIGNED_SHORT
+
+/// Embeds the 2D transform into the xy plane, z is left alone
+impl From<Mat3> for Mat4 {
+    fn from(value: Mat3) -> Self {
+        let a = |row: usize, column: usize| value.at(row, column);
+        Mat4::from_rows([
+            [a(0, 0), a(0, 1), 0.0, a(0, 2)],
+            [a(1, 0), a(1, 1), 0.0, a(1, 2)],
+            [0.0, 0.0, 1.0, 0.0],
+            [a(2, 0), a(2, 1), 0.0, a(2, 2)],
+        ])
     }
 }
-impl IndicesBackend for u32 {
-    type Backend = u32;
-    fn get_opengl_type() -> u32 {
-        glcore::GL_UNSIGNED_INT
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    fn assert_near(actual: &[f32], expected: &[f32]) {
+        let near = actual
+            .iter()
+            .zip(expected)
+            .all(|(actual, expected)| (actual - expected).abs() < 1e-5);
+        assert!(near, "{actual:?} != {expected:?}");
+    }
+
+    #[test]
+    fn mat3_inverse_undoes_the_transform() {
+        let transform = Mat3::translate(Vec2::new(12.0, -3.0))
+            * Mat3::rotate(0.7)
+            * Mat3::scale(Vec2::new(2.0, 0.5));
+        let inverse = transform.inverse().expect("Failed inverse");
+        assert_near(
+            (transform * inverse).columns.as_flattened(),
+            Mat3::identity().columns.as_flattened(),
+        );
+        assert_near(
+            (inverse * transform).columns.as_flattened(),
+            Mat3::identity().columns.as_flattened(),
+        );
+    }
+
+    #[test]
+    fn mat4_inverse_undoes_the_transform() {
+        let transform = Mat4::translate(Vec3::new(12.0, -3.0, 1.5))
+            * Mat4::rotate(0.7)
+            * Mat4::scale(Vec3::new(2.0, 0.5, 4.0));
+        let inverse = transform.inverse().expect("Failed inverse");
+        assert_near(
+            (transform * inverse).as_slice(),
+            Mat4::identity().as_slice(),
+        );
+        let projection = Mat4::ortho(0.0, 300.0, 200.0, 0.0, -1.0, 1.0);
+        let inverse = projection.inverse().expect("Failed ortho inverse");
+        assert_near(
+            (inverse * projection).as_slice(),
+            Mat4::identity().as_slice(),
+        );
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        assert_eq!(Mat3::scale(Vec2::new(0.0, 1.0)).inverse(), None);
+        assert_eq!(
+            Mat3::from_rows([[1.0, 2.0, 0.0], [2.0, 4.0, 0.0], [0.0, 0.0, 1.0]]).inverse(),
+            None
+        );
+        assert_eq!(Mat4::scale(Vec3::new(1.0, 1.0, 0.0)).inverse(), None);
+        assert_eq!(Mat4::from(Mat3::scale(Vec2::new(3.0, 0.0))).inverse(), None);
+    }
+
+    #[test]
+    fn scale_factor_ignores_rotation_and_translation() {
+        let transform = Mat3::translate(Vec2::new(12.0, -3.0))
+            * Mat3::rotate(0.7)
+            * Mat3::scale(Vec2::new(2.0, 2.0));
+        assert_near(&[transform.scale_factor()], &[2.0]);
+        assert_near(&[Mat3::scale(Vec2::new(2.0, 8.0)).scale_factor()], &[4.0]);
+        assert_near(&[Mat3::scale(Vec2::new(-3.0, 3.0)).scale_factor()], &[3.0]);
+    }
+
+    #[test]
+    fn multiply_applies_the_right_side_first() {
+        let transform = Mat3::translate(Vec2::new(10.0, 0.0)) * Mat3::scale(Vec2::new(2.0, 2.0));
+        let point = transform.transform_point(Vec2::new(1.0, 1.0));
+        assert_near(&[point.x, point.y], &[12.0, 2.0]);
+    }
+
+    #[test]
+    fn ortho_maps_the_corners_to_device_coordinates() {
+        let projection = Mat4::ortho(0.0, 300.0, 200.0, 0.0, -1.0, 1.0);
+        for ((x, y), expected) in [
+            ((0.0, 0.0), [-1.0, 1.0]),
+            ((300.0, 0.0), [1.0, 1.0]),
+            ((0.0, 200.0), [-1.0, -1.0]),
+            ((300.0, 200.0), [1.0, -1.0]),
+            ((150.0, 100.0), [0.0, 0.0]),
+        ] {
+            let point = projection.transform_point(Vec3::new(x, y, 0.0));
+            assert_near(
+                &[point.x, point.y, point.z],
+                &[expected[0], expected[1], 0.0],
+            );
+        }
+    }
+
+    #[test]
+    fn rotation_is_clockwise_with_y_down() {
+        // A quarter turn takes the point right of the origin to the one below it
+        let point = Mat3::rotate(FRAC_PI_2).transform_point(Vec2::new(1.0, 0.0));
+        assert_near(&[point.x, point.y], &[0.0, 1.0]);
+        let point = Mat3::rotate(FRAC_PI_2).transform_point(Vec2::new(0.0, 1.0));
+        assert_near(&[point.x, point.y], &[-1.0, 0.0]);
+
+        let point = Mat4::rotate(FRAC_PI_2).transform_point(Vec3::new(1.0, 0.0, 0.0));
+        assert_near(&[point.x, point.y, point.z], &[0.0, 1.0, 0.0]);
+        assert_near(
+            Mat4::rotate(0.3).as_slice(),
+            Mat4::from(Mat3::rotate(0.3)).as_slice(),
+        );
     }
 }
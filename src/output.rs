@@ -0,0 +1,247 @@
+use wayland_client::{
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+    protocol::wl_output::{self, Transform, WlOutput},
+};
+use wayland_protocols::xdg::xdg_output::zv1::client::{
+    zxdg_output_manager_v1::ZxdgOutputManagerV1,
+    zxdg_output_v1::{self, ZxdgOutputV1},
+};
+
+use crate::state::WaylandState;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputMode {
+    pub width: i32,
+    pub height: i32,
+    /// Refresh rate in mHz
+    pub refresh: i32,
+}
+
+/// Everything the compositor told us about an output
+///
+/// The logical position and size are only known if the compositor supports `xdg_output`.
+#[derive(Debug, Clone)]
+pub struct OutputInfo {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub make: String,
+    pub model: String,
+    pub x: i32,
+    pub y: i32,
+    pub physical_width: i32,
+    pub physical_height: i32,
+    pub transform: Transform,
+    pub scale: i32,
+    pub mode: Option<OutputMode>,
+    pub logical_position: Option<(i32, i32)>,
+    pub logical_size: Option<(i32, i32)>,
+}
+
+impl Default for OutputInfo {
+    fn default() -> Self {
+        Self {
+            name: None,
+            description: None,
+            make: String::new(),
+            model: String::new(),
+            x: 0,
+            y: 0,
+            physical_width: 0,
+            physical_height: 0,
+            transform: Transform::Normal,
+            scale: 1,
+            mode: None,
+            logical_position: None,
+            logical_size: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Output {
+    output: WlOutput,
+    xdg_output: Option<ZxdgOutputV1>,
+    /// Information as of the last `done` event
+    info: OutputInfo,
+    /// Information which is still being received, applied atomically on `done`
+    pending: OutputInfo,
+    /// The first `wl_output.done` arrived
+    configured: bool,
+    /// The `xdg_output` was requested, but its name and logical geometry didn't arrive yet
+    awaiting_xdg: bool,
+    announced: bool,
+}
+
+impl Output {
+    pub fn new(output: WlOutput) -> Output {
+        Output {
+            output,
+            xdg_output: None,
+            info: OutputInfo::default(),
+            pending: OutputInfo::default(),
+            configured: false,
+            awaiting_xdg: false,
+            announced: false,
+        }
+    }
+
+    pub fn get_output(&self) -> &WlOutput {
+        &self.output
+    }
+
+    pub fn get_info(&self) -> &OutputInfo {
+        &self.info
+    }
+
+    /// Whether the output received its initial state and has been announced
+    pub fn is_ready(&self) -> bool {
+        self.announced
+    }
+
+    /// Request the `xdg_output` extension for this output, if not done already
+    pub fn bind_xdg_output(
+        &mut self,
+        manager: &ZxdgOutputManagerV1,
+        global_name: u32,
+        queue_handle: &QueueHandle<WaylandState>,
+    ) {
+        if self.xdg_output.is_none() {
+            self.xdg_output = Some(manager.get_xdg_output(&self.output, queue_handle, global_name));
+            self.awaiting_xdg = !self.announced;
+        }
+    }
+
+    /// Marks the output as announced once its initial state is complete, returning the state to
+    /// announce
+    ///
+    /// Outputs with an `xdg_output` wait for it as well, so they are announced with their name.
+    fn take_announcement(&mut self) -> Option<OutputInfo> {
+        if self.announced || !self.configured || self.awaiting_xdg {
+            return None;
+        }
+        self.announced = true;
+        Some(self.info.clone())
+    }
+
+    pub fn destroy(self) {
+        if let Some(xdg_output) = self.xdg_output {
+            xdg_output.destroy();
+        }
+        if self.output.version() >= 3 {
+            self.output.release();
+        }
+    }
+}
+
+impl Dispatch<WlOutput, u32> for WaylandState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlOutput,
+        event: wl_output::Event,
+        global_name: &u32,
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        let Some(output) = state.outputs.get_mut(global_name) else {
+            return;
+        };
+
+        match event {
+            wl_output::Event::Geometry {
+                x,
+                y,
+                physical_width,
+                physical_height,
+                make,
+                model,
+                transform,
+                ..
+            } => {
+                output.pending.x = x;
+                output.pending.y = y;
+                output.pending.physical_width = physical_width;
+                output.pending.physical_height = physical_height;
+                output.pending.make = make;
+                output.pending.model = model;
+                if let WEnum::Value(transform) = transform {
+                    output.pending.transform = transform;
+                }
+            }
+            wl_output::Event::Mode {
+                flags,
+                width,
+                height,
+                refresh,
+            } => {
+                if let WEnum::Value(flags) = flags
+                    && flags.contains(wl_output::Mode::Current)
+                {
+                    output.pending.mode = Some(OutputMode {
+                        width,
+                        height,
+                        refresh,
+                    });
+                }
+            }
+            wl_output::Event::Scale { factor } => output.pending.scale = factor,
+            wl_output::Event::Name { name } => output.pending.name = Some(name),
+            wl_output::Event::Description { description } => {
+                output.pending.description = Some(description)
+            }
+            wl_output::Event::Done => {
+                output.info = output.pending.clone();
+                output.configured = true;
+                if let Some(info) = output.take_announcement() {
+                    state.notify_output_added(&info);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<ZxdgOutputV1, u32> for WaylandState {
+    fn event(
+        state: &mut Self,
+        proxy: &ZxdgOutputV1,
+        event: zxdg_output_v1::Event,
+        global_name: &u32,
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        let Some(output) = state.outputs.get_mut(global_name) else {
+            return;
+        };
+
+        // From version 3 on the initial events are followed by a `wl_output.done`
+        if proxy.version() >= 3 && !matches!(event, zxdg_output_v1::Event::Done) {
+            output.awaiting_xdg = false;
+        }
+        match event {
+            zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                output.pending.logical_position = Some((x, y))
+            }
+            zxdg_output_v1::Event::LogicalSize { width, height } => {
+                output.pending.logical_size = Some((width, height))
+            }
+            // `wl_output` v4 names and descriptions take precedence
+            zxdg_output_v1::Event::Name { name } => {
+                output.pending.name.get_or_insert(name);
+            }
+            zxdg_output_v1::Event::Description { description } => {
+                output.pending.description.get_or_insert(description);
+            }
+            // Deprecated since version 3, where `wl_output.done` is used instead
+            zxdg_output_v1::Event::Done if proxy.version() < 3 => {
+                output.awaiting_xdg = false;
+                if output.configured {
+                    output.info = output.pending.clone();
+                }
+                if let Some(info) = output.take_announcement() {
+                    state.notify_output_added(&info);
+                }
+            }
+            _ => {}
+        }
+    }
+}
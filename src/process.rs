@@ -0,0 +1,480 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::File,
+    io::{self, ErrorKind, Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::process::ExitStatusExt,
+    },
+    path::PathBuf,
+    process::{Child, Command, ExitStatus, Stdio},
+    rc::{Rc, Weak},
+    sync::Once,
+    time::Duration,
+};
+
+use crate::event_loop::{EventLoop, Interest, SourceId};
+
+/// How often the exit status is polled on kernels without `pidfd_open`
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Called with every line without its newline, and whether it ended in one
+pub type LineCallback = Box<dyn FnMut(String, bool)>;
+pub type ExitCallback = Box<dyn FnOnce(ExitReason)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitReason {
+    /// The process exited on its own, with the given exit code
+    Exit(i32),
+    /// The process was terminated by the given signal
+    Signal(i32),
+}
+
+impl From<ExitStatus> for ExitReason {
+    fn from(status: ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => ExitReason::Exit(code),
+            (None, Some(signal)) => ExitReason::Signal(signal),
+            (None, None) => ExitReason::Exit(-1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SpawnOptions {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Variables added to, or overriding, the environment of the current process
+    pub env: HashMap<String, String>,
+    pub cwd: Option<PathBuf>,
+    /// Pipe stdin so it can be written to, otherwise it is connected to `/dev/null`
+    pub stdin: bool,
+    /// Send `SIGTERM` to the process if it is still running after this long
+    pub timeout: Option<Duration>,
+}
+
+impl SpawnOptions {
+    /// Runs `command` through `sh -c`
+    pub fn shell(command: &str) -> SpawnOptions {
+        SpawnOptions {
+            program: "sh".into(),
+            args: vec!["-c".into(), command.into()],
+            ..SpawnOptions::default()
+        }
+    }
+}
+
+/// Callbacks invoked from the event loop, output is split in lines without the trailing newline
+///
+/// The exit callback is invoked last, after all output has been delivered.
+#[derive(Default)]
+pub struct ProcessCallbacks {
+    pub stdout: Option<LineCallback>,
+    pub stderr: Option<LineCallback>,
+    pub exit: Option<ExitCallback>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Non-blocking pipe which is read into complete lines
+struct LineReader {
+    file: File,
+    buffer: Vec<u8>,
+    watcher: SourceId,
+}
+
+impl LineReader {
+    /// Reads everything available, returns the complete lines, each with whether it ended in a
+    /// newline, and whether the pipe was closed
+    ///
+    /// A trailing line without newline is only returned once the pipe is closed.
+    fn read_lines(&mut self) -> (Vec<(String, bool)>, bool) {
+        let mut chunk = [0; 4096];
+        let closed = loop {
+            match self.file.read(&mut chunk) {
+                Ok(0) => break true,
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break false,
+                Err(_) => break true,
+            }
+        };
+
+        let mut lines = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            lines.push((String::from_utf8_lossy(&line[..end]).into_owned(), true));
+        }
+        if closed && !self.buffer.is_empty() {
+            lines.push((String::from_utf8_lossy(&self.buffer).into_owned(), false));
+            self.buffer.clear();
+        }
+        (lines, closed)
+    }
+}
+
+fn set_nonblocking(fd: &impl AsRawFd) -> io::Result<()> {
+    let fd = fd.as_raw_fd();
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 || libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// An fd which becomes readable once the process exits, requires Linux 5.3
+fn pidfd_open(pid: u32) -> io::Result<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) })
+}
+
+/// Makes writes to a pipe whose reader exited fail with `EPIPE`, instead of raising `SIGPIPE`
+/// which kills the host by default
+///
+/// Hosts which handle the signal themselves are left alone.
+fn ignore_sigpipe() {
+    static IGNORE: Once = Once::new();
+    IGNORE.call_once(|| unsafe {
+        let mut current: libc::sigaction = std::mem::zeroed();
+        if libc::sigaction(libc::SIGPIPE, std::ptr::null(), &mut current) == 0
+            && current.sa_sigaction == libc::SIG_DFL
+        {
+            libc::signal(libc::SIGPIPE, libc::SIG_IGN);
+        }
+    });
+}
+
+pub type ProcessHandle = Rc<RefCell<Process>>;
+
+/// A child process driven by the event loop
+///
+/// The event loop keeps the process alive until it exited and all of its output was read, so
+/// dropping every handle does not stop the callbacks.
+pub struct Process {
+    child: Child,
+    stdin: Option<File>,
+    /// Data which did not fit in the pipe yet
+    stdin_buffer: Vec<u8>,
+    stdin_watcher: Option<SourceId>,
+    close_stdin: bool,
+    stdout: Option<LineReader>,
+    stderr: Option<LineReader>,
+    status: Option<ExitReason>,
+    /// Notifies about the exit, either a `pidfd` watcher or a polling timer
+    exit_source: Option<SourceId>,
+    /// Kept alive for the `exit_source` watcher
+    pidfd: Option<OwnedFd>,
+    timeout: Option<SourceId>,
+    callbacks: ProcessCallbacks,
+    event_loop: Weak<RefCell<EventLoop>>,
+}
+
+impl Process {
+    pub fn spawn(
+        event_loop: &Rc<RefCell<EventLoop>>,
+        options: SpawnOptions,
+        callbacks: ProcessCallbacks,
+    ) -> io::Result<ProcessHandle> {
+        let piped_if = |condition: bool| match condition {
+            true => Stdio::piped(),
+            false => Stdio::null(),
+        };
+
+        if options.stdin {
+            ignore_sigpipe();
+        }
+
+        let mut command = Command::new(&options.program);
+        command
+            .args(&options.args)
+            .envs(&options.env)
+            .stdin(piped_if(options.stdin))
+            .stdout(piped_if(callbacks.stdout.is_some()))
+            .stderr(piped_if(callbacks.stderr.is_some()));
+        if let Some(cwd) = &options.cwd {
+            command.current_dir(cwd);
+        }
+        let mut child = command.spawn()?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .map(|pipe| File::from(OwnedFd::from(pipe)));
+        let stdout = child
+            .stdout
+            .take()
+            .map(|pipe| File::from(OwnedFd::from(pipe)));
+        let stderr = child
+            .stderr
+            .take()
+            .map(|pipe| File::from(OwnedFd::from(pipe)));
+        if let Err(err) = stdin
+            .iter()
+            .chain(&stdout)
+            .chain(&stderr)
+            .try_for_each(set_nonblocking)
+        {
+            // Nothing would ever reap the child, don't leave it behind as a zombie
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(err);
+        }
+        let pidfd = pidfd_open(child.id()).ok();
+
+        let handle = Rc::new(RefCell::new(Process {
+            child,
+            stdin,
+            stdin_buffer: Vec::new(),
+            stdin_watcher: None,
+            close_stdin: false,
+            stdout: None,
+            stderr: None,
+            status: None,
+            exit_source: None,
+            pidfd: None,
+            timeout: None,
+            callbacks,
+            event_loop: Rc::downgrade(event_loop),
+        }));
+
+        let mut event_loop = event_loop.borrow_mut();
+        let mut process = handle.borrow_mut();
+        for (file, stream) in [(stdout, Stream::Stdout), (stderr, Stream::Stderr)] {
+            let Some(file) = file else {
+                continue;
+            };
+            let reader_handle = handle.clone();
+            let watcher = event_loop.add_watcher(
+                file.as_raw_fd(),
+                Interest::READ,
+                Box::new(move |_| Process::on_output(&reader_handle, stream)),
+            );
+            let reader = LineReader {
+                file,
+                buffer: Vec::new(),
+                watcher,
+            };
+            match stream {
+                Stream::Stdout => process.stdout = Some(reader),
+                Stream::Stderr => process.stderr = Some(reader),
+            }
+        }
+
+        let exit_handle = handle.clone();
+        let on_exit = move || Process::on_exit(&exit_handle);
+        process.exit_source = Some(match &pidfd {
+            Some(pidfd) => event_loop.add_watcher(
+                pidfd.as_raw_fd(),
+                Interest::READ,
+                Box::new(move |_| on_exit()),
+            ),
+            None => event_loop.add_timer(
+                EXIT_POLL_INTERVAL,
+                Some(EXIT_POLL_INTERVAL),
+                Box::new(on_exit),
+            ),
+        });
+        process.pidfd = pidfd;
+
+        if let Some(timeout) = options.timeout {
+            let weak = Rc::downgrade(&handle);
+            process.timeout = Some(event_loop.add_timer(
+                timeout,
+                None,
+                Box::new(move || {
+                    if let Some(handle) = weak.upgrade() {
+                        let _ = handle.borrow().kill(libc::SIGTERM);
+                    }
+                }),
+            ));
+        }
+
+        drop(process);
+        Ok(handle)
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Whether the process did not exit yet, its output may still be in flight afterwards
+    pub fn is_running(&self) -> bool {
+        self.status.is_none()
+    }
+
+    pub fn kill(&self, signal: i32) -> io::Result<()> {
+        if self.status.is_some() {
+            return Ok(());
+        }
+        // The process is not reaped before `status` is set, so the pid can't have been reused
+        if unsafe { libc::kill(self.child.id() as libc::pid_t, signal) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Queues `data` to be written to stdin, without blocking
+    pub fn write(handle: &ProcessHandle, data: &[u8]) -> io::Result<()> {
+        let mut guard = handle.borrow_mut();
+        let process = &mut *guard;
+        if process.stdin.is_none() || process.close_stdin {
+            return Err(io::Error::new(
+                ErrorKind::BrokenPipe,
+                "stdin is not piped or was closed",
+            ));
+        }
+        process.stdin_buffer.extend_from_slice(data);
+        process.flush_stdin()?;
+
+        if !process.stdin_buffer.is_empty()
+            && process.stdin_watcher.is_none()
+            && let Some(event_loop) = process.event_loop.upgrade()
+            && let Some(stdin) = &process.stdin
+        {
+            let writer_handle = handle.clone();
+            process.stdin_watcher = Some(event_loop.borrow_mut().add_watcher(
+                stdin.as_raw_fd(),
+                Interest {
+                    read: false,
+                    write: true,
+                },
+                Box::new(move |_| {
+                    let _ = writer_handle.borrow_mut().flush_stdin();
+                }),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Closes stdin once everything written so far reached the pipe
+    pub fn close_stdin(&mut self) {
+        self.close_stdin = true;
+        if self.stdin_buffer.is_empty() {
+            self.stdin = None;
+        }
+    }
+
+    fn flush_stdin(&mut self) -> io::Result<()> {
+        let Some(stdin) = &mut self.stdin else {
+            return Ok(());
+        };
+
+        let result = loop {
+            if self.stdin_buffer.is_empty() {
+                break Ok(());
+            }
+            match stdin.write(&self.stdin_buffer) {
+                Ok(written) => {
+                    self.stdin_buffer.drain(..written);
+                }
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) if error.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(error) => {
+                    // The process closed its end, nothing will ever be written anymore
+                    self.stdin_buffer.clear();
+                    break Err(error);
+                }
+            }
+        };
+
+        if self.stdin_buffer.is_empty() {
+            self.remove_source(|process| process.stdin_watcher.take());
+            if self.close_stdin {
+                self.stdin = None;
+            }
+        }
+        result
+    }
+
+    fn remove_source(&mut self, take: impl FnOnce(&mut Process) -> Option<SourceId>) {
+        if let Some(id) = take(self)
+            && let Some(event_loop) = self.event_loop.upgrade()
+        {
+            event_loop.borrow_mut().remove(id);
+        }
+    }
+
+    fn on_output(handle: &ProcessHandle, stream: Stream) {
+        let (lines, callback) = {
+            let mut process = handle.borrow_mut();
+            let reader = match stream {
+                Stream::Stdout => &mut process.stdout,
+                Stream::Stderr => &mut process.stderr,
+            };
+            let Some(reader) = reader.as_mut() else {
+                return;
+            };
+            let (lines, closed) = reader.read_lines();
+            if closed {
+                process.remove_source(|process| match stream {
+                    Stream::Stdout => process.stdout.take().map(|reader| reader.watcher),
+                    Stream::Stderr => process.stderr.take().map(|reader| reader.watcher),
+                });
+            }
+            let callback = match stream {
+                Stream::Stdout => process.callbacks.stdout.take(),
+                Stream::Stderr => process.callbacks.stderr.take(),
+            };
+            (lines, callback)
+        };
+
+        // The process is not borrowed while calling, so the callback may write to it or kill it
+        if let Some(mut callback) = callback {
+            for (line, terminated) in lines {
+                callback(line, terminated);
+            }
+            let mut process = handle.borrow_mut();
+            match stream {
+                Stream::Stdout => process.callbacks.stdout.get_or_insert(callback),
+                Stream::Stderr => process.callbacks.stderr.get_or_insert(callback),
+            };
+        }
+        Process::finish_if_done(handle);
+    }
+
+    fn on_exit(handle: &ProcessHandle) {
+        {
+            let mut process = handle.borrow_mut();
+            match process.child.try_wait() {
+                Ok(Some(status)) => process.status = Some(ExitReason::from(status)),
+                Ok(None) => return,
+                // Reaped by someone else, nothing more will be known about the process
+                Err(_) => process.status = Some(ExitReason::Exit(-1)),
+            }
+            process.remove_source(|process| process.exit_source.take());
+            process.pidfd = None;
+        }
+        Process::finish_if_done(handle);
+    }
+
+    /// Reports the exit once the process exited and all of its output has been read
+    fn finish_if_done(handle: &ProcessHandle) {
+        let (callback, reason) = {
+            let mut process = handle.borrow_mut();
+            let Some(reason) = process.status else {
+                return;
+            };
+            if process.stdout.is_some() || process.stderr.is_some() {
+                return;
+            }
+            process.remove_source(|process| process.timeout.take());
+            process.remove_source(|process| process.stdin_watcher.take());
+            process.stdin = None;
+            (process.callbacks.exit.take(), reason)
+        };
+
+        if let Some(callback) = callback {
+            callback(reason);
+        }
+    }
+}
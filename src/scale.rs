@@ -0,0 +1,220 @@
+use wayland_client::{
+    Connection, Dispatch, QueueHandle,
+    backend::ObjectId,
+    delegate_noop,
+    protocol::{
+        wl_output::WlOutput,
+        wl_surface::{self, WlSurface},
+    },
+};
+use wayland_protocols::wp::{
+    fractional_scale::v1::client::{
+        wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        wp_fractional_scale_v1::{self, WpFractionalScaleV1},
+    },
+    viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
+};
+
+use crate::{state::WaylandState, surface::Sizes};
+
+/// `wp_fractional_scale_v1` sends scales as multiples of 1/120
+const FRACTIONAL_SCALE_DENOMINATOR: f64 = 120.0;
+
+/// The scale a surface is drawn at, so it stays sharp on HiDPI outputs
+///
+/// Compositors with `wp_fractional_scale_v1` and `wp_viewporter` tell us the exact scale to use,
+/// the buffers are drawn at that scale and the viewport maps them back onto the logical size.
+/// Other compositors get the integer scale of the outputs the surface is on through
+/// `wl_surface.set_buffer_scale`.
+///
+/// Everything facing the user, sizes, regions and pointer positions, stays in logical pixels.
+#[derive(Debug)]
+pub struct SurfaceScale {
+    /// Only created when the viewporter is available too, both are needed for fractional scales
+    fractional: Option<(WpFractionalScaleV1, WpViewport)>,
+    /// Sent by `wp_fractional_scale_v1`
+    preferred: Option<f64>,
+    /// Sent by `wl_surface.preferred_buffer_scale`, on compositors which support it
+    preferred_buffer_scale: Option<i32>,
+    /// The outputs the surface is on, along with their scales
+    outputs: Vec<(WlOutput, i32)>,
+}
+
+impl SurfaceScale {
+    /// Sets up fractional scaling for `surface` if the compositor supports it, `surface_id` is
+    /// the `ObjectId` of its layer surface
+    pub fn new(
+        surface: &WlSurface,
+        surface_id: ObjectId,
+        state: &WaylandState,
+        queue_handle: &QueueHandle<WaylandState>,
+    ) -> SurfaceScale {
+        let fractional = state
+            .fractional_scale_manager
+            .as_ref()
+            .zip(state.viewporter.as_ref())
+            .map(|(manager, viewporter)| {
+                (
+                    manager.get_fractional_scale(surface, queue_handle, surface_id),
+                    viewporter.get_viewport(surface, queue_handle, ()),
+                )
+            });
+        SurfaceScale {
+            fractional,
+            preferred: None,
+            preferred_buffer_scale: None,
+            outputs: Vec::new(),
+        }
+    }
+
+    /// The number of buffer pixels per logical pixel
+    pub fn factor(&self) -> f64 {
+        match (&self.fractional, self.preferred) {
+            (Some(_), Some(preferred)) => preferred,
+            _ => self.integer_factor() as f64,
+        }
+    }
+
+    fn integer_factor(&self) -> i32 {
+        self.preferred_buffer_scale.unwrap_or_else(|| {
+            self.outputs
+                .iter()
+                .map(|(_, scale)| *scale)
+                .max()
+                .unwrap_or(1)
+        })
+    }
+
+    /// The size of the buffers for a surface of `sizes` logical pixels
+    pub fn buffer_size(&self, sizes: Sizes) -> Sizes {
+        let scale = |logical: u32| ((logical as f64 * self.factor()).round() as u32).max(1);
+        Sizes {
+            width: scale(sizes.width),
+            height: scale(sizes.height),
+        }
+    }
+
+    /// Tells the compositor how the buffers map onto the `sizes` logical pixels of `surface`,
+    /// takes effect on the next commit
+    pub fn apply(&self, surface: &WlSurface, sizes: Sizes) {
+        match &self.fractional {
+            Some((_, viewport)) => {
+                surface.set_buffer_scale(1);
+                viewport.set_destination(sizes.width as i32, sizes.height as i32);
+            }
+            None => surface.set_buffer_scale(self.integer_factor()),
+        }
+    }
+
+    /// Returns whether the factor changed, as do the other setters
+    fn set_preferred(&mut self, scale: f64) -> bool {
+        let old = self.factor();
+        self.preferred = Some(scale);
+        old != self.factor()
+    }
+
+    fn set_preferred_buffer_scale(&mut self, scale: i32) -> bool {
+        let old = self.factor();
+        self.preferred_buffer_scale = Some(scale);
+        old != self.factor()
+    }
+
+    fn enter(&mut self, output: WlOutput, scale: i32) -> bool {
+        let old = self.factor();
+        self.outputs.push((output, scale));
+        old != self.factor()
+    }
+
+    fn leave(&mut self, output: &WlOutput) -> bool {
+        let old = self.factor();
+        self.outputs.retain(|(entered, _)| entered != output);
+        old != self.factor()
+    }
+
+    pub fn destroy(self) {
+        if let Some((fractional, viewport)) = self.fractional {
+            fractional.destroy();
+            viewport.destroy();
+        }
+    }
+}
+
+impl WaylandState {
+    /// Updates the scale of the surface with the layer surface `id`, a created surface is
+    /// resized and redrawn if its scale changed
+    fn update_scale(&mut self, id: &ObjectId, update: impl FnOnce(&mut SurfaceScale) -> bool) {
+        if let Some(linked) = self.surface_links.get_mut(id) {
+            if update(linked.get_scale_mut()) {
+                linked.rescale();
+            }
+        } else if let Some(uninit) = self.surface_creators.get_mut(id) {
+            // Not configured yet, the buffers are created at the right size right away
+            update(uninit.get_scale_mut());
+        }
+    }
+
+    fn find_any_surface_id(&self, surface: &WlSurface) -> Option<ObjectId> {
+        self.find_surface_id(surface).or_else(|| {
+            self.surface_creators
+                .iter()
+                .find(|(_, uninit)| uninit.get_wl_surface() == surface)
+                .map(|(id, _)| id.clone())
+        })
+    }
+}
+
+impl Dispatch<WpFractionalScaleV1, ObjectId> for WaylandState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpFractionalScaleV1,
+        event: wp_fractional_scale_v1::Event,
+        surface_id: &ObjectId,
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        if let wp_fractional_scale_v1::Event::PreferredScale { scale } = event {
+            let scale = scale as f64 / FRACTIONAL_SCALE_DENOMINATOR;
+            state.update_scale(surface_id, |surface_scale| {
+                surface_scale.set_preferred(scale)
+            });
+        }
+    }
+}
+
+impl Dispatch<WlSurface, ()> for WaylandState {
+    fn event(
+        state: &mut Self,
+        proxy: &WlSurface,
+        event: wl_surface::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        let Some(id) = state.find_any_surface_id(proxy) else {
+            return;
+        };
+        match event {
+            wl_surface::Event::Enter { output } => {
+                let scale = state
+                    .outputs
+                    .values()
+                    .find(|known| known.get_output() == &output)
+                    .map_or(1, |known| known.get_info().scale);
+                state.update_scale(&id, |surface_scale| surface_scale.enter(output, scale));
+            }
+            wl_surface::Event::Leave { output } => {
+                state.update_scale(&id, |surface_scale| surface_scale.leave(&output));
+            }
+            wl_surface::Event::PreferredBufferScale { factor } => {
+                state.update_scale(&id, |surface_scale| {
+                    surface_scale.set_preferred_buffer_scale(factor)
+                });
+            }
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(WaylandState: ignore WpFractionalScaleManagerV1);
+delegate_noop!(WaylandState: ignore WpViewporter);
+delegate_noop!(WaylandState: ignore WpViewport);
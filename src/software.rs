@@ -0,0 +1,690 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use fontdue::layout::GlyphRasterConfig;
+
+use crate::{
+    canvas::{BlendMode, Canvas, CanvasStack, ClipRect, Image},
+    opengl::{
+        highlevel::{ElementsMode, Primitive},
+        image::{DecodedImage, ImageKey, MAX_CACHED_IMAGES},
+        text::{Font, TextOptions},
+        types::{GlResult, Mat3, Vec2, Vec4},
+    },
+};
+
+const BYTES_PER_PIXEL: usize = 4;
+/// Cached glyphs are dropped all at once past this amount, text rarely uses that many
+const MAX_CACHED_GLYPHS: usize = 1024;
+
+/// A rasterized glyph, one byte of coverage per pixel
+#[derive(Debug)]
+struct GlyphBitmap {
+    width: usize,
+    height: usize,
+    coverage: Vec<u8>,
+}
+
+/// Glyphs rasterized for a software surface, the counterpart of the `GlyphAtlas` on the GPU
+#[derive(Debug, Default)]
+pub struct GlyphCache {
+    glyphs: HashMap<GlyphRasterConfig, GlyphBitmap>,
+}
+
+impl GlyphCache {
+    fn get_or_insert(&mut self, font: &Font, key: GlyphRasterConfig) -> &GlyphBitmap {
+        if self.glyphs.len() >= MAX_CACHED_GLYPHS && !self.glyphs.contains_key(&key) {
+            self.glyphs.clear();
+        }
+        self.glyphs.entry(key).or_insert_with(|| {
+            let (metrics, coverage) = font.rasterize(key);
+            GlyphBitmap {
+                width: metrics.width,
+                height: metrics.height,
+                coverage,
+            }
+        })
+    }
+}
+
+/// Image files decoded for a software surface, the counterpart of the `TextureCache` on the GPU
+#[derive(Debug, Default)]
+pub struct ImageCache {
+    images: HashMap<ImageKey, DecodedImage>,
+}
+
+impl ImageCache {
+    fn get_or_load(&mut self, path: &Path, width: u32, height: u32) -> GlResult<&DecodedImage> {
+        let key = (path.to_path_buf(), width, height);
+        if self.images.len() >= MAX_CACHED_IMAGES && !self.images.contains_key(&key) {
+            self.images.clear();
+        }
+        if !self.images.contains_key(&key) {
+            let image = DecodedImage::load(path, width, height)?;
+            self.images.insert(key.clone(), image);
+        }
+        Ok(&self.images[&key])
+    }
+}
+
+/// CPU rasterizer drawing into the ARGB8888 pixels of a `wl_shm` buffer
+///
+/// Takes the same coordinates as the GL path, logical pixels with the origin in the top-left
+/// corner, so drawing code works the same on both. Colors are straight alpha and blended over the
+/// existing pixels, which are stored premultiplied as `wl_shm` expects.
+pub struct Pixmap<'a> {
+    pixels: &'a mut [u8],
+    width: usize,
+    height: usize,
+    /// Pixels outside of `min_x..max_x`, `min_y..max_y` are left alone
+    clip: (i64, i64, i64, i64),
+    blend_mode: BlendMode,
+    /// Pixels per logical pixel
+    scale: f32,
+}
+
+impl<'a> Pixmap<'a> {
+    /// Wraps `pixels`, which must hold at least `width * height` pixels without row padding
+    pub fn new(pixels: &'a mut [u8], width: u32, height: u32) -> Pixmap<'a> {
+        let (width, height) = (width as usize, height as usize);
+        // Never index out of bounds, even if the buffer and the size are briefly out of sync
+        let height = height.min(pixels.len() / (width * BYTES_PER_PIXEL).max(1));
+        Pixmap {
+            pixels,
+            width,
+            height,
+            clip: (0, 0, width as i64, height as i64),
+            blend_mode: BlendMode::default(),
+            scale: 1.0,
+        }
+    }
+
+    /// Scales coordinates up by `scale`, for buffers of surfaces on HiDPI outputs
+    pub fn set_scale(&mut self, scale: f32) {
+        self.scale = scale;
+    }
+
+    pub fn get_scale(&self) -> f32 {
+        self.scale
+    }
+
+    pub fn set_blend_mode(&mut self, mode: BlendMode) {
+        self.blend_mode = mode;
+    }
+
+    /// Restricts drawing to `clip`, or the whole pixmap if `None`
+    pub fn set_clip(&mut self, clip: Option<ClipRect>) {
+        self.clip = match clip {
+            Some(clip) => {
+                let min = self.to_pixels(clip.min);
+                let max = self.to_pixels(clip.max);
+                let (min_x, max_x) = span(min.x, max.x, self.width);
+                let (min_y, max_y) = span(min.y, max.y, self.height);
+                (min_x, min_y, max_x.max(min_x), max_y.max(min_y))
+            }
+            None => (0, 0, self.width as i64, self.height as i64),
+        };
+    }
+
+    /// Converts logical pixels to pixels of the buffer
+    fn to_pixels(&self, point: Vec2) -> Vec2 {
+        point * Vec2::new(self.scale, self.scale)
+    }
+
+    /// Overwrites every pixel inside the clip with `color`, alpha included
+    pub fn clear(&mut self, color: Vec4) {
+        let pixel = premultiply(color, 1.0);
+        let (min_x, min_y, max_x, max_y) = self.clip;
+        for y in min_y as usize..max_y as usize {
+            let row = y * self.width;
+            for chunk in self.pixels
+                [(row + min_x as usize) * BYTES_PER_PIXEL..(row + max_x as usize) * BYTES_PER_PIXEL]
+                .chunks_exact_mut(BYTES_PER_PIXEL)
+            {
+                chunk.copy_from_slice(&pixel);
+            }
+        }
+    }
+
+    /// Blends `color` onto the pixel at `x`, `y` with the blend mode, `coverage` scales its alpha
+    fn blend(&mut self, x: i64, y: i64, color: Vec4, coverage: f32) {
+        let (min_x, min_y, max_x, max_y) = self.clip;
+        if x < min_x || y < min_y || x >= max_x || y >= max_y {
+            return;
+        }
+        let offset = (y as usize * self.width + x as usize) * BYTES_PER_PIXEL;
+        let pixel = &mut self.pixels[offset..offset + BYTES_PER_PIXEL];
+        let source = match self.blend_mode {
+            BlendMode::Premultiplied => {
+                // Scaling every channel keeps the color premultiplied
+                let channel = |value: f32| (value * coverage).clamp(0.0, 1.0) * 255.0;
+                [color.z, color.y, color.x, color.w].map(|value| channel(value).round() as u8)
+            }
+            _ => premultiply(color, coverage),
+        };
+        match self.blend_mode {
+            BlendMode::Replace => pixel.copy_from_slice(&source),
+            BlendMode::Additive => {
+                for (destination, source) in pixel.iter_mut().zip(source) {
+                    *destination = destination.saturating_add(source);
+                }
+            }
+            BlendMode::Normal | BlendMode::Premultiplied => {
+                let remaining = 255 - source[3] as u32;
+                for (destination, source) in pixel.iter_mut().zip(source) {
+                    *destination =
+                        (source as u32 + (*destination as u32 * remaining + 127) / 255) as u8;
+                }
+            }
+        }
+    }
+
+    /// Fills the rectangle with its top-left corner at `pos`, like `Canvas::fill_rect`
+    pub fn fill_rect(&mut self, pos: Vec2, size: Vec2, color: Vec4) {
+        let corner = self.to_pixels(pos);
+        let opposite = self.to_pixels(pos + size);
+        let (min_x, max_x) = span(corner.x, opposite.x, self.width);
+        let (min_y, max_y) = span(corner.y, opposite.y, self.height);
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                self.blend(x, y, color, 1.0);
+            }
+        }
+    }
+
+    /// Draws `points` assembled into primitives the same way `glDrawArrays` does for `mode`
+    pub fn draw_polygon(&mut self, mode: ElementsMode, points: &[Vec2], color: Vec4) {
+        let points: Vec<Vec2> = points.iter().map(|point| self.to_pixels(*point)).collect();
+        let (primitive, points) = mode.assemble(&points);
+        match primitive {
+            Primitive::Points => {
+                for point in &points {
+                    self.blend(point.x.floor() as i64, point.y.floor() as i64, color, 1.0);
+                }
+            }
+            Primitive::Lines => {
+                for line in points.chunks_exact(2) {
+                    self.draw_line(line[0], line[1], color);
+                }
+            }
+            Primitive::Triangles => {
+                for triangle in points.chunks_exact(3) {
+                    self.fill_triangle([triangle[0], triangle[1], triangle[2]], color);
+                }
+            }
+        }
+    }
+
+    /// Draws a one pixel wide line between two points in pixels
+    fn draw_line(&mut self, from: Vec2, to: Vec2, color: Vec4) {
+        let delta = to - from;
+        let steps = delta.x.abs().max(delta.y.abs()).ceil().max(1.0);
+        let step = delta / Vec2::new(steps, steps);
+        let mut point = from;
+        for _ in 0..=steps as usize {
+            self.blend(point.x.floor() as i64, point.y.floor() as i64, color, 1.0);
+            point = point + step;
+        }
+    }
+
+    /// Fills the pixels whose centers lie inside the triangle, given in pixels
+    fn fill_triangle(&mut self, [a, b, c]: [Vec2; 3], color: Vec4) {
+        let area = edge(a, b, c);
+        if area == 0.0 {
+            return;
+        }
+        let (min_x, max_x) = span(a.x.min(b.x).min(c.x), a.x.max(b.x).max(c.x), self.width);
+        let (min_y, max_y) = span(a.y.min(b.y).min(c.y), a.y.max(b.y).max(c.y), self.height);
+        // Works for both windings by flipping the edge functions of clockwise triangles
+        let sign = area.signum();
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let center = Vec2::new(x as f32 + 0.5, y as f32 + 0.5);
+                if edge(a, b, center) * sign >= 0.0
+                    && edge(b, c, center) * sign >= 0.0
+                    && edge(c, a, center) * sign >= 0.0
+                {
+                    self.blend(x, y, color, 1.0);
+                }
+            }
+        }
+    }
+
+    /// Draws `text` with its top-left corner at `pos` mapped through `transform`, like
+    /// `SimpleGL::draw_text`
+    pub fn draw_text(
+        &mut self,
+        cache: &mut GlyphCache,
+        font: &Font,
+        text: &str,
+        transform: Mat3,
+        pos: Vec2,
+        options: &TextOptions,
+    ) {
+        let transform = Mat3::scale(Vec2::new(self.scale, self.scale)) * transform;
+        let scale = transform.scale_factor();
+        if !scale.is_normal() {
+            return;
+        }
+        // Rasterized at the scaled size, so the layout is in pixels of the buffer
+        let options = &TextOptions {
+            size: options.size * scale,
+            ..*options
+        };
+        let layout = font.layout(text, options);
+        let pixel = Vec2::new(1.0 / scale, 1.0 / scale);
+        for position in layout.glyphs() {
+            if position.width == 0 || position.height == 0 {
+                continue;
+            }
+            let glyph = cache.get_or_insert(font, position.key);
+            let (width, height) = (glyph.width, glyph.height);
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let min = pos + Vec2::new(position.x, position.y) * pixel;
+            let size = Vec2::new(width as f32, height as f32) * pixel;
+            let quad = transform * Mat3::translate(min) * Mat3::scale(size);
+            let coverage = &glyph.coverage;
+            self.draw_texels(width, height, quad, |index| match coverage[index] {
+                0 => None,
+                coverage => Some((options.color, coverage as f32 / 255.0)),
+            });
+        }
+    }
+
+    /// Draws `image` stretched over the unit square, which `transform` maps onto the pixmap, its
+    /// colors multiplied by `tint`
+    pub fn draw_image(&mut self, image: Image, transform: Mat3, tint: Vec4) {
+        let (image_width, image_height) = (image.width, image.height);
+        if image_width == 0
+            || image_height == 0
+            || image.pixels.len() < image_width * image_height * 4
+        {
+            return;
+        }
+        let transform = Mat3::scale(Vec2::new(self.scale, self.scale)) * transform;
+        self.draw_texels(image_width, image_height, transform, |index| {
+            let texel = &image.pixels[index * 4..index * 4 + 4];
+            let color = Vec4::new(
+                texel[0] as f32 / 255.0,
+                texel[1] as f32 / 255.0,
+                texel[2] as f32 / 255.0,
+                texel[3] as f32 / 255.0,
+            );
+            Some((color * tint, 1.0))
+        });
+    }
+
+    /// Stretches `width` by `height` texels over the unit square, which `transform` maps onto the
+    /// buffer, `texel` gives the color and coverage of a texel by its row-major index
+    fn draw_texels(
+        &mut self,
+        width: usize,
+        height: usize,
+        transform: Mat3,
+        texel: impl Fn(usize) -> Option<(Vec4, f32)>,
+    ) {
+        // Every pixel looks up the texel under its center, this is not possible for images
+        // squashed onto a line
+        let Some(inverse) = transform.inverse() else {
+            return;
+        };
+        let corners = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)]
+            .map(|(x, y)| transform.transform_point(Vec2::new(x, y)));
+        let (min_x, max_x) = span(
+            corners
+                .iter()
+                .map(|corner| corner.x)
+                .fold(f32::MAX, f32::min),
+            corners
+                .iter()
+                .map(|corner| corner.x)
+                .fold(f32::MIN, f32::max),
+            self.width,
+        );
+        let (min_y, max_y) = span(
+            corners
+                .iter()
+                .map(|corner| corner.y)
+                .fold(f32::MAX, f32::min),
+            corners
+                .iter()
+                .map(|corner| corner.y)
+                .fold(f32::MIN, f32::max),
+            self.height,
+        );
+
+        for y in min_y..max_y {
+            for x in min_x..max_x {
+                let uv = inverse.transform_point(Vec2::new(x as f32 + 0.5, y as f32 + 0.5));
+                let (u, v) = (uv.x, uv.y);
+                if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+                    continue;
+                }
+                let row = ((v * height as f32) as usize).min(height - 1);
+                let column = ((u * width as f32) as usize).min(width - 1);
+                if let Some((color, coverage)) = texel(row * width + column) {
+                    self.blend(x, y, color, coverage);
+                }
+            }
+        }
+    }
+}
+
+/// A `Canvas` over the current `wl_shm` buffer of a surface rendered in software
+///
+/// Created for every drawing call, the transforms and clips live on in the `CanvasStack`.
+pub struct SoftwareCanvas<'a> {
+    pixmap: Pixmap<'a>,
+    glyphs: &'a mut GlyphCache,
+    images: &'a mut ImageCache,
+    stack: &'a mut CanvasStack,
+}
+
+impl<'a> SoftwareCanvas<'a> {
+    pub fn new(
+        mut pixmap: Pixmap<'a>,
+        glyphs: &'a mut GlyphCache,
+        images: &'a mut ImageCache,
+        stack: &'a mut CanvasStack,
+    ) -> SoftwareCanvas<'a> {
+        pixmap.set_clip(stack.clip());
+        pixmap.set_blend_mode(stack.blend_mode());
+        SoftwareCanvas {
+            pixmap,
+            glyphs,
+            images,
+            stack,
+        }
+    }
+}
+
+impl Canvas for SoftwareCanvas<'_> {
+    fn clear(&mut self, color: Vec4) -> GlResult<()> {
+        self.pixmap.clear(color);
+        Ok(())
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) -> GlResult<()> {
+        self.stack.set_blend_mode(mode);
+        self.pixmap.set_blend_mode(mode);
+        Ok(())
+    }
+
+    fn fill_rect(&mut self, pos: Vec2, size: Vec2, color: Vec4) -> GlResult<()> {
+        let transform = self.stack.transform();
+        if !transform.is_axis_aligned() {
+            // A rotated rectangle is no longer a rectangle in pixels
+            let corners = [
+                pos,
+                pos + Vec2::new(size.x, 0.0),
+                pos + size,
+                pos + Vec2::new(0.0, size.y),
+            ];
+            return self.fill_polygon(ElementsMode::TriangleFan, &corners, color);
+        }
+        let corner = transform.transform_point(pos);
+        let opposite = transform.transform_point(pos + size);
+        self.pixmap.fill_rect(corner, opposite - corner, color);
+        Ok(())
+    }
+
+    fn fill_polygon(&mut self, mode: ElementsMode, points: &[Vec2], color: Vec4) -> GlResult<()> {
+        let transform = self.stack.transform();
+        let points: Vec<Vec2> = points
+            .iter()
+            .map(|point| transform.transform_point(*point))
+            .collect();
+        self.pixmap.draw_polygon(mode, &points, color);
+        Ok(())
+    }
+
+    fn stroke_path(&mut self, points: &[Vec2], closed: bool, color: Vec4) -> GlResult<()> {
+        let mode = match closed {
+            true => ElementsMode::LineLoop,
+            false => ElementsMode::LineStrip,
+        };
+        self.fill_polygon(mode, points, color)
+    }
+
+    fn draw_text(
+        &mut self,
+        font: &Font,
+        text: &str,
+        pos: Vec2,
+        options: &TextOptions,
+    ) -> GlResult<()> {
+        let transform = self.stack.transform();
+        self.pixmap
+            .draw_text(self.glyphs, font, text, transform, pos, options);
+        Ok(())
+    }
+
+    fn draw_image(&mut self, image: Image, pos: Vec2, size: Vec2) -> GlResult<()> {
+        let transform = self.stack.transform() * Mat3::translate(pos) * Mat3::scale(size);
+        let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
+        self.pixmap.draw_image(image, transform, white);
+        Ok(())
+    }
+
+    /// Images are decoded at the size they cover in the buffer
+    fn draw_image_file(&mut self, path: &Path, pos: Vec2, size: Vec2, tint: Vec4) -> GlResult<()> {
+        let scale = self.pixmap.get_scale();
+        let (width, height) = (
+            (size.x * scale).abs().round() as u32,
+            (size.y * scale).abs().round() as u32,
+        );
+        let image = self.images.get_or_load(path, width, height)?;
+        let transform = self.stack.transform() * Mat3::translate(pos) * Mat3::scale(size);
+        self.pixmap.draw_image(image.as_image(), transform, tint);
+        Ok(())
+    }
+
+    fn push_clip(&mut self, pos: Vec2, size: Vec2) -> GlResult<()> {
+        self.stack.push_clip(pos, size);
+        self.pixmap.set_clip(self.stack.clip());
+        Ok(())
+    }
+
+    fn pop_clip(&mut self) -> GlResult<()> {
+        self.stack.pop_clip();
+        self.pixmap.set_clip(self.stack.clip());
+        Ok(())
+    }
+
+    fn push_transform(&mut self, transform: Mat3) {
+        self.stack.push_transform(transform);
+    }
+
+    fn pop_transform(&mut self) {
+        self.stack.pop_transform();
+    }
+}
+
+/// `color` premultiplied by its alpha and `coverage`, in the byte order of ARGB8888
+fn premultiply(color: Vec4, coverage: f32) -> [u8; 4] {
+    let alpha = (color.w * coverage).clamp(0.0, 1.0);
+    let channel = |value: f32| (value.clamp(0.0, 1.0) * alpha * 255.0).round() as u8;
+    [
+        channel(color.z),
+        channel(color.y),
+        channel(color.x),
+        (alpha * 255.0).round() as u8,
+    ]
+}
+
+/// The pixels with their centers between `from` and `to`, clamped to `0..limit`
+fn span(from: f32, to: f32, limit: usize) -> (i64, i64) {
+    let clamp = |value: f32| (value.round() as i64).clamp(0, limit as i64);
+    (clamp(from.min(to)), clamp(from.max(to)))
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `point`
+fn edge(a: Vec2, b: Vec2, point: Vec2) -> f32 {
+    (b.x - a.x) * (point.y - a.y) - (b.y - a.y) * (point.x - a.x)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    const SIZE: u32 = 4;
+    const EMPTY: [u8; 4] = [0, 0, 0, 0];
+    // ARGB8888 is stored as B, G, R, A in memory
+    const RED: [u8; 4] = [0, 0, 255, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+    const BLUE: [u8; 4] = [255, 0, 0, 255];
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+
+    fn buffer() -> Vec<u8> {
+        vec![0; (SIZE * SIZE) as usize * BYTES_PER_PIXEL]
+    }
+
+    fn pixel(pixels: &[u8], x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * SIZE as usize + x) * BYTES_PER_PIXEL;
+        pixels[offset..offset + BYTES_PER_PIXEL]
+            .try_into()
+            .expect("Failed pixel")
+    }
+
+    /// The coordinates of every pixel which isn't fully transparent
+    fn painted(pixels: &[u8]) -> Vec<(usize, usize)> {
+        (0..SIZE as usize)
+            .flat_map(|y| (0..SIZE as usize).map(move |x| (x, y)))
+            .filter(|(x, y)| pixel(pixels, *x, *y) != EMPTY)
+            .collect()
+    }
+
+    #[test]
+    fn fill_rect_covers_pixel_centers() {
+        let mut pixels = buffer();
+        let mut pixmap = Pixmap::new(&mut pixels, SIZE, SIZE);
+        pixmap.fill_rect(
+            Vec2::new(1.0, 1.0),
+            Vec2::new(2.0, 2.0),
+            Vec4::new(1.0, 0.0, 0.0, 1.0),
+        );
+        assert_eq!(painted(&pixels), [(1, 1), (2, 1), (1, 2), (2, 2)]);
+        assert_eq!(pixel(&pixels, 1, 1), RED);
+
+        // Scaled buffers cover the logical pixel with several of their own
+        let mut pixels = buffer();
+        let mut pixmap = Pixmap::new(&mut pixels, SIZE, SIZE);
+        pixmap.set_scale(2.0);
+        pixmap.fill_rect(
+            Vec2::new(1.0, 0.0),
+            Vec2::new(1.0, 1.0),
+            Vec4::new(0.0, 1.0, 0.0, 1.0),
+        );
+        assert_eq!(painted(&pixels), [(2, 0), (3, 0), (2, 1), (3, 1)]);
+        assert_eq!(pixel(&pixels, 3, 1), GREEN);
+    }
+
+    #[test]
+    fn drawing_is_clipped_at_the_edges() {
+        let blue = Vec4::new(0.0, 0.0, 1.0, 1.0);
+        let mut pixels = buffer();
+        let mut pixmap = Pixmap::new(&mut pixels, SIZE, SIZE);
+        pixmap.fill_rect(Vec2::new(-2.0, -2.0), Vec2::new(3.0, 3.0), blue);
+        pixmap.fill_rect(Vec2::new(3.0, 3.0), Vec2::new(10.0, 10.0), blue);
+        pixmap.draw_polygon(
+            ElementsMode::Lines,
+            &[Vec2::new(-6.5, 2.5), Vec2::new(10.5, 2.5)],
+            blue,
+        );
+        assert_eq!(
+            painted(&pixels),
+            [(0, 0), (0, 2), (1, 2), (2, 2), (3, 2), (3, 3)]
+        );
+        assert_eq!(pixel(&pixels, 3, 3), BLUE);
+
+        let mut pixels = buffer();
+        let mut pixmap = Pixmap::new(&mut pixels, SIZE, SIZE);
+        pixmap.set_clip(Some(ClipRect {
+            min: Vec2::new(1.0, 2.0),
+            max: Vec2::new(3.0, 8.0),
+        }));
+        pixmap.clear(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        pixmap.fill_rect(Vec2::new(0.0, 0.0), Vec2::new(2.0, 4.0), blue);
+        assert_eq!(painted(&pixels), [(1, 2), (2, 2), (1, 3), (2, 3)]);
+        assert_eq!(pixel(&pixels, 1, 3), BLUE);
+        assert_eq!(pixel(&pixels, 2, 3), WHITE);
+    }
+
+    #[test]
+    fn blending_keeps_pixels_premultiplied() {
+        let half_red = Vec4::new(1.0, 0.0, 0.0, 0.5);
+        let mut pixels = buffer();
+        let mut pixmap = Pixmap::new(&mut pixels, SIZE, SIZE);
+        pixmap.fill_rect(Vec2::new(0.0, 0.0), Vec2::new(1.0, 1.0), half_red);
+        assert_eq!(pixel(&pixels, 0, 0), [0, 0, 128, 128]);
+
+        let mut pixels = buffer();
+        let mut pixmap = Pixmap::new(&mut pixels, SIZE, SIZE);
+        pixmap.clear(Vec4::new(1.0, 1.0, 1.0, 1.0));
+        pixmap.fill_rect(Vec2::new(0.0, 0.0), Vec2::new(1.0, 1.0), half_red);
+        pixmap.set_blend_mode(BlendMode::Premultiplied);
+        let premultiplied = Vec4::new(0.5, 0.0, 0.0, 0.5);
+        pixmap.fill_rect(Vec2::new(1.0, 0.0), Vec2::new(1.0, 1.0), premultiplied);
+        pixmap.set_blend_mode(BlendMode::Replace);
+        pixmap.fill_rect(Vec2::new(2.0, 0.0), Vec2::new(1.0, 1.0), half_red);
+        pixmap.set_blend_mode(BlendMode::Additive);
+        pixmap.fill_rect(Vec2::new(3.0, 0.0), Vec2::new(1.0, 1.0), half_red);
+        // Half of the white shows through the red, adding to white saturates
+        assert_eq!(pixel(&pixels, 0, 0), [127, 127, 255, 255]);
+        assert_eq!(pixel(&pixels, 1, 0), [127, 127, 255, 255]);
+        assert_eq!(pixel(&pixels, 2, 0), [0, 0, 128, 128]);
+        assert_eq!(pixel(&pixels, 3, 0), WHITE);
+        assert_eq!(pixel(&pixels, 0, 1), WHITE);
+    }
+
+    #[test]
+    fn images_are_sampled_through_the_inverse_transform() {
+        // Red, green, blue and white texels in straight RGBA
+        let texels = [
+            [255, 0, 0, 255],
+            [0, 255, 0, 255],
+            [0, 0, 255, 255],
+            [255, 255, 255, 255],
+        ]
+        .concat();
+        let image = || Image {
+            pixels: &texels,
+            width: 2,
+            height: 2,
+        };
+        let white = Vec4::new(1.0, 1.0, 1.0, 1.0);
+
+        let mut pixels = buffer();
+        let mut pixmap = Pixmap::new(&mut pixels, SIZE, SIZE);
+        let transform = Mat3::translate(Vec2::new(1.0, 1.0)) * Mat3::scale(Vec2::new(2.0, 2.0));
+        pixmap.draw_image(image(), transform, white);
+        assert_eq!(painted(&pixels), [(1, 1), (2, 1), (1, 2), (2, 2)]);
+        assert_eq!(pixel(&pixels, 1, 1), RED);
+        assert_eq!(pixel(&pixels, 2, 1), GREEN);
+        assert_eq!(pixel(&pixels, 1, 2), BLUE);
+        assert_eq!(pixel(&pixels, 2, 2), WHITE);
+
+        // A quarter turn clockwise around the top-left corner, moved back into view
+        let mut pixels = buffer();
+        let mut pixmap = Pixmap::new(&mut pixels, SIZE, SIZE);
+        let transform = Mat3::translate(Vec2::new(3.0, 1.0))
+            * Mat3::rotate(FRAC_PI_2)
+            * Mat3::scale(Vec2::new(2.0, 2.0));
+        pixmap.draw_image(image(), transform, Vec4::new(1.0, 1.0, 1.0, 0.5));
+        assert_eq!(painted(&pixels), [(1, 1), (2, 1), (1, 2), (2, 2)]);
+        assert_eq!(pixel(&pixels, 2, 1), [0, 0, 128, 128]);
+        assert_eq!(pixel(&pixels, 2, 2), [0, 128, 0, 128]);
+        assert_eq!(pixel(&pixels, 1, 1), [128, 0, 0, 128]);
+        assert_eq!(pixel(&pixels, 1, 2), [128, 128, 128, 128]);
+
+        // Images squashed onto a line have no inverse and cover no pixel centers
+        let mut pixels = buffer();
+        let mut pixmap = Pixmap::new(&mut pixels, SIZE, SIZE);
+        pixmap.draw_image(image(), Mat3::scale(Vec2::new(4.0, 0.0)), white);
+        assert_eq!(painted(&pixels), []);
+    }
+}
@@ -1,26 +1,40 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Instant};
+
+use glutin::error::Error as GlutError;
 
 use wayland_client::{
     self, Connection, Dispatch, DispatchError, EventQueue,
     backend::ObjectId,
     delegate_noop,
     protocol::{
-        wl_buffer::WlBuffer,
         wl_compositor::WlCompositor,
         wl_display::WlDisplay,
+        wl_output::WlOutput,
+        wl_region::WlRegion,
         wl_registry::{self, WlRegistry},
+        wl_seat::WlSeat,
         wl_shm::WlShm,
         wl_shm_pool::WlShmPool,
         wl_surface::WlSurface,
     },
 };
-use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::{
-    Layer, ZwlrLayerShellV1,
+use wayland_protocols::{
+    wp::{
+        fractional_scale::v1::client::wp_fractional_scale_manager_v1::WpFractionalScaleManagerV1,
+        viewporter::client::wp_viewporter::WpViewporter,
+    },
+    xdg::xdg_output::zv1::client::zxdg_output_manager_v1::ZxdgOutputManagerV1,
 };
+use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_shell_v1::ZwlrLayerShellV1;
 
 use crate::{
-    gpu_surface::GlAbstraction,
-    surface::{Surface, UninitSurface},
+    buffer_pool::BufferPool,
+    gpu_surface::{GlAbstraction, GlOptions},
+    input::{PointerEvent, SEAT_VERSION, Seat},
+    keyboard::{Keyboard, KeyboardEvent},
+    opengl::text::FontCache,
+    output::{Output, OutputInfo},
+    surface::{LAYER_SHELL_VERSION, Surface, SurfaceOptions, UninitSurface},
 };
 
 #[derive(Debug, Clone, Default)]
@@ -65,25 +79,81 @@ impl BoundProtocols {
     }
 }
 
+pub type SurfaceCallback = Box<dyn FnOnce(&mut WaylandState, ObjectId)>;
+pub type OutputCallback = Box<dyn FnMut(&mut WaylandState, &OutputInfo)>;
+pub type PointerCallback = Box<dyn FnMut(&mut WaylandState, ObjectId, PointerEvent)>;
+pub type DrawCallback = Box<dyn FnMut(&mut WaylandState, ObjectId)>;
+pub type KeyboardCallback = Box<dyn FnMut(&mut WaylandState, ObjectId, KeyboardEvent)>;
+
+/// How the surfaces of a client are rendered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RenderBackend {
+    /// OpenGL if EGL is available, software rendering otherwise
+    #[default]
+    Auto,
+    /// OpenGL through EGL, the client can't be created without it
+    Gl,
+    /// The CPU draws into the `wl_shm` buffers, EGL is never touched
+    Software,
+}
+
 pub struct WaylandState {
     pub unbound: UnboundProtocols,
     pub bound: Option<BoundProtocols>,
     pub surface_creators: HashMap<ObjectId, UninitSurface>,
     pub surface_links: HashMap<ObjectId, Surface>,
-    pub surface_creation_callback: HashMap<ObjectId, Box<dyn FnOnce(&mut Self, ObjectId)>>,
-    pub gl: GlAbstraction,
+    pub surface_creation_callback: HashMap<ObjectId, SurfaceCallback>,
+    pub surface_closed_callback: HashMap<ObjectId, SurfaceCallback>,
+    pub draw_callbacks: HashMap<ObjectId, DrawCallback>,
+    /// Outputs keyed by their registry name
+    pub outputs: HashMap<u32, Output>,
+    pub output_manager: Option<ZxdgOutputManagerV1>,
+    /// Fractional scaling is only used if both of these are available
+    pub fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
+    pub viewporter: Option<WpViewporter>,
+    pub output_added_callback: Option<OutputCallback>,
+    pub output_removed_callback: Option<OutputCallback>,
+    /// Seats keyed by their registry name
+    pub seats: HashMap<u32, Seat>,
+    pub pointer_callbacks: HashMap<ObjectId, PointerCallback>,
+    pub keyboard_callbacks: HashMap<ObjectId, KeyboardCallback>,
+    /// `None` when rendering in software
+    pub gl: Option<GlAbstraction>,
+    pub fonts: FontCache,
 }
 
 impl WaylandState {
-    pub fn new(display: &WlDisplay) -> WaylandState {
-        WaylandState {
+    /// Fails only if `backend` is `RenderBackend::Gl` and EGL can't be used with `gl_options`
+    pub fn new(
+        display: &WlDisplay,
+        backend: RenderBackend,
+        gl_options: GlOptions,
+    ) -> Result<WaylandState, GlutError> {
+        let gl = match backend {
+            RenderBackend::Auto => GlAbstraction::new(display, gl_options).ok(),
+            RenderBackend::Gl => Some(GlAbstraction::new(display, gl_options)?),
+            RenderBackend::Software => None,
+        };
+        Ok(WaylandState {
             unbound: UnboundProtocols::default(),
             bound: None,
             surface_creators: HashMap::new(),
             surface_links: HashMap::new(),
             surface_creation_callback: HashMap::new(),
-            gl: GlAbstraction::new(display).expect("Unable to abstract GL"),
-        }
+            surface_closed_callback: HashMap::new(),
+            draw_callbacks: HashMap::new(),
+            outputs: HashMap::new(),
+            output_manager: None,
+            fractional_scale_manager: None,
+            viewporter: None,
+            output_added_callback: None,
+            output_removed_callback: None,
+            seats: HashMap::new(),
+            pointer_callbacks: HashMap::new(),
+            keyboard_callbacks: HashMap::new(),
+            gl,
+            fonts: FontCache::default(),
+        })
     }
 
     pub fn post_dispatch(
@@ -91,7 +161,25 @@ impl WaylandState {
         event_queue: &mut EventQueue<Self>,
     ) -> Result<(), DispatchError> {
         event_queue.blocking_dispatch(self)?;
+        self.finalize_surfaces();
+
+        Ok(())
+    }
 
+    /// Dispatches the events which were already read, without blocking
+    pub fn dispatch_pending(
+        &mut self,
+        event_queue: &mut EventQueue<Self>,
+    ) -> Result<(), DispatchError> {
+        event_queue.dispatch_pending(self)?;
+        self.dispatch_key_repeats();
+        self.finalize_surfaces();
+
+        Ok(())
+    }
+
+    /// Promotes the surfaces which finished their creation and invokes their creation callbacks
+    fn finalize_surfaces(&mut self) {
         let ready: Vec<(ObjectId, UninitSurface)> = self
             .surface_creators
             .extract_if(|_, uninit| uninit.is_ready())
@@ -104,8 +192,6 @@ impl WaylandState {
                 callback(self, key)
             }
         }
-
-        Ok(())
     }
 
     pub fn handle_events(
@@ -113,6 +199,7 @@ impl WaylandState {
         event_queue: &mut EventQueue<Self>,
     ) -> Result<(), DispatchError> {
         event_queue.dispatch_pending(self)?;
+        self.dispatch_key_repeats();
 
         self.post_dispatch(event_queue)
     }
@@ -126,6 +213,140 @@ impl WaylandState {
         self.post_dispatch(event_queue)
     }
 
+    /// Destroy a surface and release all of its resources
+    ///
+    /// Works on both finalized and still initializing surfaces. The closed callback registered for
+    /// the surface, if any, is invoked after the surface has been torn down. Returns `false` if
+    /// no surface with the given `ObjectId` exists.
+    pub fn destroy_surface(&mut self, id: &ObjectId) -> bool {
+        self.surface_creation_callback.remove(id);
+        self.draw_callbacks.remove(id);
+        self.pointer_callbacks.remove(id);
+        self.keyboard_callbacks.remove(id);
+        for seat in self.seats.values_mut() {
+            seat.unfocus(id);
+        }
+
+        if let Some(surface) = self.surface_links.remove(id) {
+            surface.destroy();
+        } else if let Some(uninit) = self.surface_creators.remove(id) {
+            uninit.destroy();
+        } else {
+            return false;
+        }
+
+        if let Some(callback) = self.surface_closed_callback.remove(id) {
+            callback(self, id.clone())
+        }
+        true
+    }
+
+    /// Find an announced output by its name, e.g. `DP-1`
+    pub fn find_output(&self, name: &str) -> Option<&Output> {
+        self.outputs
+            .values()
+            .find(|output| output.is_ready() && output.get_info().name.as_deref() == Some(name))
+    }
+
+    pub fn notify_output_added(&mut self, info: &OutputInfo) {
+        if let Some(mut callback) = self.output_added_callback.take() {
+            callback(self, info);
+            // The callback may have replaced itself
+            self.output_added_callback.get_or_insert(callback);
+        }
+    }
+
+    pub fn notify_output_removed(&mut self, info: &OutputInfo) {
+        if let Some(mut callback) = self.output_removed_callback.take() {
+            callback(self, info);
+            self.output_removed_callback.get_or_insert(callback);
+        }
+    }
+
+    /// Invokes the draw callback of every surface which requested a redraw and may draw now
+    ///
+    /// A surface without draw callback keeps its redraw request until it gets one.
+    pub fn dispatch_redraws(&mut self) {
+        let ready: Vec<ObjectId> = self
+            .surface_links
+            .iter_mut()
+            .filter(|(id, _)| self.draw_callbacks.contains_key(id))
+            .filter_map(|(id, surface)| surface.take_redraw().then(|| id.clone()))
+            .collect();
+        for id in ready {
+            if let Some(mut callback) = self.draw_callbacks.remove(&id) {
+                callback(self, id.clone());
+                if self.surface_links.contains_key(&id) {
+                    self.draw_callbacks.entry(id).or_insert(callback);
+                }
+            }
+        }
+    }
+
+    /// The buffers of a surface, whether or not it finished its creation
+    pub fn get_buffer_pool(&mut self, id: &ObjectId) -> Option<&mut BufferPool> {
+        match self.surface_links.get_mut(id) {
+            Some(surface) => Some(surface.get_buffer_pool_mut()),
+            None => self.surface_creators.get_mut(id)?.get_buffer_pool_mut(),
+        }
+    }
+
+    /// Find the layer surface `ObjectId` belonging to a `WlSurface`
+    pub fn find_surface_id(&self, surface: &WlSurface) -> Option<ObjectId> {
+        self.surface_links
+            .iter()
+            .find(|(_, linked)| linked.get_wl_surface() == surface)
+            .map(|(id, _)| id.clone())
+    }
+
+    /// Passes a pointer event to the callback registered for the surface, if any
+    pub fn notify_pointer(&mut self, id: &ObjectId, event: PointerEvent) {
+        if let Some(mut callback) = self.pointer_callbacks.remove(id) {
+            callback(self, id.clone(), event);
+            // The callback may have replaced itself, or the surface may have been destroyed
+            if self.surface_links.contains_key(id) {
+                self.pointer_callbacks.entry(id.clone()).or_insert(callback);
+            }
+        }
+    }
+
+    /// Passes a keyboard event to the callback registered for the surface, if any
+    pub fn notify_keyboard(&mut self, id: &ObjectId, event: KeyboardEvent) {
+        if let Some(mut callback) = self.keyboard_callbacks.remove(id) {
+            callback(self, id.clone(), event);
+            if self.surface_links.contains_key(id) {
+                self.keyboard_callbacks
+                    .entry(id.clone())
+                    .or_insert(callback);
+            }
+        }
+    }
+
+    /// The moment the next repeated key event is due, if any key is being held down
+    pub fn next_key_repeat(&self) -> Option<Instant> {
+        self.seats
+            .values()
+            .filter_map(Seat::get_keyboard)
+            .filter_map(Keyboard::next_repeat)
+            .min()
+    }
+
+    /// Sends the repeated key events which became due for every focused keyboard
+    pub fn dispatch_key_repeats(&mut self) {
+        let now = Instant::now();
+        let mut repeats = Vec::new();
+        for keyboard in self.seats.values_mut().filter_map(Seat::get_keyboard_mut) {
+            if let Some(focus) = keyboard.get_focus().cloned()
+                && let Some(event) = keyboard.take_repeat(now)
+            {
+                repeats.push((focus, event));
+            }
+        }
+        for (focus, event) in repeats {
+            self.notify_keyboard(&focus, event);
+        }
+    }
+
     /// Start the creation of a surface (`ZwlrLayerShellV1`)
     ///
     /// Due to the nature of Wayland, the creation is not immediate and requires a roundtrip with
@@ -133,13 +354,12 @@ impl WaylandState {
     /// surface creation has been finalized.
     pub fn create_surface_async(
         &mut self,
-        width: u32,
-        height: u32,
-        layer: Layer,
+        options: SurfaceOptions,
+        output: Option<&WlOutput>,
         event_queue: &mut EventQueue<Self>,
     ) -> Option<ObjectId> {
         let queue_handle = event_queue.handle();
-        UninitSurface::setup(width, height, layer, self, &queue_handle)
+        UninitSurface::setup(options, output, self, &queue_handle)
     }
 
     /// Start the creation of a surface (`ZwlrLayerShellV1`) and wait for its completion
@@ -148,13 +368,12 @@ impl WaylandState {
     /// This function is VERY prone to deadlocks, only use it for quick debugging purposes
     pub fn create_surface_blocking(
         &mut self,
-        width: u32,
-        height: u32,
-        layer: Layer,
+        options: SurfaceOptions,
+        output: Option<&WlOutput>,
         event_queue: &mut EventQueue<Self>,
     ) -> Option<ObjectId> {
         let queue_handle = event_queue.handle();
-        let id = UninitSurface::setup(width, height, layer, self, &queue_handle)?;
+        let id = UninitSurface::setup(options, output, self, &queue_handle)?;
 
         while !self.surface_links.contains_key(&id) {
             self.handle_events(event_queue).ok()?;
@@ -172,13 +391,12 @@ impl Dispatch<WlRegistry, ()> for WaylandState {
         _conn: &Connection,
         qhandle: &wayland_client::QueueHandle<Self>,
     ) {
-        if let wl_registry::Event::Global {
-            name,
-            interface,
-            version,
-        } = event
-        {
-            match interface.as_str() {
+        match event {
+            wl_registry::Event::Global {
+                name,
+                interface,
+                version,
+            } => match interface.as_str() {
                 "wl_compositor" => {
                     state.unbound.compositor =
                         Some(proxy.bind::<WlCompositor, _, _>(name, version, qhandle, ()));
@@ -189,19 +407,71 @@ impl Dispatch<WlRegistry, ()> for WaylandState {
                     state.bound = state.unbound.finalize();
                 }
                 "zwlr_layer_shell_v1" => {
-                    state.unbound.layer =
-                        Some(proxy.bind::<ZwlrLayerShellV1, _, _>(name, version, qhandle, ()));
+                    state.unbound.layer = Some(proxy.bind::<ZwlrLayerShellV1, _, _>(
+                        name,
+                        version.min(LAYER_SHELL_VERSION),
+                        qhandle,
+                        (),
+                    ));
                     state.bound = state.unbound.finalize();
                 }
+                "wl_output" => {
+                    let wl_output =
+                        proxy.bind::<WlOutput, _, _>(name, version.min(4), qhandle, name);
+                    let mut output = Output::new(wl_output);
+                    if let Some(manager) = &state.output_manager {
+                        output.bind_xdg_output(manager, name, qhandle);
+                    }
+                    state.outputs.insert(name, output);
+                }
+                "wl_seat" => {
+                    let wl_seat =
+                        proxy.bind::<WlSeat, _, _>(name, version.min(SEAT_VERSION), qhandle, name);
+                    state.seats.insert(name, Seat::new(wl_seat));
+                }
+                "wp_fractional_scale_manager_v1" => {
+                    state.fractional_scale_manager =
+                        Some(proxy.bind::<WpFractionalScaleManagerV1, _, _>(name, 1, qhandle, ()));
+                }
+                "wp_viewporter" => {
+                    state.viewporter = Some(proxy.bind::<WpViewporter, _, _>(name, 1, qhandle, ()));
+                }
+                "zxdg_output_manager_v1" => {
+                    let manager =
+                        proxy.bind::<ZxdgOutputManagerV1, _, _>(name, version.min(3), qhandle, ());
+                    for (global_name, output) in state.outputs.iter_mut() {
+                        output.bind_xdg_output(&manager, *global_name, qhandle);
+                    }
+                    state.output_manager = Some(manager);
+                }
                 _ => {}
+            },
+            wl_registry::Event::GlobalRemove { name } => {
+                if let Some(output) = state.outputs.remove(&name) {
+                    let info = output.get_info().clone();
+                    let announced = output.is_ready();
+                    output.destroy();
+                    if announced {
+                        state.notify_output_removed(&info);
+                    }
+                } else if let Some(seat) = state.seats.remove(&name) {
+                    if let Some(focus) = seat.get_pointer_focus().cloned() {
+                        state.notify_pointer(&focus, PointerEvent::Leave);
+                    }
+                    if let Some(focus) = seat.get_keyboard().and_then(|k| k.get_focus()).cloned() {
+                        state.notify_keyboard(&focus, KeyboardEvent::Leave);
+                    }
+                    seat.destroy();
+                }
             }
+            _ => {}
         }
     }
 }
 
 delegate_noop!(WaylandState: ignore WlCompositor);
 delegate_noop!(WaylandState: ignore WlShm);
-delegate_noop!(WaylandState: ignore WlSurface);
+delegate_noop!(WaylandState: ignore WlRegion);
 delegate_noop!(WaylandState: ignore WlShmPool);
-delegate_noop!(WaylandState: ignore WlBuffer);
 delegate_noop!(WaylandState: ignore ZwlrLayerShellV1);
+delegate_noop!(WaylandState: ignore ZxdgOutputManagerV1);
@@ -1,13 +1,16 @@
-use std::num::NonZero;
+use std::{cell::RefCell, io::Result as IoResult, num::NonZero, rc::Rc};
 
-use glcore::GLCore;
-use memfd::Shm;
+use glcore::{GLCore, GLCoreError};
 use mlua::FromLua;
 use wayland_client::{
     self, Connection, Dispatch, Proxy, QueueHandle,
     backend::ObjectId,
     protocol::{
-        wl_buffer::WlBuffer, wl_shm::Format, wl_shm_pool::WlShmPool, wl_surface::WlSurface,
+        wl_callback::{self, WlCallback},
+        wl_compositor::WlCompositor,
+        wl_output::WlOutput,
+        wl_region::WlRegion,
+        wl_surface::WlSurface,
     },
 };
 use wayland_protocols_wlr::layer_shell::v1::client::zwlr_layer_surface_v1::Event as LayerEvent;
@@ -16,9 +19,23 @@ use wayland_protocols_wlr::layer_shell::v1::client::{
     zwlr_layer_surface_v1::{Anchor, KeyboardInteractivity, ZwlrLayerSurfaceV1},
 };
 
-use crate::{gpu_surface::GpuSurface, state::WaylandState};
+use crate::{
+    buffer_pool::BufferPool,
+    gpu_surface::{GlAbstraction, GpuSurface, RenderError},
+    opengl::{
+        batch::{Batch, FrameStats},
+        image::TextureCache,
+        text::GlyphAtlas,
+        types::GlResult,
+    },
+    scale::SurfaceScale,
+    software::{GlyphCache, ImageCache, Pixmap},
+    state::WaylandState,
+};
 
 const BUFFER_NAMESPACE: &str = "DWR_BUF";
+/// The highest `zwlr_layer_shell_v1` version we know of, 5 added `set_exclusive_edge`
+pub const LAYER_SHELL_VERSION: u32 = 5;
 
 #[derive(Debug, Clone, Copy, Default)]
 pub struct Margins {
@@ -28,6 +45,15 @@ pub struct Margins {
     pub left: i32,
 }
 
+/// A rectangle in surface local pixels, the origin is the top-left corner
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct Sizes {
     pub width: u32,
@@ -41,6 +67,36 @@ pub struct SurfaceProperties {
     pub interactivity: KeyboardInteractivity,
     pub layer: Layer,
     pub sizes: Sizes,
+    /// Pixels reserved at the anchored edge, so other surfaces don't overlap them
+    ///
+    /// 0 lets the surface be moved around by others, -1 ignores their reserved space.
+    pub exclusive_zone: i32,
+    /// The edge the exclusive zone applies to, for surfaces anchored to a corner
+    ///
+    /// Must be one of the anchored edges, it is dropped when the anchor no longer includes it.
+    pub exclusive_edge: Option<Anchor>,
+}
+
+impl SurfaceProperties {
+    /// Whether `edge` is a single edge the surface is anchored to, the compositor raises a
+    /// protocol error for any other exclusive edge
+    pub fn accepts_exclusive_edge(&self, edge: Anchor) -> bool {
+        [Anchor::Top, Anchor::Bottom, Anchor::Left, Anchor::Right].contains(&edge)
+            && self.anchor.contains(edge)
+    }
+
+    /// Whether the compositor accepts the size, a width or height of 0 lets it decide that
+    /// dimension, which is only allowed when the surface is anchored to both opposite edges
+    pub fn has_valid_size(&self) -> bool {
+        (self.sizes.width != 0 || self.anchor.contains(Anchor::Left | Anchor::Right))
+            && (self.sizes.height != 0 || self.anchor.contains(Anchor::Top | Anchor::Bottom))
+    }
+
+    /// The exclusive edge, if the anchor still includes it
+    fn valid_exclusive_edge(&self) -> Option<Anchor> {
+        self.exclusive_edge
+            .filter(|edge| self.accepts_exclusive_edge(*edge))
+    }
 }
 
 impl Default for SurfaceProperties {
@@ -51,6 +107,70 @@ impl Default for SurfaceProperties {
             interactivity: KeyboardInteractivity::None,
             layer: Layer::Top,
             sizes: Default::default(),
+            exclusive_zone: 0,
+            exclusive_edge: None,
+        }
+    }
+}
+
+/// Everything a surface is configured with before its first commit
+#[derive(Debug, Clone)]
+pub struct SurfaceOptions {
+    pub properties: SurfaceProperties,
+    /// Tells the compositor what the surface is for, so it can apply rules to it
+    pub namespace: String,
+}
+
+impl SurfaceOptions {
+    pub fn new(width: u32, height: u32) -> SurfaceOptions {
+        let mut options = SurfaceOptions::default();
+        options.properties.sizes = Sizes { width, height };
+        options
+    }
+}
+
+impl Default for SurfaceOptions {
+    fn default() -> Self {
+        Self {
+            properties: SurfaceProperties::default(),
+            namespace: BUFFER_NAMESPACE.into(),
+        }
+    }
+}
+
+/// Where the contents of a surface are drawn
+#[derive(Debug)]
+pub enum RenderTarget {
+    /// OpenGL draws into an EGL window surface
+    Gpu(Box<GpuSurface>),
+    /// The CPU draws straight into the `wl_shm` buffers of the surface
+    Software(GlyphCache, ImageCache),
+}
+
+impl RenderTarget {
+    /// Renders on the GPU if `gl` is available, in software otherwise
+    pub fn new(
+        gl: Option<&GlAbstraction>,
+        surface: &WlSurface,
+        width: NonZero<u32>,
+        height: NonZero<u32>,
+    ) -> Result<RenderTarget, glutin::error::Error> {
+        match gl {
+            Some(gl) => GpuSurface::new(gl, surface, width, height)
+                .map(|gpu_surface| RenderTarget::Gpu(Box::new(gpu_surface))),
+            None => Ok(RenderTarget::Software(
+                GlyphCache::default(),
+                ImageCache::default(),
+            )),
+        }
+    }
+
+    fn get_gpu_surface(&mut self) -> GlResult<&mut GpuSurface> {
+        match self {
+            RenderTarget::Gpu(gpu_surface) => Ok(gpu_surface),
+            RenderTarget::Software(..) => Err(GLCoreError::InvalidOperation(
+                "The surface is rendered in software",
+            )),
         }
     }
 }
@@ -59,26 +179,184 @@ impl Default for SurfaceProperties {
 pub struct Surface {
     surface: WlSurface,
     layer_surface: ZwlrLayerSurfaceV1,
-    pool: WlShmPool,
-    gpu_surface: GpuSurface,
-    shm: Shm,
+    buffers: BufferPool,
+    target: RenderTarget,
     properties: SurfaceProperties,
+    scale: SurfaceScale,
+    queue_handle: QueueHandle<WaylandState>,
+    /// The contents are outdated and should be drawn again
+    needs_redraw: bool,
+    /// A frame callback was requested and the compositor did not signal it yet
+    frame_pending: bool,
+    /// What the last GPU frame submitted
+    frame_stats: Option<FrameStats>,
+    /// The buffers were resized, the scale is sent along with the first buffer of the new size
+    scale_pending: bool,
 }
 
 impl Surface {
-    pub fn get_renderer(&self) -> GLCore {
-        self.gpu_surface.get_renderer()
+    pub fn get_wl_surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    pub fn is_software(&self) -> bool {
+        matches!(self.target, RenderTarget::Software(..))
     }
 
-    pub fn render(
+    /// The number of buffer pixels per logical pixel the surface is drawn at
+    pub fn get_scale(&self) -> f64 {
+        self.scale.factor()
+    }
+
+    pub fn get_scale_mut(&mut self) -> &mut SurfaceScale {
+        &mut self.scale
+    }
+
+    /// The size of the buffers in pixels, the logical size multiplied by the scale
+    pub fn get_buffer_size(&self) -> Sizes {
+        self.scale.buffer_size(self.properties.sizes)
+    }
+
+    /// Sizes the buffers for the logical size at the current scale
+    ///
+    /// The compositor is only told how they map onto the surface along with a buffer of the new
+    /// size, committing the scale with an old buffer may be a protocol error.
+    fn resize_buffers(&mut self) -> IoResult<()> {
+        let size = self.get_buffer_size();
+        self.buffers.resize(size.width, size.height)?;
+        if let RenderTarget::Gpu(gpu_surface) = &mut self.target {
+            gpu_surface.resize(non_zero(size.width), non_zero(size.height));
+        }
+        self.scale_pending = true;
+        Ok(())
+    }
+
+    /// Stages the scale for the next commit, call this when a buffer of the new size is attached
+    fn apply_pending_scale(&mut self) {
+        if self.scale_pending {
+            self.scale.apply(&self.surface, self.properties.sizes);
+            self.scale_pending = false;
+        }
+    }
+
+    /// Commits a blank buffer of the current size, or the old one if every buffer is still in
+    /// use, and redraws the surface afterwards
+    fn present_resized(&mut self) {
+        if self.resize_buffers().is_err() {
+            return;
+        }
+        if self.buffers.acquire(&self.queue_handle) {
+            self.buffers.attach(&self.surface);
+            self.apply_pending_scale();
+        }
+        self.surface.commit();
+        self.request_redraw();
+    }
+
+    /// Resizes the buffers after the scale changed, the new scale is committed with them
+    pub fn rescale(&mut self) {
+        self.present_resized();
+    }
+
+    /// The GL functions of the surface, fails for surfaces rendered in software
+    pub fn get_renderer(&mut self) -> GlResult<GLCore> {
+        Ok(self.target.get_gpu_surface()?.get_renderer())
+    }
+
+    /// Makes the EGL context of the surface current, required before any GL call for it
+    pub fn make_current(&mut self) -> Result<(), RenderError> {
+        self.target.get_gpu_surface()?.make_current()
+    }
+
+    /// Runs `render` with the GL functions of the surface, after making its context current
+    pub fn render<R>(
         &mut self,
-        render: fn(glcore::GLCore) -> Result<(), glcore::GLCoreError>,
-    ) -> Result<(), glcore::GLCoreError> {
-        render(self.get_renderer())
+        render: impl FnOnce(GLCore) -> GlResult<R>,
+    ) -> Result<R, RenderError> {
+        self.make_current()?;
+        Ok(render(self.get_renderer()?)?)
+    }
+
+    pub fn get_texture_cache(&mut self) -> GlResult<Rc<RefCell<TextureCache>>> {
+        Ok(self.target.get_gpu_surface()?.get_texture_cache())
+    }
+
+    pub fn get_batch(&mut self) -> GlResult<Rc<RefCell<Batch>>> {
+        self.target.get_gpu_surface()?.get_batch()
+    }
+
+    pub fn get_glyph_atlas(&mut self) -> GlResult<Rc<RefCell<GlyphAtlas>>> {
+        self.target.get_gpu_surface()?.get_glyph_atlas()
+    }
+
+    /// Picks the `wl_shm` buffer the next software frame is drawn into
+    ///
+    /// Returns `false` while the compositor still reads from every buffer, a buffer release
+    /// wakes up the event loop so the frame can be drawn then.
+    pub fn prepare_pixel_buffer(&mut self) -> bool {
+        self.buffers.acquire(&self.queue_handle)
+    }
+
+    /// The pixels of the current `wl_shm` buffer and the glyphs and images decoded for the
+    /// surface, `None` for surfaces rendered by the GPU
+    pub fn get_pixmap(&mut self) -> Option<(Pixmap<'_>, &mut GlyphCache, &mut ImageCache)> {
+        match &mut self.target {
+            RenderTarget::Software(glyphs, images) => {
+                let mut pixmap = self.buffers.get_pixmap();
+                pixmap.set_scale(self.scale.factor() as f32);
+                Some((pixmap, glyphs, images))
+            }
+            RenderTarget::Gpu(_) => None,
+        }
+    }
+
+    /// Marks the surface as outdated, it is redrawn once the compositor is ready for a new frame
+    pub fn request_redraw(&mut self) {
+        self.needs_redraw = true;
+    }
+
+    /// Returns whether the surface should be drawn now, clearing the redraw request if so
+    pub fn take_redraw(&mut self) -> bool {
+        let ready = self.needs_redraw && !self.frame_pending;
+        if ready {
+            self.needs_redraw = false;
+        }
+        ready
+    }
+
+    /// Asks the compositor to signal when it is a good time to draw the next frame
+    ///
+    /// The request is part of the next commit, so call this before swapping the buffers.
+    pub fn request_frame(&mut self) {
+        self.needs_redraw = false;
+        if !self.frame_pending {
+            self.surface
+                .frame(&self.queue_handle, self.layer_surface.id());
+            self.frame_pending = true;
+        }
     }
 
-    pub fn swap_buffers(&mut self) -> Result<(), glutin::error::Error> {
-        self.gpu_surface.swap_buffers()
+    /// The draw calls and vertices of the last presented frame, `None` for surfaces rendered in
+    /// software or which weren't presented yet
+    pub fn get_frame_stats(&self) -> Option<FrameStats> {
+        self.frame_stats
+    }
+
+    /// Presents the drawn frame, software frames are attached and committed
+    pub fn swap_buffers(&mut self) -> Result<(), RenderError> {
+        // Frames are always drawn at the current size, EGL windows are resized right away
+        self.apply_pending_scale();
+        match &mut self.target {
+            RenderTarget::Gpu(gpu_surface) => {
+                self.frame_stats = Some(gpu_surface.swap_buffers()?);
+                Ok(())
+            }
+            RenderTarget::Software(..) => {
+                self.buffers.attach(&self.surface);
+                self.surface.commit();
+                Ok(())
+            }
+        }
     }
 
     pub fn set_margin(&mut self, margins: Margins) {
@@ -88,6 +366,38 @@ impl Surface {
         self.surface.commit();
     }
 
+    /// Limits pointer input to `rects`, `None` accepts input on the whole surface
+    ///
+    /// Takes effect on the next commit.
+    pub fn set_input_region(&mut self, compositor: &WlCompositor, rects: Option<&[Rect]>) {
+        let region = rects.map(|rects| self.create_region(compositor, rects));
+        self.surface.set_input_region(region.as_ref());
+        if let Some(region) = region {
+            region.destroy();
+        }
+    }
+
+    /// Marks `rects` as fully opaque, `None` for surfaces which may be transparent anywhere
+    ///
+    /// Transparent pixels in the opaque region are drawn wrong by the compositor. Takes effect on
+    /// the next commit.
+    pub fn set_opaque_region(&mut self, compositor: &WlCompositor, rects: Option<&[Rect]>) {
+        let region = rects.map(|rects| self.create_region(compositor, rects));
+        self.surface.set_opaque_region(region.as_ref());
+        if let Some(region) = region {
+            region.destroy();
+        }
+    }
+
+    /// The region is copied once it is set, so it can be destroyed right after
+    fn create_region(&self, compositor: &WlCompositor, rects: &[Rect]) -> WlRegion {
+        let region = compositor.create_region(&self.queue_handle, ());
+        for rect in rects {
+            region.add(rect.x, rect.y, rect.width, rect.height);
+        }
+        region
+    }
+
     pub fn set_size(&mut self, sizes: Sizes) {
         self.layer_surface.set_size(sizes.width, sizes.height);
         self.surface.commit();
@@ -95,15 +405,47 @@ impl Surface {
 
     pub fn set_layer(&mut self, layer: Layer) {
         self.layer_surface.set_layer(layer);
+        self.properties.layer = layer;
         self.surface.commit();
     }
 
     pub fn set_anchor(&mut self, anchor: Anchor) {
         self.layer_surface.set_anchor(anchor);
         self.properties.anchor = anchor;
+        self.send_exclusive_edge();
         self.surface.commit();
     }
 
+    /// Reserves `zone` pixels at the anchored edge, see `SurfaceProperties::exclusive_zone`
+    pub fn set_exclusive_zone(&mut self, zone: i32) {
+        self.layer_surface.set_exclusive_zone(zone);
+        self.properties.exclusive_zone = zone;
+        self.surface.commit();
+    }
+
+    /// Picks the edge the exclusive zone applies to, returns `false` if the compositor doesn't
+    /// support picking one or the surface isn't anchored to `edge`
+    pub fn set_exclusive_edge(&mut self, edge: Anchor) -> bool {
+        if self.layer_surface.version() < 5 || !self.properties.accepts_exclusive_edge(edge) {
+            return false;
+        }
+        self.layer_surface.set_exclusive_edge(edge);
+        self.properties.exclusive_edge = Some(edge);
+        self.surface.commit();
+        true
+    }
+
+    /// Sends the exclusive edge after the anchor changed, an edge which is no longer anchored is
+    /// dropped so the compositor doesn't raise a protocol error
+    fn send_exclusive_edge(&mut self) {
+        self.properties.exclusive_edge = self.properties.valid_exclusive_edge();
+        if self.layer_surface.version() >= 5 {
+            // An empty edge unsets it
+            self.layer_surface
+                .set_exclusive_edge(self.properties.exclusive_edge.unwrap_or(Anchor::empty()));
+        }
+    }
+
     pub fn set_keyboard_interactivity(&mut self, keyboard_interactivity: KeyboardInteractivity) {
         self.layer_surface
             .set_keyboard_interactivity(keyboard_interactivity);
@@ -111,12 +453,17 @@ impl Surface {
         self.surface.commit();
     }
 
+    /// Pixels of the `wl_shm` buffer the current frame is drawn into
     pub fn get_pixel_buffer(&self) -> &[u8] {
-        self.shm.data()
+        self.buffers.data()
     }
 
     pub fn get_pixel_buffer_mut(&mut self) -> &mut [u8] {
-        self.shm.data_mut()
+        self.buffers.data_mut()
+    }
+
+    pub fn get_buffer_pool_mut(&mut self) -> &mut BufferPool {
+        &mut self.buffers
     }
 
     pub fn set_properties(&mut self, mut props: SurfaceProperties) {
@@ -133,8 +480,12 @@ impl Surface {
             self.properties.margins.left,
         );
         self.layer_surface.set_anchor(self.properties.anchor);
+        self.layer_surface.set_layer(self.properties.layer);
         self.layer_surface
             .set_keyboard_interactivity(self.properties.interactivity);
+        self.layer_surface
+            .set_exclusive_zone(self.properties.exclusive_zone);
+        self.send_exclusive_edge();
         self.layer_surface
             .set_size(new_sizes.width, new_sizes.height);
         self.surface.commit();
@@ -143,6 +494,22 @@ impl Surface {
     pub fn get_properties(&self) -> &SurfaceProperties {
         &self.properties
     }
+
+    /// Tears down every resource owned by the surface
+    ///
+    /// The EGL surface and context are released first, as they reference the `WlSurface`. The
+    /// Wayland objects are destroyed afterwards, in reverse order of creation.
+    pub fn destroy(self) {
+        drop(self.target);
+        self.scale.destroy();
+        self.layer_surface.destroy();
+        self.surface.destroy();
+        self.buffers.destroy();
+    }
+}
+
+fn non_zero(value: u32) -> NonZero<u32> {
+    NonZero::new(value).unwrap_or(NonZero::<u32>::MIN)
 }
 
 #[derive(Debug)]
@@ -150,14 +517,27 @@ pub struct UninitSurface {
     properties: SurfaceProperties,
     surface: WlSurface,
     layer_surface: ZwlrLayerSurfaceV1,
-    gpu_surface: Option<GpuSurface>,
-    buffers: Option<(WlShmPool, WlBuffer)>,
-    data: Option<Shm>,
+    target: Option<RenderTarget>,
+    buffers: Option<BufferPool>,
+    scale: SurfaceScale,
+    queue_handle: QueueHandle<WaylandState>,
 }
 
 impl UninitSurface {
     pub fn is_ready(&self) -> bool {
-        self.buffers.is_some() && self.data.is_some()
+        self.buffers.is_some()
+    }
+
+    pub fn get_wl_surface(&self) -> &WlSurface {
+        &self.surface
+    }
+
+    pub fn get_scale_mut(&mut self) -> &mut SurfaceScale {
+        &mut self.scale
+    }
+
+    pub fn get_buffer_pool_mut(&mut self) -> Option<&mut BufferPool> {
+        self.buffers.as_mut()
     }
 
     /// Starts the creation of a Wayland native surface, in specific a `ZwlrLayerSurfaceV1`
@@ -165,53 +545,65 @@ impl UninitSurface {
     /// Creating a surface in Wayland is async, it requires a roundtrip with the server and
     /// therefore cannot be done directly.
     ///
+    /// Without an `output` the compositor decides on which output the surface is placed.
+    ///
+    /// Every property in `options` is applied before the first commit, so the compositor
+    /// configures the surface with them right away.
+    ///
     /// TODO: explain `UninitSurface` -> `Surface`
     pub fn setup(
-        width: u32,
-        height: u32,
-        layer: Layer,
+        options: SurfaceOptions,
+        output: Option<&WlOutput>,
         state: &mut WaylandState,
         queue_handle: &QueueHandle<WaylandState>,
     ) -> Option<ObjectId> {
         let protocols = state.bound.as_ref()?;
+        let mut properties = options.properties;
+        properties.exclusive_edge = properties.valid_exclusive_edge();
 
         let surface = protocols.get_compositor().create_surface(queue_handle, ());
         let layer_surface = protocols.get_layer().get_layer_surface(
             &surface,
-            None,
-            layer,
-            BUFFER_NAMESPACE.into(),
+            output,
+            properties.layer,
+            options.namespace,
             queue_handle,
             (),
         );
         let layer_id = layer_surface.id().clone();
+        let scale = SurfaceScale::new(&surface, layer_id.clone(), state, queue_handle);
 
-        let mut uninit_surface = UninitSurface {
-            properties: SurfaceProperties::default(),
+        let uninit_surface = UninitSurface {
+            properties,
             surface,
             layer_surface,
-            gpu_surface: None,
+            target: None,
             buffers: None,
-            data: None,
+            scale,
+            queue_handle: queue_handle.clone(),
         };
-        uninit_surface.properties.sizes = Sizes { width, height };
 
         uninit_surface.layer_surface.set_margin(
-            uninit_surface.properties.margins.top,
-            uninit_surface.properties.margins.right,
-            uninit_surface.properties.margins.bottom,
-            uninit_surface.properties.margins.left,
+            properties.margins.top,
+            properties.margins.right,
+            properties.margins.bottom,
+            properties.margins.left,
         );
+        uninit_surface.layer_surface.set_anchor(properties.anchor);
         uninit_surface
             .layer_surface
-            .set_anchor(uninit_surface.properties.anchor);
+            .set_keyboard_interactivity(properties.interactivity);
         uninit_surface
             .layer_surface
-            .set_keyboard_interactivity(uninit_surface.properties.interactivity);
-        uninit_surface.layer_surface.set_size(
-            uninit_surface.properties.sizes.width,
-            uninit_surface.properties.sizes.height,
-        );
+            .set_exclusive_zone(properties.exclusive_zone);
+        if let Some(edge) = properties.exclusive_edge
+            && uninit_surface.layer_surface.version() >= 5
+        {
+            uninit_surface.layer_surface.set_exclusive_edge(edge);
+        }
+        uninit_surface
+            .layer_surface
+            .set_size(properties.sizes.width, properties.sizes.height);
         uninit_surface.surface.commit();
 
         state
@@ -222,16 +614,21 @@ impl UninitSurface {
 
     /// Make sure `is_ready()` returns true!
     pub fn finalize(self, state: &mut WaylandState) -> Option<ObjectId> {
-        self.data
-            .zip(self.buffers)
-            .zip(self.gpu_surface)
-            .map(|((shm, (pool, _)), gpu_surface)| Surface {
-                shm,
+        self.buffers
+            .zip(self.target)
+            .map(|(buffers, target)| Surface {
                 surface: self.surface,
                 layer_surface: self.layer_surface,
-                gpu_surface,
-                pool,
+                target,
+                buffers,
                 properties: self.properties,
+                scale: self.scale,
+                queue_handle: self.queue_handle,
+                // Nothing has been drawn yet
+                needs_redraw: true,
+                frame_pending: false,
+                frame_stats: None,
+                scale_pending: false,
             })
             .map(|surface| {
                 let id = surface.layer_surface.id();
@@ -239,6 +636,17 @@ impl UninitSurface {
                 id
             })
     }
+
+    /// Tears down a surface which never finished its creation
+    pub fn destroy(self) {
+        drop(self.target);
+        self.scale.destroy();
+        self.layer_surface.destroy();
+        self.surface.destroy();
+        if let Some(buffers) = self.buffers {
+            buffers.destroy();
+        }
+    }
 }
 
 impl Dispatch<ZwlrLayerSurfaceV1, ()> for WaylandState {
@@ -259,78 +667,69 @@ impl Dispatch<ZwlrLayerSurfaceV1, ()> for WaylandState {
                 proxy.ack_configure(serial);
 
                 // The server may give us 0, 0
-                // This means 'you decide', we default to 100x100
+                // This means 'you decide', we default to 1x1
                 // Maybe change this to whatever the surface has?
-                let nn_width: NonZero<u32> = width
-                    .try_into()
-                    .unwrap_or(unsafe { NonZero::new_unchecked(1) });
-                let nn_height: NonZero<u32> = height
-                    .try_into()
-                    .unwrap_or(unsafe { NonZero::new_unchecked(1) });
-                let width = u32::from(nn_width);
-                let height = u32::from(nn_height);
-
-                let bytes_per_pixel = 4;
-                let stride = width * bytes_per_pixel;
-                let num_of_frames = 2;
-                let total_buffer_size = height * stride * num_of_frames;
-
-                if let Some(linked) = state.surface_links.get_mut(&proxy.id())
-                    && let Ok(_) = linked.shm.resize(total_buffer_size as usize)
-                {
-                    linked.gpu_surface.resize(nn_width, nn_height);
-
-                    linked.properties.sizes.height = height;
-                    linked.properties.sizes.width = width;
-
-                    let buffer = linked.pool.create_buffer(
-                        0,
-                        width as i32,
-                        height as i32,
-                        stride as i32,
-                        Format::Argb8888,
-                        qhandle,
-                        (),
-                    );
-                    linked.gpu_surface.resize(nn_width, nn_height);
-                    linked.surface.attach(Some(&buffer), 0, 0);
-                    linked.surface.damage(0, 0, width as i32, height as i32);
-                    linked.surface.commit();
+                // The configured size is in logical pixels, the buffers are scaled up from it
+                let sizes = Sizes {
+                    width: width.max(1),
+                    height: height.max(1),
+                };
+
+                if let Some(linked) = state.surface_links.get_mut(&proxy.id()) {
+                    linked.properties.sizes = sizes;
+                    linked.present_resized();
                 }
 
                 if let Some(linked) = state.surface_creators.get_mut(&proxy.id())
                     && let Some(protocols) = &state.bound
-                    && let Ok(shm) = Shm::new(total_buffer_size as usize)
-                    && let Ok(egl_surface) =
-                        GpuSurface::new(&state.gl, &linked.surface, nn_width, nn_height)
-                {
-                    let pool = protocols.get_shm().create_pool(
-                        shm.get_fd(),
-                        total_buffer_size as i32,
-                        qhandle,
-                        (),
-                    );
-                    let buffer = pool.create_buffer(
-                        0,
-                        width as i32,
-                        height as i32,
-                        stride as i32,
-                        Format::Argb8888,
+                    && let size = linked.scale.buffer_size(sizes)
+                    && let Ok(target) = RenderTarget::new(
+                        state.gl.as_ref(),
+                        &linked.surface,
+                        non_zero(size.width),
+                        non_zero(size.height),
+                    )
+                    && let Ok(mut buffers) = BufferPool::new(
+                        protocols.get_shm(),
+                        size.width,
+                        size.height,
+                        proxy.id(),
                         qhandle,
-                        (),
-                    );
-
-                    linked.gpu_surface = Some(egl_surface);
-                    linked.surface.attach(Some(&buffer), 0, 0);
-                    linked.surface.damage(0, 0, width as i32, height as i32);
+                    )
+                {
+                    linked.properties.sizes = sizes;
+                    linked.scale.apply(&linked.surface, sizes);
+                    linked.target = Some(target);
+                    if buffers.acquire(qhandle) {
+                        buffers.attach(&linked.surface);
+                    }
                     linked.surface.commit();
 
-                    linked.buffers = Some((pool, buffer));
-                    linked.data = Some(shm);
+                    linked.buffers = Some(buffers);
                 }
             }
-            LayerEvent::Closed => todo!(),
-            _ => todo!(),
+            LayerEvent::Closed => {
+                state.destroy_surface(&proxy.id());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Frame callbacks carry the `ObjectId` of the layer surface they were requested for
+impl Dispatch<WlCallback, ObjectId> for WaylandState {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlCallback,
+        event: wl_callback::Event,
+        surface_id: &ObjectId,
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        if let wl_callback::Event::Done { .. } = event
+            && let Some(surface) = state.surface_links.get_mut(surface_id)
+        {
+            surface.frame_pending = false;
         }
     }
 }